@@ -1,296 +1,4253 @@
-use std::time::Duration;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use orfail::OrFail;
 
-use crate::layout::{KeyCode, KeyPressState, KeyState, Layout, Preview};
-use crate::tmux_client::TmuxClient;
+use crate::frame_limiter::FrameLimiter;
+use crate::layout::{
+    BorderStyle, Chords, Key, KeyCode, KeyCondition, KeyContext, KeyPressState, KeyState, Layouts,
+    ModifierMode, PaneTarget, Preview, RenderOptions, regions_overlap,
+};
+use crate::pane_map::{PANE_MAP_FORMAT, PANE_MAP_SIZE, PaneMap};
+use crate::tap_hold::{TapHold, TapHoldAction};
+use crate::tmux_client::{Notification, TmuxBackend, TmuxSocket, binding_command};
 
 #[derive(Debug)]
 pub struct AppOptions {
     pub cursor_refresh_interval: Duration,
     pub auto_resize: bool,
+    pub tmux_path: PathBuf,
+
+    /// Socket of the tmux server to connect to
+    pub tmux_socket: TmuxSocket,
+
+    /// tmux session whose panes receive the keys
+    pub tmux_session: String,
+
+    /// tmux client whose session receives the keys and which alone shows the pane numbers
+    pub tmux_client: Option<String>,
+    pub tmux_timeout: Duration,
+
+    /// Minimum time between rendered frames; updates arriving faster are merged
+    pub min_frame_interval: Duration,
+
+    /// How much later than due a timer may fire so that it shares a wakeup with another one
+    pub timer_slack: Duration,
+    pub pane_badge: Option<Corner>,
+
+    /// Corner to draw a clickable map of the window's panes in, for selecting the target pane
+    pub pane_map: Option<Corner>,
+
+    /// What to do when keys end up in the top or bottom row, where the status line and badges are drawn
+    pub status_overlap: StatusOverlap,
+    pub empty_click_action: EmptyClickAction,
+
+    /// How long a one-shot modifier waits for the next key before it clears by itself
+    pub oneshot_timeout: Option<Duration>,
+
+    /// How long a key with Ctrl or Alt applied waits for a second click before it is sent
+    ///
+    /// Without this, such keys are sent on the first click.
+    pub confirm_modified: Option<Duration>,
+    pub press_animation: bool,
+    pub ascii_border: bool,
+    pub no_mouse: bool,
+
+    /// Normalize text typed by snippet and dynamic keys to NFC (e.g. `e` + U+0301 to `é`)
+    pub normalize_nfc: bool,
+
+    /// Start in queue mode, where keys are queued until a `FlushQueue` key sends them
+    pub queue_keys: bool,
+
+    /// Cancel a click when the button is released outside the key it was pressed on
+    pub drag_to_cancel: bool,
+
+    /// Flip the layouts horizontally
+    pub mirror: bool,
+
+    /// Send `Delete` for `BSpace` keys and vice versa
+    pub swap_delete: bool,
+
+    /// Whether to transpose the layouts for tall screens
+    pub orientation: Orientation,
+
+    /// Draw keys such as Enter and the arrows as symbols (e.g. `⏎`) instead of their names
+    pub glyph_labels: bool,
+
+    /// Mark keys that have a `target` with their pane (e.g. `→2`)
+    pub show_targets: bool,
+
+    /// Where to write per-key press counts on exit (`-` for stdout)
+    pub stats_path: Option<PathBuf>,
+
+    /// Show layout coordinates around the keyboard (e.g. for writing layouts)
+    pub debug_grid: bool,
+
+    /// Enable `Broadcast` keys, which send keys to all other panes of the window
+    pub allow_broadcast: bool,
+
+    /// Shell command run in the background on each key press (e.g. to play a click sound)
+    pub on_press_command: Option<String>,
+
+    /// Where to show each sent key (e.g. for screencasts)
+    pub echo_target: Option<EchoTarget>,
+
+    /// Set the terminal title to the last sent key (e.g. for screencasts)
+    pub title_shows_last_key: bool,
+}
+
+const DEFAULT_COPY_MODE_COLOR: tuinix::TerminalColor = tuinix::TerminalColor::YELLOW;
+
+/// Selections of `ModifierCycle` keys in cycling order, with their labels
+const MODIFIER_CYCLE: &[(Modifiers, &str)] = &[
+    (Modifiers::new(false, false, false), "Mod"),
+    (Modifiers::new(true, false, false), "C-"),
+    (Modifiers::new(false, true, false), "M-"),
+    (Modifiers::new(false, false, true), "S-"),
+    (Modifiers::new(true, true, false), "C-M-"),
+    (Modifiers::new(true, false, true), "C-S-"),
+    (Modifiers::new(false, true, true), "M-S-"),
+    (Modifiers::new(true, true, true), "C-M-S-"),
+];
+
+/// Values of the `pane-border-status` window option in the order `TogglePaneBorders` keys cycle them
+const PANE_BORDER_STATUSES: &[&str] = &["off", "top", "bottom"];
+
+/// Largest file that a `Snippet` key types, so that a wrong path cannot flood the pane
+const SNIPPET_MAX_BYTES: u64 = 64 * 1024;
+
+/// How often the labels of keys with a `"label_format"` are updated
+const FORMAT_LABEL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Label of a key whose `"label_format"` could not be evaluated
+const FORMAT_LABEL_PLACEHOLDER: &str = "?";
+
+/// Maximum number of buffers shown in the paste history panel
+const PASTE_HISTORY_LIMIT: usize = 8;
+
+/// Minimum width of the buttons in the paste history panel
+const PASTE_HISTORY_MIN_WIDTH: usize = 20;
+
+/// Number of columns scrolled by a mouse wheel step or an arrow key
+const H_SCROLL_STEP: usize = 4;
+
+/// How long an expanded tray waits for a key to be chosen before it collapses
+const TRAY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the pane numbers are shown and the next digit key selects a pane after `DisplayPanes`
+const PANE_NUMBER_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How long notifications (see [`App::notify()`]) are shown unless another input clears them
+const NOTIFICATION_TTL: Duration = Duration::from_secs(5);
+
+const PRESS_ANIMATION_FRAMES: usize = 2;
+const PRESS_ANIMATION_INTERVAL: Duration = Duration::from_millis(80);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Corner {
+    fn position(
+        self,
+        terminal_size: tuinix::TerminalSize,
+        size: tuinix::TerminalSize,
+    ) -> tuinix::TerminalPosition {
+        let bottom = terminal_size.rows.saturating_sub(size.rows);
+        let right = terminal_size.cols.saturating_sub(size.cols);
+        match self {
+            Self::TopLeft => tuinix::TerminalPosition::ZERO,
+            Self::TopRight => tuinix::TerminalPosition::col(right),
+            Self::BottomLeft => tuinix::TerminalPosition::row(bottom),
+            Self::BottomRight => tuinix::TerminalPosition::row_col(bottom, right),
+        }
+    }
+}
+
+impl std::str::FromStr for Corner {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "top-left" => Ok(Self::TopLeft),
+            "top-right" => Ok(Self::TopRight),
+            "bottom-left" => Ok(Self::BottomLeft),
+            "bottom-right" => Ok(Self::BottomRight),
+            _ => Err(format!("unknown corner: {s:?}")),
+        }
+    }
+}
+
+/// Where sent keys are echoed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EchoTarget {
+    /// The status line of tuke itself
+    Status,
+
+    /// A pane of the current window (e.g. one running `cat`), typed into via `send-keys`
+    Pane(usize),
+}
+
+impl std::str::FromStr for EchoTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "status" {
+            return Ok(Self::Status);
+        }
+        s.strip_prefix("pane:")
+            .and_then(|i| i.parse().ok())
+            .map(Self::Pane)
+            .ok_or_else(|| {
+                format!("unknown echo target: {s:?} (expected \"status\" or \"pane:N\")")
+            })
+    }
+}
+
+/// How to handle keys drawn in the rows shared with the status line, the badges and the indicators
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum StatusOverlap {
+    /// Keep the keyboard centered and show a warning
+    #[default]
+    Warn,
+
+    /// Move the keyboard off the top row if there is room, and reserve both rows when resizing the pane
+    Avoid,
+}
+
+impl std::str::FromStr for StatusOverlap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "warn" => Ok(Self::Warn),
+            "avoid" => Ok(Self::Avoid),
+            _ => Err(format!(
+                "unknown status overlap handling: {s:?} (expected \"warn\" or \"avoid\")"
+            )),
+        }
+    }
+}
+
+/// Arrangement of the key grid on screen
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    /// As written in the layout files
+    #[default]
+    Landscape,
+
+    /// Rows and columns swapped, so that a wide keyboard fits a tall screen (e.g. a phone)
+    Portrait,
+}
+
+impl std::str::FromStr for Orientation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "landscape" => Ok(Self::Landscape),
+            "portrait" => Ok(Self::Portrait),
+            _ => Err(format!(
+                "unknown orientation: {s:?} (expected \"landscape\" or \"portrait\")"
+            )),
+        }
+    }
+}
+
+/// Mode of the target pane, which changes what the keys do
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum AppMode {
+    #[default]
+    Normal,
+    CopyMode,
+}
+
+/// What to do when clicking outside of any key
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum EmptyClickAction {
+    #[default]
+    None,
+    ClearModifiers,
+    Quit,
+    SendKey(KeyCode),
+}
+
+impl std::str::FromStr for EmptyClickAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "clear-modifiers" => Ok(Self::ClearModifiers),
+            "quit" => Ok(Self::Quit),
+            _ => {
+                let token = s
+                    .strip_prefix("send:")
+                    .ok_or_else(|| format!("unknown empty click action: {s:?}"))?;
+                match KeyCode::from_token(token) {
+                    Some(code) if code.is_sendable() => Ok(Self::SendKey(code)),
+                    _ => Err(format!("not a sendable key: {token:?}")),
+                }
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct App {
-    terminal: tuinix::Terminal,
+    /// `None` in dry runs, where nothing is drawn
+    terminal: Option<tuinix::Terminal>,
+    terminal_size: tuinix::TerminalSize,
     options: AppOptions,
+    layouts: Layouts,
+    active_layout: String,
     keys: Vec<KeyState>,
     preview: Option<Preview>,
     exit: bool,
     offset: tuinix::TerminalPosition,
-    tmux_client: TmuxClient,
+
+    /// Number of columns scrolled to the right when the layout is wider than the terminal
+    h_offset: usize,
+    tmux_client: Box<dyn TmuxBackend>,
+    pane_index: usize,
+    mode: AppMode,
+
+    /// Whether a selection has been started in the target pane's copy mode
+    has_selection: bool,
+    pending_chord: Option<PendingChord>,
+    notification: Option<TimedMessage>,
+    focused_key: Option<usize>,
+
+    /// Index into [`MODIFIER_CYCLE`] selected by `ModifierCycle` keys
+    modifier_cycle: usize,
+
+    /// Index into [`PANE_BORDER_STATUSES`] last set by a `TogglePaneBorders` key
+    pane_border_status: Option<usize>,
+
+    /// Geometry of the panes in the current window, while `pane_map` is enabled
+    pane_map: Option<PaneMap>,
+
+    /// Key under the mouse when the left button was last pressed (for `drag_to_cancel`)
+    left_press_index: Option<usize>,
+
+    /// Momentary modifier held down by the mouse and its state before the press
+    held_modifier: Option<(usize, KeyPressState)>,
+
+    /// Whether keys without an explicit target are sent to all panes except the target pane
+    broadcast: bool,
+
+    /// Digits entered since a `NumberEntry` key was pressed
+    number_entry: Option<String>,
+
+    /// Keys clicked in queue mode and their modifiers, in order, or `None` outside queue mode
+    queue: Option<Vec<(Key, Modifiers)>>,
+
+    /// Until when the next digit key selects a pane, after a `DisplayPanes` key was pressed
+    pane_number_deadline: Option<Instant>,
+
+    /// When unused one-shot modifiers clear (with `oneshot_timeout`)
+    oneshot_deadline: Option<Instant>,
+
+    /// Key with Ctrl or Alt applied that is sent if clicked again (with `confirm_modified`)
+    pending_confirm: Option<PendingConfirm>,
+
+    /// Revert of the last pressed momentary special key, sent when due
+    pending_revert: Option<PendingRevert>,
+
+    /// Tap/hold key held down by the mouse
+    held_tap_hold: Option<HeldTapHold>,
+
+    /// Index of the dead key waiting for the character to accent
+    pending_dead_key: Option<usize>,
+
+    /// Index of the first paste history button in `keys`, while the panel is open
+    paste_history_start: Option<usize>,
+
+    /// Index of the first tray key in `keys` and when the tray collapses, while it is expanded
+    tray: Option<(usize, Instant)>,
+    cursor_refresh_deadline: Option<Instant>,
+
+    /// When the labels of keys with a `"label_format"` are next updated, if there are any
+    format_label_deadline: Option<Instant>,
+    animation_deadline: Option<Instant>,
+    press_counts: Option<HashMap<KeyCode, usize>>,
+
+    frame_limiter: FrameLimiter,
+
+    /// Start time of the app, from which the timestamps of echoed keys are measured
+    started_at: Instant,
+
+    /// Running processes of `on_press_command`, kept to be reaped
+    on_press_children: Vec<std::process::Child>,
+
+    /// What to restore in the terminal on exit, `None` in dry runs
+    cleanup: Option<ExitCleanup>,
+}
+
+/// Escape sequences that disable mouse reporting, as [`tuinix::Terminal::disable_mouse_input()`] does
+const MOUSE_OFF: &[u8] = b"\x1b[?1006l\x1b[?1015l\x1b[?1002l\x1b[?1000l";
+
+/// Restores the terminal settings that [`tuinix::Terminal`] does not restore by itself
+struct ExitCleanup {
+    output: Box<dyn std::io::Write>,
+    mouse: bool,
+    title: bool,
+}
+
+impl ExitCleanup {
+    fn run(&mut self) -> std::io::Result<()> {
+        if self.title {
+            self.output.write_all(title_sequence("").as_bytes())?;
+        }
+        if self.mouse {
+            self.output.write_all(MOUSE_OFF)?;
+        }
+        self.output.flush()
+    }
+}
+
+impl std::fmt::Debug for ExitCleanup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExitCleanup")
+            .field("mouse", &self.mouse)
+            .field("title", &self.title)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Modifiers {
+    ctrl: bool,
+    alt: bool,
+    shift: bool,
+}
+
+impl Modifiers {
+    const fn new(ctrl: bool, alt: bool, shift: bool) -> Self {
+        Self { ctrl, alt, shift }
+    }
+
+    /// Returns how `key` is written in tmux with these modifiers applied (e.g. `C-c`)
+    fn key_name(self, key: &Key) -> String {
+        let modifiable = key.code.is_modifiable();
+        let code = if self.shift {
+            &key.shift_code
+        } else {
+            &key.code
+        };
+        format!(
+            "{}{}{code}",
+            if self.ctrl && modifiable { "C-" } else { "" },
+            if self.alt && modifiable { "M-" } else { "" },
+        )
+    }
+}
+
+/// A key press that may become the first half of a chord
+#[derive(Debug)]
+struct PendingChord {
+    index: usize,
+    mouse_input: tuinix::MouseInput,
+    deadline: Instant,
+}
+
+/// Transient message drawn at the bottom left until it expires or the next input arrives
+#[derive(Debug)]
+struct TimedMessage {
+    text: String,
+    deadline: Instant,
+}
+
+/// A modified key waiting for the click that confirms sending it
+#[derive(Debug)]
+struct PendingConfirm {
+    index: usize,
+
+    /// Modifiers consumed by the first click, applied when confirmed
+    modifiers: Modifiers,
+    deadline: Instant,
+}
+
+/// Undo of a momentary special key's action (see [`crate::layout::Revert`])
+#[derive(Debug)]
+struct PendingRevert {
+    /// Index of the momentary key, which stays pressed until then
+    /// (`None` once the key is gone, e.g. with a closed tray)
+    index: Option<usize>,
+
+    /// Key sent to the momentary key's target
+    key: Key,
+    deadline: Instant,
+}
+
+/// A press of a key with a `"tap_hold"` member that has not been released yet
+#[derive(Debug)]
+struct HeldTapHold {
+    key: Key,
+    state: TapHold,
+
+    /// Modifiers consumed by the press, applied to every send
+    modifiers: Modifiers,
 }
 
 impl App {
-    pub fn new(layout: Layout, options: AppOptions) -> orfail::Result<Self> {
+    pub fn new(
+        layouts: Layouts,
+        tmux_client: impl TmuxBackend + 'static,
+        options: AppOptions,
+    ) -> orfail::Result<Self> {
         let mut terminal = tuinix::Terminal::new().or_fail()?;
 
-        terminal.enable_mouse_input().or_fail()?;
+        if !options.no_mouse {
+            terminal.enable_mouse_input().or_fail()?;
+        }
+        install_panic_hook();
+
+        let terminal_size = terminal.size();
+        let mut app = Self::with_terminal(
+            Some(terminal),
+            terminal_size,
+            layouts,
+            Box::new(tmux_client),
+            options,
+        )?;
+        app.cleanup = Some(ExitCleanup {
+            output: Box::new(std::io::stdout()),
+            mouse: !app.options.no_mouse,
+            title: app.options.title_shows_last_key,
+        });
+        Ok(app)
+    }
+
+    /// Makes an app that handles input without a terminal, as if it were `terminal_size`
+    #[cfg(test)]
+    fn dry_run(
+        terminal_size: tuinix::TerminalSize,
+        layouts: Layouts,
+        tmux_client: impl TmuxBackend + 'static,
+        options: AppOptions,
+    ) -> orfail::Result<Self> {
+        Self::with_terminal(None, terminal_size, layouts, Box::new(tmux_client), options)
+    }
+
+    fn with_terminal(
+        terminal: Option<tuinix::Terminal>,
+        terminal_size: tuinix::TerminalSize,
+        mut layouts: Layouts,
+        tmux_client: Box<dyn TmuxBackend>,
+        options: AppOptions,
+    ) -> orfail::Result<Self> {
+        arrange_layouts(&mut layouts, &options);
 
+        let layout = layouts.entries.first().or_fail()?.clone();
         let keys = layout
             .keys
             .iter()
             .map(|k| KeyState::new(k.clone()))
             .collect();
 
-        let tmux_client = TmuxClient::new().or_fail()?;
-
         let mut app = Self {
             terminal,
-            options,
+            terminal_size,
+            layouts,
+            active_layout: layout.name,
             keys,
             preview: layout.preview,
             exit: false,
             offset: tuinix::TerminalPosition::default(),
+            h_offset: 0,
             tmux_client,
+            pane_index: 0,
+            mode: AppMode::Normal,
+            has_selection: false,
+            pending_chord: None,
+            notification: None,
+            focused_key: None,
+            modifier_cycle: 0,
+            pane_border_status: None,
+            pane_map: None,
+            left_press_index: None,
+            held_modifier: None,
+            broadcast: false,
+            number_entry: None,
+            queue: options.queue_keys.then(Vec::new),
+            pane_number_deadline: None,
+            oneshot_deadline: None,
+            pending_confirm: None,
+            pending_revert: None,
+            held_tap_hold: None,
+            pending_dead_key: None,
+            paste_history_start: None,
+            tray: None,
+            cursor_refresh_deadline: None,
+            format_label_deadline: None,
+            animation_deadline: None,
+            press_counts: options.stats_path.is_some().then(HashMap::new),
+            frame_limiter: FrameLimiter::new(options.min_frame_interval),
+            started_at: Instant::now(),
+            on_press_children: Vec::new(),
+            cleanup: None,
+            options,
         };
 
         app.calculate_offset();
+        app.update_modifier_cycle_labels();
 
         Ok(app)
     }
 
     pub fn run(mut self) -> orfail::Result<()> {
-        self.render().or_fail()?;
+        self.refresh_mode().or_fail()?;
+        self.refresh_pane_map().or_fail()?;
+        self.update_binding_labels();
+        self.refresh_format_labels();
+        self.request_render().or_fail()?;
+        self.schedule_cursor_refresh();
 
-        let mut set_timeout = true;
         while !self.exit {
-            let timeout = set_timeout.then_some(self.options.cursor_refresh_interval);
-            match self.terminal.poll_event(&[], &[], timeout).or_fail()? {
+            if self.tmux_client.has_pending_notifications() {
+                // Notifications received while waiting for command responses
+                self.handle_tmux_notifications().or_fail()?;
+            }
+
+            let timeout = self.next_timeout(Instant::now());
+            let tmux_fd = self.tmux_client.fd();
+            match self
+                .terminal
+                .as_mut()
+                .or_fail()?
+                .poll_event(&[tmux_fd], &[], timeout)
+                .or_fail()?
+            {
                 Some(tuinix::TerminalEvent::Input(input)) => {
                     self.handle_input(input).or_fail()?;
-                    self.render().or_fail()?;
-                    set_timeout = true;
+                    self.request_render().or_fail()?;
+                    self.schedule_cursor_refresh();
                 }
-                Some(tuinix::TerminalEvent::Resize(_)) => {
+                Some(tuinix::TerminalEvent::Resize(size)) => {
+                    self.terminal_size = size;
                     self.calculate_offset();
-                    self.render().or_fail()?;
-                    set_timeout = true;
+                    self.request_render().or_fail()?;
+                    self.schedule_cursor_refresh();
+                }
+                Some(tuinix::TerminalEvent::FdReady { .. }) => {
+                    self.handle_tmux_notifications().or_fail()?;
                 }
                 None => {
                     // Timeout
-                    self.tmux_command("select-pane", &["-t", "0:.0"])
-                        .or_fail()?;
-                    set_timeout = false;
+                    self.handle_timers().or_fail()?;
                 }
-                _ => {}
             }
         }
+
         Ok(())
     }
 
-    fn handle_input(&mut self, input: tuinix::TerminalInput) -> orfail::Result<()> {
-        match input {
-            tuinix::TerminalInput::Key(key_input) => {
-                self.exit = match key_input.code {
-                    tuinix::KeyCode::Char('q') => true,
-                    tuinix::KeyCode::Char('c') if key_input.ctrl => true,
-                    _ => false,
-                };
-            }
-            tuinix::TerminalInput::Mouse(mouse_input) => {
-                self.handle_mouse_input(mouse_input).or_fail()?;
+    fn handle_tmux_notifications(&mut self) -> orfail::Result<()> {
+        let mut needs_render = false;
+        let mut needs_mode_refresh = false;
+        for notification in self.tmux_client.take_notifications().or_fail()? {
+            match notification {
+                Notification::WindowPaneChanged { pane_id } => {
+                    // Follow pane selections made outside of tuke
+                    if own_pane_id().as_deref() == Some(pane_id.as_str()) {
+                        continue;
+                    }
+                    let lines = self
+                        .tmux_client
+                        .query("display-message", &["-p", "-t", &pane_id, "#{pane_index}"])
+                        .or_fail()?;
+                    if let Some(index) = lines.first().and_then(|l| l.parse().ok())
+                        && index != self.pane_index
+                    {
+                        self.pane_index = index;
+                        needs_render = true;
+                        needs_mode_refresh = true;
+                    }
+                }
+                Notification::LayoutChange => {
+                    self.refresh_pane_map().or_fail()?;
+                    needs_render |= self.options.pane_map.is_some();
+                    let fell_back = self.fall_back_if_pane_missing().or_fail()?;
+                    needs_render |= fell_back;
+                    needs_mode_refresh |= fell_back;
+                }
+                Notification::PaneModeChanged => {
+                    needs_mode_refresh = true;
+                }
             }
         }
+        if needs_mode_refresh {
+            needs_render |= self.refresh_mode().or_fail()?;
+        }
+
+        if needs_render {
+            self.request_render().or_fail()?;
+        }
         Ok(())
     }
 
-    fn handle_mouse_input(&mut self, mouse_input: tuinix::MouseInput) -> orfail::Result<()> {
-        if mouse_input.event != tuinix::MouseEvent::LeftRelease {
+    /// Reloads the pane geometry shown by the pane map, if enabled
+    fn refresh_pane_map(&mut self) -> orfail::Result<()> {
+        if self.options.pane_map.is_none() {
             return Ok(());
         }
+        let lines = self
+            .tmux_client
+            .query("list-panes", &["-t", ":", "-F", PANE_MAP_FORMAT])
+            .or_fail()?;
+        self.pane_map = Some(PaneMap::parse(&lines).or_fail()?);
+        Ok(())
+    }
 
-        let adjusted_position = tuinix::TerminalPosition::row_col(
-            mouse_input.position.row.saturating_sub(self.offset.row),
-            mouse_input.position.col.saturating_sub(self.offset.col),
-        );
+    /// Returns the region of the terminal where the pane map is drawn
+    fn pane_map_region(&self) -> Option<tuinix::TerminalRegion> {
+        let corner = self.options.pane_map?;
+        self.pane_map.as_ref()?;
+        let position = corner.position(self.terminal_size, PANE_MAP_SIZE);
+        Some(tuinix::TerminalRegion {
+            position,
+            size: PANE_MAP_SIZE,
+        })
+    }
 
-        let Some(pressed_index) = self
-            .keys
-            .iter()
-            .position(|ks| ks.key.region.contains(adjusted_position))
-        else {
-            return Ok(());
+    /// Updates the mode from the state of the target pane and returns `true` if it has changed
+    fn refresh_mode(&mut self) -> orfail::Result<bool> {
+        let target = PaneTarget::Index(self.pane_index).to_string();
+        let lines = self
+            .tmux_client
+            .query(
+                "display-message",
+                &["-p", "-t", &target, "#{pane_in_mode} #{selection_present}"],
+            )
+            .or_fail()?;
+        let mut fields = lines.first().map(|l| l.split(' ')).into_iter().flatten();
+        let mode = if fields.next() == Some("1") {
+            AppMode::CopyMode
+        } else {
+            AppMode::Normal
         };
+        let has_selection = fields.next() == Some("1");
+        let changed = mode != self.mode || has_selection != self.has_selection;
+        self.mode = mode;
+        self.has_selection = has_selection;
+        Ok(changed)
+    }
 
-        if self.keys[pressed_index].key.code.is_modifier() {
-            self.handle_modifier_key_pressed(pressed_index).or_fail()?;
-        } else {
-            self.handle_normal_key_pressed(pressed_index).or_fail()?;
+    /// Returns the state that the `"when"` conditions of keys are evaluated against
+    fn key_context(&self) -> KeyContext {
+        KeyContext {
+            copy_mode: self.mode == AppMode::CopyMode,
+            has_selection: self.has_selection,
+            pane_index: self.pane_index,
         }
+    }
 
-        Ok(())
+    /// Returns `true` if the key is scrolled into view and its `"when"` condition holds
+    fn is_shown(&self, key: &Key) -> bool {
+        self.is_visible(key.region) && key.is_enabled(&self.key_context())
     }
 
-    fn reset_pressed_keys(&mut self) {
-        for key in &mut self.keys {
-            if key.press == KeyPressState::Pressed {
-                key.press = KeyPressState::Neutral;
-            }
-        }
+    fn schedule_cursor_refresh(&mut self) {
+        self.cursor_refresh_deadline = Some(Instant::now() + self.options.cursor_refresh_interval);
     }
 
-    fn tmux_command(&mut self, command: &str, args: &[&str]) -> orfail::Result<()> {
-        self.tmux_client.send_command(command, args).or_fail()?;
-        Ok(())
+    /// Returns how long to wait for input before handling the timers, or `None` to wait indefinitely
+    fn next_timeout(&self, now: Instant) -> Option<Duration> {
+        self.next_deadline()
+            .map(|deadline| deadline.saturating_duration_since(now))
     }
 
-    fn handle_modifier_key_pressed(&mut self, i: usize) -> orfail::Result<()> {
-        self.reset_pressed_keys();
+    fn next_deadline(&self) -> Option<Instant> {
+        let deadlines = [
+            self.cursor_refresh_deadline,
+            self.format_label_deadline,
+            self.pending_chord.as_ref().map(|p| p.deadline),
+            self.animation_deadline,
+            self.held_tap_hold.as_ref().and_then(|h| h.state.deadline()),
+            self.pane_number_deadline,
+            self.oneshot_deadline,
+            self.pending_confirm.as_ref().map(|p| p.deadline),
+            self.pending_revert.as_ref().map(|r| r.deadline),
+            self.notification.as_ref().map(|n| n.deadline),
+            self.tray.map(|(_, deadline)| deadline),
+            self.frame_limiter.deadline(),
+        ];
+        wakeup_time(deadlines.into_iter().flatten(), self.options.timer_slack)
+    }
 
-        match self.keys[i].press {
-            KeyPressState::Neutral => {
-                self.keys[i].press = KeyPressState::OneshotActivated;
-            }
-            KeyPressState::Pressed => {
-                self.keys[i].press = KeyPressState::OneshotActivated;
-            }
-            KeyPressState::Activated => {
-                self.keys[i].press = KeyPressState::Neutral;
-            }
-            KeyPressState::OneshotActivated => {
-                self.keys[i].press = KeyPressState::Activated;
-            }
+    fn handle_timers(&mut self) -> orfail::Result<()> {
+        let now = Instant::now();
+        let mut needs_render = false;
+
+        if self
+            .pending_chord
+            .as_ref()
+            .is_some_and(|p| p.deadline <= now)
+        {
+            // No second key arrived in time
+            self.flush_pending_chord().or_fail()?;
+            self.schedule_cursor_refresh();
+            needs_render = true;
         }
 
-        Ok(())
-    }
+        if let Some(held) = &mut self.held_tap_hold
+            && held.state.poll(now) == Some(TapHoldAction::Repeat)
+        {
+            let (key, modifiers) = (held.key.clone(), held.modifiers);
+            self.send_key(&key, modifiers).or_fail()?;
+            needs_render = true;
+        }
 
-    fn handle_normal_key_pressed(&mut self, i: usize) -> orfail::Result<()> {
-        for key in &mut self.keys {
-            match key.press {
-                KeyPressState::Neutral => {}
-                KeyPressState::Pressed => {
+        if self.pane_number_deadline.is_some_and(|d| d <= now) {
+            self.pane_number_deadline = None;
+            needs_render = true;
+        }
+
+        if self.oneshot_deadline.is_some_and(|d| d <= now) {
+            self.oneshot_deadline = None;
+            let held = self.held_modifier.map(|(i, _)| i);
+            for (i, key) in self.keys.iter_mut().enumerate() {
+                // A modifier held down for a drag is not waiting for a click
+                if key.press == KeyPressState::OneshotActivated && Some(i) != held {
                     key.press = KeyPressState::Neutral;
                 }
-                KeyPressState::Activated => {}
-                KeyPressState::OneshotActivated => {
-                    key.press = KeyPressState::Pressed;
-                }
             }
+            needs_render = true;
         }
-        self.keys[i].press = KeyPressState::Pressed;
 
-        let mut code = self.keys[i].key.code;
-        let mut key_string = String::new();
-        let mut ctrl = false;
-        let mut alt = false;
-        if code.is_modifiable() {
-            if self.is_ctrl_pressed() {
-                key_string.push_str("C-");
-                ctrl = true;
-            }
-            if self.is_alt_pressed() {
-                key_string.push_str("M-");
-                alt = true;
+        if self
+            .pending_confirm
+            .take_if(|p| p.deadline <= now)
+            .is_some()
+        {
+            // Not confirmed in time, so the key is dropped
+            self.notification = None;
+            self.reset_pressed_keys();
+            needs_render = true;
+        }
+
+        if let Some(revert) = self.pending_revert.take_if(|r| r.deadline <= now) {
+            self.send_key(&revert.key, Modifiers::default()).or_fail()?;
+            if let Some(key) = revert.index.and_then(|i| self.keys.get_mut(i))
+                && key.press == KeyPressState::Pressed
+            {
+                key.press = KeyPressState::Neutral;
             }
+            self.refresh_mode().or_fail()?;
+            needs_render = true;
         }
-        if self.is_shift_pressed() {
-            code = self.keys[i].key.shift_code;
+
+        if self.notification.take_if(|n| n.deadline <= now).is_some() {
+            needs_render = true;
         }
 
-        key_string.push_str(&code.to_string());
+        if self.tray.is_some_and(|(_, deadline)| deadline <= now) {
+            self.close_tray();
+            needs_render = true;
+        }
 
-        self.tmux_command("send-keys", &["-t", "0:.0", &key_string])
-            .or_fail()?;
+        if self.animation_deadline.is_some_and(|d| d <= now) {
+            self.advance_press_animations();
+            needs_render = true;
+        }
 
-        if let Some(preview) = &mut self.preview {
-            preview.on_key_sent(code, ctrl, alt);
+        if self.cursor_refresh_deadline.is_some_and(|d| d <= now) {
+            self.cursor_refresh_deadline = None;
+            let target = PaneTarget::Index(self.pane_index).to_string();
+            if let Err(e) = self.tmux_command("select-pane", &["-t", &target]) {
+                self.recover_from_missing_pane(e).or_fail()?;
+                needs_render = true;
+            } else if self
+                .keys
+                .iter()
+                .any(|k| k.key.when == Some(KeyCondition::HasSelection))
+            {
+                // Starting or clearing a selection does not notify a mode change
+                needs_render |= self.refresh_mode().or_fail()?;
+            }
+        }
+
+        if self.format_label_deadline.is_some_and(|d| d <= now) {
+            needs_render |= self.refresh_format_labels();
         }
 
+        if needs_render {
+            self.request_render().or_fail()?;
+        } else if self.frame_limiter.poll(now) {
+            // Render the latest state that was held back by the frame rate cap
+            self.render().or_fail()?;
+        }
         Ok(())
     }
 
-    fn is_ctrl_pressed(&self) -> bool {
-        self.keys.iter().any(|k| {
-            k.key.code == KeyCode::Ctrl
-                && matches!(k.press, KeyPressState::Pressed | KeyPressState::Activated)
-        })
+    /// Shows `text` at the bottom left for `ttl`, or until the next input
+    fn notify(&mut self, text: impl Into<String>, ttl: Duration) {
+        self.notification = Some(TimedMessage {
+            text: text.into(),
+            deadline: Instant::now() + ttl,
+        });
     }
 
-    fn is_alt_pressed(&self) -> bool {
-        self.keys.iter().any(|k| {
-            k.key.code == KeyCode::Alt
-                && matches!(k.press, KeyPressState::Pressed | KeyPressState::Activated)
-        })
+    /// Returns the notification and the prompts of the modes waiting for input, bottom line last
+    fn status_lines(&self) -> Vec<(String, tuinix::TerminalStyle)> {
+        let prompt_style = tuinix::TerminalStyle::new().bold().reverse();
+        let mut lines = Vec::new();
+        if let Some(message) = self.notification_text() {
+            lines.push((message.to_owned(), tuinix::TerminalStyle::new().bold()));
+        }
+        if let Some(queue) = &self.queue {
+            let names = queue
+                .iter()
+                .map(|(key, modifiers)| modifiers.key_name(key))
+                .collect::<Vec<_>>();
+            lines.push((format!("Queue: {}_", names.join(" ")), prompt_style));
+        }
+        if let Some(number) = &self.number_entry {
+            lines.push((format!("Number: {number}_"), prompt_style));
+        }
+        if self.pane_number_deadline.is_some() {
+            lines.push(("Pane: _".to_owned(), prompt_style));
+        }
+        lines
     }
 
-    fn is_shift_pressed(&self) -> bool {
-        self.keys.iter().any(|k| {
-            k.key.code == KeyCode::Shift
-                && matches!(k.press, KeyPressState::Pressed | KeyPressState::Activated)
-        })
+    fn notification_text(&self) -> Option<&str> {
+        self.notification.as_ref().map(|n| n.text.as_str())
     }
 
-    fn is_shift_active(&self) -> bool {
-        self.keys.iter().any(|k| {
-            k.key.code == KeyCode::Shift
-                && matches!(
-                    k.press,
-                    KeyPressState::OneshotActivated | KeyPressState::Activated
-                )
-        })
+    fn start_press_animation(&mut self, i: usize) {
+        if !self.options.press_animation {
+            return;
+        }
+
+        // Restarting from the first frame cancels any animation in progress on this key
+        self.keys[i].animation_frame = Some(0);
+        self.animation_deadline = Some(Instant::now() + PRESS_ANIMATION_INTERVAL);
     }
 
-    fn calculate_offset(&mut self) {
-        let terminal_size = self.terminal.size();
-        let mut actual_frame_size = tuinix::TerminalSize::default();
+    fn advance_press_animations(&mut self) {
+        let mut animating = false;
+        for key in &mut self.keys {
+            key.animation_frame = key
+                .animation_frame
+                .map(|frame| frame + 1)
+                .filter(|&frame| frame < PRESS_ANIMATION_FRAMES);
+            animating |= key.animation_frame.is_some();
+        }
+        self.animation_deadline = animating.then(|| Instant::now() + PRESS_ANIMATION_INTERVAL);
+    }
 
-        for key_state in &self.keys {
-            actual_frame_size.rows = actual_frame_size
-                .rows
-                .max(key_state.key.region.position.row + key_state.key.region.size.rows);
-            actual_frame_size.cols = actual_frame_size
-                .cols
-                .max(key_state.key.region.position.col + key_state.key.region.size.cols);
+    fn handle_input(&mut self, input: tuinix::TerminalInput) -> orfail::Result<()> {
+        self.notification = None;
+        match input {
+            tuinix::TerminalInput::Key(key_input) => {
+                self.handle_key_input(key_input).or_fail()?;
+            }
+            tuinix::TerminalInput::Mouse(mouse_input) => {
+                self.handle_mouse_input(mouse_input).or_fail()?;
+            }
         }
+        Ok(())
+    }
 
-        // Calculate centering offset
-        let offset_row = (terminal_size.rows.saturating_sub(actual_frame_size.rows)) / 2;
-        let offset_col = (terminal_size.cols.saturating_sub(actual_frame_size.cols)) / 2;
+    fn handle_key_input(&mut self, key_input: tuinix::KeyInput) -> orfail::Result<()> {
+        match key_input.code {
+            tuinix::KeyCode::Char('c') if key_input.ctrl => {
+                self.exit = true;
+            }
+            tuinix::KeyCode::Enter => {
+                if let Some(i) = self.focused_key
+                    && self.keys[i].key.is_enabled(&self.key_context())
+                {
+                    self.activate_key(i, self.keyboard_activation_input(i))
+                        .or_fail()?;
+                }
+            }
+            tuinix::KeyCode::Left => {
+                self.scroll_left();
+            }
+            tuinix::KeyCode::Right => {
+                self.scroll_right();
+            }
+            tuinix::KeyCode::Escape => {
+                self.focused_key = None;
+                self.close_paste_history();
+                self.close_tray();
+                if self.number_entry.take().is_some() {
+                    self.update_toggle_keys();
+                }
+            }
+            tuinix::KeyCode::Char('l') if key_input.ctrl && !key_input.alt => {
+                self.force_redraw().or_fail()?;
+            }
+            tuinix::KeyCode::Char('q') if !key_input.ctrl && !key_input.alt => {
+                self.exit = true;
+            }
+            tuinix::KeyCode::Char(c) if !key_input.ctrl && !key_input.alt => {
+                let candidates = self
+                    .keys
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, k)| {
+                        k.key.mnemonic == Some(c) && k.key.is_enabled(&self.key_context())
+                    })
+                    .map(|(i, _)| i)
+                    .collect::<Vec<_>>();
+                match candidates.as_slice() {
+                    [] => {}
+                    [i] => {
+                        self.activate_key(*i, self.keyboard_activation_input(*i))
+                            .or_fail()?;
+                    }
+                    _ => {
+                        // Duplicate mnemonics: cycle the focus, then Enter activates the focused key
+                        let next = candidates
+                            .iter()
+                            .copied()
+                            .find(|&i| self.focused_key.is_some_and(|f| f < i))
+                            .unwrap_or(candidates[0]);
+                        self.focused_key = Some(next);
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
 
-        self.offset = tuinix::TerminalPosition::row_col(offset_row, offset_col);
+    /// Makes a synthetic click for activating a key from the keyboard
+    fn keyboard_activation_input(&self, i: usize) -> tuinix::MouseInput {
+        tuinix::MouseInput {
+            event: tuinix::MouseEvent::LeftRelease,
+            position: self.keys[i].key.region.position,
+            ctrl: false,
+            alt: false,
+            shift: false,
+        }
     }
 
-    fn render(&mut self) -> orfail::Result<()> {
-        let terminal_size = self.terminal.size();
+    fn handle_mouse_input(&mut self, mouse_input: tuinix::MouseInput) -> orfail::Result<()> {
+        // The pane map is drawn over the keys, so it takes clicks first
+        if let Some(region) = self.pane_map_region()
+            && region.contains(mouse_input.position)
+        {
+            if mouse_input.event == tuinix::MouseEvent::LeftPress
+                && let Some(map) = &self.pane_map
+            {
+                let position = tuinix::TerminalPosition::row_col(
+                    mouse_input.position.row - region.position.row,
+                    mouse_input.position.col - region.position.col,
+                );
+                if let Some(index) = map.pane_at(position) {
+                    self.set_target_pane(index).or_fail()?;
+                }
+            }
+            return Ok(());
+        }
 
-        if self.options.auto_resize {
-            let required_rows = self
-                .keys
-                .iter()
-                .map(|k| k.key.region)
-                .chain(self.preview.iter().map(|p| p.region))
-                .map(|r| r.bottom_left().row + 1)
-                .max()
-                .unwrap_or_default();
-            if terminal_size.rows != required_rows {
+        let adjusted_position = tuinix::TerminalPosition::row_col(
+            mouse_input.position.row.saturating_sub(self.offset.row),
+            mouse_input.position.col.saturating_sub(self.offset.col) + self.h_offset,
+        );
+        let hit_padding = self
+            .layouts
+            .get(&self.active_layout)
+            .map_or(0, |l| l.hit_padding);
+        // Hidden keys get an empty region so that their padding cannot take clicks either,
+        // and while the tray is expanded, only its keys take clicks
+        let tray_start = self.tray.map_or(0, |(start, _)| start);
+        let regions = self.keys.iter().enumerate().map(|(i, ks)| {
+            if i >= tray_start && self.is_shown(&ks.key) {
+                ks.key.region
+            } else {
+                tuinix::TerminalRegion::default()
+            }
+        });
+        let pressed_index = crate::layout::hit_test(regions, adjusted_position, hit_padding);
+
+        match mouse_input.event {
+            tuinix::MouseEvent::ScrollUp => {
+                self.scroll_left();
+                return Ok(());
+            }
+            tuinix::MouseEvent::ScrollDown => {
+                self.scroll_right();
+                return Ok(());
+            }
+            tuinix::MouseEvent::LeftPress => {
+                self.left_press_index = pressed_index;
+                if let Some(i) = pressed_index
+                    && self.keys[i].key.code.is_modifier()
+                    && self.keys[i].key.modifier_mode == ModifierMode::Momentary
+                {
+                    self.held_modifier = Some((i, self.keys[i].press));
+                    self.keys[i].press = KeyPressState::OneshotActivated;
+                } else if let Some(i) = pressed_index
+                    && self.held_modifier.is_none()
+                    && self.number_entry.is_none()
+                    && let Some(config) = self.keys[i].key.tap_hold
+                {
+                    self.flush_pending_chord().or_fail()?;
+                    self.run_on_press_command(&self.keys[i].key.code.clone());
+                    let modifiers = self.consume_modifiers(mouse_input);
+                    self.keys[i].press = KeyPressState::Pressed;
+                    self.held_tap_hold = Some(HeldTapHold {
+                        key: self.keys[i].key.clone(),
+                        state: TapHold::new(config, Instant::now()),
+                        modifiers,
+                    });
+                }
+                return Ok(());
+            }
+            tuinix::MouseEvent::MiddleRelease => {
+                if let Some(i) = pressed_index {
+                    self.reset_modifier(i);
+                }
+                return Ok(());
+            }
+            tuinix::MouseEvent::LeftRelease => {}
+            _ => return Ok(()),
+        }
+        let left_press_index = self.left_press_index.take();
+
+        if let Some(held) = self.held_tap_hold.take() {
+            // The press started on the key, so it counts wherever the button is released
+            if let Some(counts) = &mut self.press_counts {
+                *counts.entry(held.key.code.clone()).or_default() += 1;
+            }
+            let modifiers = match held.state.release(Instant::now()) {
+                None => return Ok(()),
+                Some(TapHoldAction::HoldRelease) => Modifiers {
+                    shift: true,
+                    ..held.modifiers
+                },
+                Some(_) => held.modifiers,
+            };
+            self.send_key(&held.key, modifiers).or_fail()?;
+            return Ok(());
+        }
+
+        if let Some((held_index, previous)) = self.held_modifier.take() {
+            // Releasing a momentary modifier anywhere but over another key cancels it
+            let Some(i) = pressed_index.filter(|&i| !self.keys[i].key.code.is_modifier()) else {
+                self.keys[held_index].press = previous;
+                return Ok(());
+            };
+            self.activate_key(i, mouse_input).or_fail()?;
+            if self.keys[held_index].press == KeyPressState::OneshotActivated {
+                // Not consumed (e.g. by a special key)
+                self.keys[held_index].press = previous;
+            }
+            return Ok(());
+        }
+
+        if self.options.drag_to_cancel && pressed_index != left_press_index {
+            // Dragged off the pressed key, like on a touch screen
+            return Ok(());
+        }
+
+        let Some(pressed_index) = pressed_index else {
+            if self.tray.is_some() {
+                // Clicking elsewhere collapses the tray without choosing anything
+                self.close_tray();
+                return Ok(());
+            }
+            self.handle_empty_click(mouse_input).or_fail()?;
+            return Ok(());
+        };
+
+        self.activate_key(pressed_index, mouse_input).or_fail()?;
+        Ok(())
+    }
+
+    fn handle_empty_click(&mut self, mouse_input: tuinix::MouseInput) -> orfail::Result<()> {
+        match self.options.empty_click_action.clone() {
+            EmptyClickAction::None => {}
+            EmptyClickAction::ClearModifiers => {
+                for key in &mut self.keys {
+                    if key.key.code.is_modifier() {
+                        key.press = KeyPressState::Neutral;
+                    }
+                }
+                self.modifier_cycle = 0;
+                self.update_modifier_cycle_labels();
+            }
+            EmptyClickAction::Quit => {
+                self.exit = true;
+            }
+            EmptyClickAction::SendKey(code) => {
+                self.flush_pending_chord().or_fail()?;
+                let modifiers = self.consume_modifiers(mouse_input);
+                let key = Key::new(code, tuinix::TerminalRegion::default());
+                self.send_key(&key, modifiers).or_fail()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn activate_key(
+        &mut self,
+        pressed_index: usize,
+        mouse_input: tuinix::MouseInput,
+    ) -> orfail::Result<()> {
+        self.run_key(pressed_index, mouse_input).or_fail()?;
+        // The tray stays open while its key waits for the confirming click
+        if self.is_tray_key(pressed_index)
+            && self
+                .pending_confirm
+                .as_ref()
+                .is_none_or(|p| p.index != pressed_index)
+        {
+            self.close_tray();
+        }
+        Ok(())
+    }
+
+    fn is_tray_key(&self, i: usize) -> bool {
+        self.tray.is_some_and(|(start, _)| i >= start)
+    }
+
+    fn run_key(
+        &mut self,
+        pressed_index: usize,
+        mouse_input: tuinix::MouseInput,
+    ) -> orfail::Result<()> {
+        self.focused_key = None;
+        if let Some(counts) = &mut self.press_counts {
+            *counts
+                .entry(self.keys[pressed_index].key.code.clone())
+                .or_default() += 1;
+        }
+        self.run_on_press_command(&self.keys[pressed_index].key.code.clone());
+
+        // Any key ends waiting for a pane number, but only a digit selects a pane
+        if self.pane_number_deadline.take().is_some()
+            && let KeyCode::Char(c) = self.keys[pressed_index].key.code
+            && let Some(index) = c.to_digit(10)
+        {
+            self.select_target_pane(pressed_index, index as usize)
+                .or_fail()?;
+            return Ok(());
+        }
+
+        if self.number_entry.is_some() && self.keys[pressed_index].key.code.is_sendable() {
+            self.handle_number_entry_key_pressed(pressed_index, mouse_input)
+                .or_fail()?;
+            return Ok(());
+        }
+
+        // Tray keys are gone by the time a chord would be flushed, so they are never candidates
+        if self.active_chords().is_some()
+            && self.keys[pressed_index].key.code.is_sendable()
+            && !self.is_tray_key(pressed_index)
+        {
+            self.handle_chord_candidate_pressed(pressed_index, mouse_input)
+                .or_fail()?;
+            return Ok(());
+        }
+        self.flush_pending_chord().or_fail()?;
+
+        if self.keys[pressed_index].key.code.is_special() {
+            self.handle_special_key_pressed(pressed_index).or_fail()?;
+        } else if self.keys[pressed_index].key.code.is_modifier() {
+            self.handle_modifier_key_pressed(pressed_index).or_fail()?;
+        } else {
+            self.handle_normal_key_pressed(pressed_index, mouse_input)
+                .or_fail()?;
+        }
+
+        Ok(())
+    }
+
+    /// Makes the pane with `index` the target pane, as chosen by a digit key after `DisplayPanes`
+    fn select_target_pane(&mut self, i: usize, index: usize) -> orfail::Result<()> {
+        self.reset_pressed_keys();
+        self.keys[i].press = KeyPressState::Pressed;
+        self.set_target_pane(index).or_fail()
+    }
+
+    /// Makes the pane with `index` the target pane unless it does not exist or is tuke's own pane
+    fn set_target_pane(&mut self, index: usize) -> orfail::Result<()> {
+        if !self.list_target_panes().or_fail()?.contains(&index) {
+            self.notify(format!("no pane {index}"), NOTIFICATION_TTL);
+            return Ok(());
+        }
+        self.pane_index = index;
+        let target = PaneTarget::Index(index).to_string();
+        self.tmux_command("select-pane", &["-t", &target])
+            .or_fail()?;
+        Ok(())
+    }
+
+    /// Shows the pane numbers on the terminals attached to the session
+    ///
+    /// The control mode client has no display of its own, so the numbers are shown on the others.
+    fn display_panes(&mut self) -> orfail::Result<()> {
+        let clients = self
+            .tmux_client
+            .query(
+                "list-clients",
+                &["-t", ":", "-F", "#{client_control_mode} #{client_name}"],
+            )
+            .or_fail()?;
+        let duration = PANE_NUMBER_TIMEOUT.as_millis().to_string();
+        for name in display_panes_clients(&clients, self.options.tmux_client.as_deref()) {
+            self.tmux_command("display-panes", &["-b", "-d", &duration, "-t", name])
+                .or_fail()?;
+        }
+        Ok(())
+    }
+
+    /// Accumulates digits while entering a number, and sends the number when finished
+    ///
+    /// Enter sends only the number, while other keys are sent after it (e.g. a count prefix for vim).
+    fn handle_number_entry_key_pressed(
+        &mut self,
+        i: usize,
+        mouse_input: tuinix::MouseInput,
+    ) -> orfail::Result<()> {
+        self.reset_pressed_keys();
+        let key = self.keys[i].key.clone();
+        if let KeyCode::Char(c) = key.code
+            && c.is_ascii_digit()
+        {
+            self.number_entry.as_mut().or_fail()?.push(c);
+            self.keys[i].press = KeyPressState::Pressed;
+            return Ok(());
+        }
+
+        let number = self.number_entry.take().or_fail()?;
+        self.update_toggle_keys();
+        match key.code {
+            KeyCode::Escape => {}
+            KeyCode::Enter => {
+                if !number.is_empty() {
+                    self.send_number(&key, &number).or_fail()?;
+                }
+                self.keys[i].press = KeyPressState::Pressed;
+            }
+            _ => {
+                if !number.is_empty() && !self.send_number(&key, &number).or_fail()? {
+                    return Ok(());
+                }
+                self.handle_normal_key_pressed(i, mouse_input).or_fail()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends an entered number to the target of `key`, or queues its digits in queue mode
+    fn send_number(&mut self, key: &Key, number: &str) -> orfail::Result<bool> {
+        // Digit by digit, so that the number keeps its place among the queued keys
+        if let Some(queue) = &mut self.queue {
+            for c in number.chars() {
+                let digit = Key {
+                    code: KeyCode::Char(c),
+                    shift_code: KeyCode::Char(c),
+                    label: None,
+                    label_format: None,
+                    repeat_count: std::num::NonZeroUsize::MIN,
+                    ..key.clone()
+                };
+                queue.push((digit, Modifiers::default()));
+            }
+            return Ok(true);
+        }
+        self.send_keys(key, &["-l", number])
+    }
+
+    /// Starts `on_press_command` without waiting for it, ignoring any failure
+    fn run_on_press_command(&mut self, code: &KeyCode) {
+        let Some(command) = &self.options.on_press_command else {
+            return;
+        };
+
+        // Reap the processes of earlier presses that have finished
+        self.on_press_children
+            .retain_mut(|child| matches!(child.try_wait(), Ok(None)));
+
+        // The output is discarded so that it cannot garble the keyboard
+        if let Ok(child) = std::process::Command::new("sh")
+            .args(["-c", command])
+            .env("TUKE_KEY", code.to_string())
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+        {
+            self.on_press_children.push(child);
+        }
+    }
+
+    fn handle_special_key_pressed(&mut self, i: usize) -> orfail::Result<()> {
+        self.reset_pressed_keys();
+
+        let key = self.keys[i].key.clone();
+        match &key.code {
+            KeyCode::SwitchLayout(name) => {
+                // Layouts built in code are not checked like layout files, so do not give up on them
+                if self.layouts.get(name).is_some() {
+                    self.switch_layout(name).or_fail()?;
+                } else {
+                    self.notify(format!("unknown layout name: {name:?}"), NOTIFICATION_TTL);
+                }
+            }
+            KeyCode::SetBuffer { text } => {
+                self.tmux_command("set-buffer", &["--", text]).or_fail()?;
+                self.keys[i].press = KeyPressState::Pressed;
+            }
+            KeyCode::Paste => {
+                let target = key.target.unwrap_or(PaneTarget::Index(self.pane_index));
+                self.tmux_command("paste-buffer", &["-t", &target.to_string()])
+                    .or_fail()?;
+                self.keys[i].press = KeyPressState::Pressed;
+            }
+            KeyCode::ModifierCycle => {
+                self.modifier_cycle = (self.modifier_cycle + 1) % MODIFIER_CYCLE.len();
+                self.update_modifier_cycle_labels();
+            }
+            KeyCode::Dynamic(kind) => {
+                let target = key
+                    .target
+                    .unwrap_or(PaneTarget::Index(self.pane_index))
+                    .to_string();
+                let lines = self
+                    .tmux_client
+                    .query(
+                        "display-message",
+                        &["-p", "-t", &target, &kind.tmux_format()],
+                    )
+                    .or_fail()?;
+                // While broadcasting, the text of the target pane goes to the other panes
+                let text = self.literal_text(lines.join("\n"));
+                if !text.is_empty() {
+                    self.send_keys(&key, &["-l", &text]).or_fail()?;
+                }
+                self.keys[i].press = KeyPressState::Pressed;
+            }
+            KeyCode::Dead(_) => {
+                // Pressing a dead key twice sends the accent itself
+                if let Some(pending) = self.pending_dead_key.take() {
+                    self.keys[pending].press = KeyPressState::Neutral;
+                    if pending == i {
+                        self.send_pending_accent(pending, &key).or_fail()?;
+                        return Ok(());
+                    }
+                }
+                self.pending_dead_key = Some(i);
+                self.keys[i].press = KeyPressState::OneshotActivated;
+            }
+            KeyCode::Tray => {
+                if self.tray.is_some() {
+                    self.close_tray();
+                } else {
+                    self.open_tray(i);
+                }
+            }
+            KeyCode::PasteHistory => {
+                if self.paste_history_start.is_some() {
+                    self.close_paste_history();
+                } else {
+                    self.open_paste_history().or_fail()?;
+                }
+            }
+            KeyCode::PasteBuffer { name, .. } => {
+                let target = key.target.unwrap_or(PaneTarget::Index(self.pane_index));
+                self.tmux_command("paste-buffer", &["-b", name, "-t", &target.to_string()])
+                    .or_fail()?;
+                self.close_paste_history();
+            }
+            KeyCode::Snippet { path } => {
+                match read_snippet(path) {
+                    Ok(text) if text.is_empty() => {}
+                    Ok(text) => {
+                        let text = self.literal_text(text);
+                        self.send_keys(&key, &["-l", &text]).or_fail()?;
+                    }
+                    Err(e) => {
+                        // The file may be fixed without restarting, so keep going
+                        self.notify(e.message, NOTIFICATION_TTL);
+                    }
+                }
+                self.keys[i].press = KeyPressState::Pressed;
+            }
+            KeyCode::Binding {
+                table,
+                key: binding,
+            } => {
+                let command = self
+                    .tmux_client
+                    .query("list-keys", &["-T", table, binding])
+                    .ok()
+                    .and_then(|lines| {
+                        lines
+                            .iter()
+                            .find_map(|l| binding_command(l))
+                            .map(|c| c.to_owned())
+                    });
+                match command {
+                    Some(command) => {
+                        // The command is in tmux syntax already, so it is sent without quoting
+                        if let Err(e) = self.tmux_command(&command, &[]) {
+                            self.notify(e.message, NOTIFICATION_TTL);
+                        }
+                    }
+                    None => {
+                        self.notify(
+                            format!("{binding} is not bound in the {table} table"),
+                            NOTIFICATION_TTL,
+                        );
+                    }
+                }
+                // Bindings often select another window or pane (e.g. `new-window`), so follow it
+                self.follow_active_pane().or_fail()?;
+                self.refresh_pane_map().or_fail()?;
+                self.refresh_mode().or_fail()?;
+                self.keys[i].press = KeyPressState::Pressed;
+            }
+            KeyCode::CapturePane { path, start, end } => {
+                let target = key
+                    .target
+                    .unwrap_or(PaneTarget::Index(self.pane_index))
+                    .to_string();
+                let mut args = vec!["-p", "-t", &target];
+                if let Some(start) = start {
+                    args.extend(["-S", start]);
+                }
+                if let Some(end) = end {
+                    args.extend(["-E", end]);
+                }
+                // Failing to save is reported, as the keyboard itself is still usable
+                let message = match self
+                    .tmux_client
+                    .query("capture-pane", &args)
+                    .and_then(|lines| write_capture(path, &lines))
+                {
+                    Ok(count) => format!("saved {count} lines to {}", path.display()),
+                    Err(e) => e.message,
+                };
+                self.notify(message, NOTIFICATION_TTL);
+                self.keys[i].press = KeyPressState::Pressed;
+            }
+            KeyCode::LoadLayoutFile(path) => match Layouts::load_from_file(path) {
+                Ok(mut layouts) => {
+                    arrange_layouts(&mut layouts, &self.options);
+                    let name = layouts.entries.first().or_fail()?.name.clone();
+                    self.layouts = layouts;
+                    self.switch_layout(&name).or_fail()?;
+                }
+                Err(e) => {
+                    // Stay in the picker so that another layout can be chosen
+                    self.notify(e.message, NOTIFICATION_TTL);
+                }
+            },
+            KeyCode::NextWindow | KeyCode::PrevWindow => {
+                let direction = if key.code == KeyCode::NextWindow {
+                    "-n"
+                } else {
+                    "-p"
+                };
+                self.tmux_command("select-window", &[direction, "-t", ":"])
+                    .or_fail()?;
+                // Pane indices are per window, so start over from the new window's active pane
+                self.follow_active_pane().or_fail()?;
+                self.refresh_pane_map().or_fail()?;
+                self.refresh_mode().or_fail()?;
+                self.keys[i].press = KeyPressState::Pressed;
+            }
+            KeyCode::NumberEntry => {
+                // Pressing it again cancels the entry
+                self.number_entry = match self.number_entry {
+                    Some(_) => None,
+                    None => Some(String::new()),
+                };
+                self.update_toggle_keys();
+            }
+            KeyCode::QueueMode => {
+                // Leaving queue mode drops what has not been flushed
+                self.queue = match self.queue.take() {
+                    Some(queue) => {
+                        if !queue.is_empty() {
+                            self.notify(
+                                format!("discarded {} queued keys", queue.len()),
+                                NOTIFICATION_TTL,
+                            );
+                        }
+                        None
+                    }
+                    None => Some(Vec::new()),
+                };
+                self.update_toggle_keys();
+            }
+            KeyCode::FlushQueue => {
+                let queue = self.queue.as_mut().map(std::mem::take).unwrap_or_default();
+                for (queued_key, modifiers) in queue {
+                    self.send_key(&queued_key, modifiers).or_fail()?;
+                }
+                self.keys[i].press = KeyPressState::Pressed;
+            }
+            KeyCode::Unqueue => {
+                if let Some(queue) = &mut self.queue {
+                    queue.pop();
+                }
+                self.keys[i].press = KeyPressState::Pressed;
+            }
+            KeyCode::TogglePaneBorders => {
+                // The option may have been changed elsewhere, so start from its current value.
+                // If it cannot be read, cycle from the value this app set last.
+                let current = self
+                    .tmux_client
+                    .query(
+                        "display-message",
+                        &["-p", "-t", ":", "#{pane-border-status}"],
+                    )
+                    .ok()
+                    .and_then(|lines| {
+                        let value = lines.first()?;
+                        PANE_BORDER_STATUSES.iter().position(|s| s == value)
+                    })
+                    .or(self.pane_border_status)
+                    .unwrap_or(0);
+                let next = (current + 1) % PANE_BORDER_STATUSES.len();
                 self.tmux_command(
-                    "resize-pane",
-                    &["-t", "0:0.1", "-y", &required_rows.to_string()],
+                    "set-option",
+                    &[
+                        "-w",
+                        "-t",
+                        ":",
+                        "pane-border-status",
+                        PANE_BORDER_STATUSES[next],
+                    ],
                 )
                 .or_fail()?;
+                self.pane_border_status = Some(next);
+                self.update_pane_border_labels();
+                self.keys[i].press = KeyPressState::Pressed;
+            }
+            KeyCode::ScrollUp | KeyCode::ScrollDown => {
+                // Scrolling is not typing, so it stays with the target pane even while broadcasting
+                let target = key
+                    .target
+                    .unwrap_or(PaneTarget::Index(self.pane_index))
+                    .to_string();
+                let in_copy_mode = self
+                    .tmux_client
+                    .query("display-message", &["-p", "-t", &target, "#{pane_in_mode}"])
+                    .or_fail()?
+                    .first()
+                    .is_some_and(|l| l == "1");
+                if key.code == KeyCode::ScrollUp {
+                    if !in_copy_mode {
+                        // Copy mode is left again once scrolled back to the bottom (-e)
+                        self.tmux_command("copy-mode", &["-e", "-t", &target])
+                            .or_fail()?;
+                    }
+                    self.tmux_command("send-keys", &["-X", "-t", &target, "halfpage-up"])
+                        .or_fail()?;
+                } else if in_copy_mode {
+                    self.tmux_command("send-keys", &["-X", "-t", &target, "halfpage-down"])
+                        .or_fail()?;
+                }
+                self.refresh_mode().or_fail()?;
+                self.keys[i].press = KeyPressState::Pressed;
+            }
+            KeyCode::Redraw => {
+                self.keys[i].press = KeyPressState::Pressed;
+                self.force_redraw().or_fail()?;
+            }
+            KeyCode::DisplayPanes => {
+                self.display_panes().or_fail()?;
+                self.pane_number_deadline = Some(Instant::now() + PANE_NUMBER_TIMEOUT);
+                self.keys[i].press = KeyPressState::Pressed;
+            }
+            KeyCode::Broadcast => {
+                if self.options.allow_broadcast {
+                    self.broadcast = !self.broadcast;
+                    self.update_toggle_keys();
+                } else {
+                    self.notify(
+                        "broadcast is disabled (start tuke with --allow-broadcast)",
+                        NOTIFICATION_TTL,
+                    );
+                }
             }
+            _ => {}
         }
 
-        let mut frame: tuinix::TerminalFrame = tuinix::TerminalFrame::new(terminal_size);
-        let shift = self.is_shift_active();
+        if let Some(revert) = &key.revert {
+            // Pressing the key again before the revert postpones it
+            self.pending_revert = Some(PendingRevert {
+                index: Some(i),
+                key: Key {
+                    code: revert.code.clone(),
+                    shift_code: revert.code.clone(),
+                    repeat_count: std::num::NonZeroUsize::MIN,
+                    revert: None,
+                    label_format: None,
+                    ..key.clone()
+                },
+                deadline: Instant::now() + revert.after,
+            });
+            if let Some(key) = self.keys.get_mut(i) {
+                key.press = KeyPressState::Pressed;
+            }
+        }
+        Ok(())
+    }
 
-        for key_state in &mut self.keys {
-            let key_frame = key_state.to_frame(shift).or_fail()?;
-            frame.draw(key_state.key.region.position, &key_frame);
+    /// Shows the values of the `"label_format"`s of the keys as their labels
+    ///
+    /// Returns `true` if any label changed.
+    fn refresh_format_labels(&mut self) -> bool {
+        let mut changed = false;
+        for i in 0..self.keys.len() {
+            let Some(format) = self.keys[i].key.label_format.clone() else {
+                continue;
+            };
+            let target = self.keys[i]
+                .key
+                .target
+                .unwrap_or(PaneTarget::Index(self.pane_index))
+                .to_string();
+            // A label is not worth interrupting the keyboard for, so failures only show as such
+            let label = match self
+                .tmux_client
+                .query("display-message", &["-p", "-t", &target, &format])
+            {
+                Ok(lines) => lines.into_iter().next().unwrap_or_default(),
+                Err(_) => FORMAT_LABEL_PLACEHOLDER.to_owned(),
+            };
+            if self.keys[i].label.as_ref() != Some(&label) {
+                self.keys[i].label = Some(label);
+                changed = true;
+            }
         }
+        self.format_label_deadline = self
+            .keys
+            .iter()
+            .any(|k| k.key.label_format.is_some())
+            .then(|| Instant::now() + FORMAT_LABEL_INTERVAL);
+        changed
+    }
 
-        if let Some(preview) = &self.preview {
-            let preview_frame = preview.to_frame().or_fail()?;
-            frame.draw(preview.region.position, &preview_frame);
+    /// Labels `Binding` keys without a `"label"` with the notes of their bindings (from `bind-key -N`)
+    fn update_binding_labels(&mut self) {
+        for i in 0..self.keys.len() {
+            let KeyCode::Binding { table, key } = &self.keys[i].key.code else {
+                continue;
+            };
+            if self.keys[i].key.label.is_some() {
+                continue;
+            }
+            // Each line is the key followed by its note, and keys without a note are not listed
+            let note = self
+                .tmux_client
+                .query("list-keys", &["-N", "-T", table, key])
+                .ok()
+                .and_then(|lines| {
+                    let (_, note) = lines.first()?.split_once(' ')?;
+                    Some(note.trim().to_owned())
+                })
+                .filter(|note| !note.is_empty());
+            if note.is_some() {
+                self.keys[i].label = note;
+            }
         }
+    }
 
-        let mut centered_frame: tuinix::TerminalFrame = tuinix::TerminalFrame::new(terminal_size);
-        centered_frame.draw(self.offset, &frame);
-        self.terminal.draw(centered_frame).or_fail()?;
+    fn switch_layout(&mut self, name: &str) -> orfail::Result<()> {
+        self.flush_pending_chord().or_fail()?;
+        let layout = self.layouts.get(name).or_fail()?;
 
+        // Modifier states are carried over so that e.g. a latched Ctrl still applies
+        let mut keys: Vec<_> = layout.keys.iter().cloned().map(KeyState::new).collect();
+        for key_state in &mut keys {
+            let code = &key_state.key.code;
+            if let Some(old) = self
+                .keys
+                .iter()
+                .find(|old| code.is_modifier() && old.key.code == *code)
+            {
+                key_state.press = old.press;
+            }
+        }
+
+        match (&mut self.preview, &layout.preview) {
+            (Some(preview), Some(new_preview)) => preview.region = new_preview.region,
+            (preview, new_preview) => *preview = new_preview.clone(),
+        }
+        self.keys = keys;
+        self.update_modifier_cycle_labels();
+        self.update_pane_border_labels();
+        self.update_toggle_keys();
+        self.focused_key = None;
+        self.pending_dead_key = None;
+        self.pending_confirm = None;
+        self.paste_history_start = None;
+        self.tray = None;
+        self.active_layout = name.to_owned();
+        self.update_binding_labels();
+        self.refresh_format_labels();
+        self.calculate_offset();
+        Ok(())
+    }
+
+    /// Adds a button for each of the most recent paste buffers below the keyboard
+    fn open_paste_history(&mut self) -> orfail::Result<()> {
+        self.close_tray();
+        let lines = self
+            .tmux_client
+            .query("list-buffers", &["-F", "#{buffer_name}\t#{buffer_sample}"])
+            .or_fail()?;
+        if lines.is_empty() {
+            self.notify("no paste buffers", NOTIFICATION_TTL);
+            return Ok(());
+        }
+
+        let (bottom, right) =
+            self.keys
+                .iter()
+                .map(|k| k.key.region)
+                .fold((0, 0), |(bottom, right), r| {
+                    (
+                        bottom.max(r.position.row + r.size.rows),
+                        right.max(r.position.col + r.size.cols),
+                    )
+                });
+        let size = tuinix::TerminalSize::rows_cols(3, right.max(PASTE_HISTORY_MIN_WIDTH));
+
+        // list-buffers shows the most recent buffers first
+        self.paste_history_start = Some(self.keys.len());
+        for (i, line) in lines.iter().take(PASTE_HISTORY_LIMIT).enumerate() {
+            let (name, sample) = line.split_once('\t').unwrap_or((line, line));
+            let code = KeyCode::PasteBuffer {
+                name: name.to_owned(),
+                sample: sample.to_owned(),
+            };
+            let position = tuinix::TerminalPosition::row(bottom + i * size.rows);
+            let region = tuinix::TerminalRegion { position, size };
+            self.keys.push(KeyState::new(Key::new(code, region)));
+        }
+        self.calculate_offset();
+        Ok(())
+    }
+
+    /// Draws the tray keys over the keyboard at the `Tray` key at `i`, moved up or left to fit
+    fn open_tray(&mut self, i: usize) {
+        let tray = self
+            .layouts
+            .get(&self.active_layout)
+            .map(|l| l.tray.clone())
+            .unwrap_or_default();
+        if tray.is_empty() {
+            self.notify("this layout has no tray", NOTIFICATION_TTL);
+            return;
+        }
+        self.close_paste_history();
+
+        let extent = |regions: &mut dyn Iterator<Item = tuinix::TerminalRegion>| {
+            regions.fold((0, 0), |(bottom, right), r| {
+                (
+                    bottom.max(r.position.row + r.size.rows),
+                    right.max(r.position.col + r.size.cols),
+                )
+            })
+        };
+        let (bottom, right) = extent(&mut self.keys.iter().map(|k| k.key.region));
+        let (rows, cols) = extent(&mut tray.iter().map(|k| k.region));
+        let anchor = self.keys[i].key.region.position;
+        let origin = tuinix::TerminalPosition::row_col(
+            anchor.row.min(bottom.saturating_sub(rows)),
+            anchor.col.min(right.saturating_sub(cols)),
+        );
+
+        self.tray = Some((self.keys.len(), Instant::now() + TRAY_TIMEOUT));
+        for mut key in tray {
+            key.region.position.row += origin.row;
+            key.region.position.col += origin.col;
+            self.keys.push(KeyState::new(key));
+        }
+        self.update_toggle_keys();
+        self.calculate_offset();
+    }
+
+    fn close_tray(&mut self) {
+        if let Some((start, _)) = self.tray.take() {
+            // Nothing may keep pointing at a tray key once it is removed
+            self.keys.truncate(start);
+            self.focused_key = self.focused_key.filter(|&i| i < start);
+            self.left_press_index = self.left_press_index.filter(|&i| i < start);
+            self.pending_dead_key = self.pending_dead_key.filter(|&i| i < start);
+            self.pending_chord.take_if(|p| p.index >= start);
+            self.pending_confirm.take_if(|p| p.index >= start);
+            self.held_modifier.take_if(|&mut (h, _)| h >= start);
+            if let Some(revert) = &mut self.pending_revert {
+                revert.index.take_if(|&mut i| i >= start);
+            }
+            self.update_toggle_keys();
+            self.calculate_offset();
+        }
+    }
+
+    fn close_paste_history(&mut self) {
+        if let Some(start) = self.paste_history_start.take() {
+            self.keys.truncate(start);
+            self.focused_key = self.focused_key.filter(|&i| i < start);
+            self.calculate_offset();
+        }
+    }
+
+    /// Releases the modifier or dead key at `i` (e.g. a stuck latch), leaving the other keys as they are
+    fn reset_modifier(&mut self, i: usize) {
+        let code = &self.keys[i].key.code;
+        if code.is_modifier() || matches!(code, KeyCode::Dead(_)) {
+            self.keys[i].press = KeyPressState::Neutral;
+            self.pending_dead_key.take_if(|&mut d| d == i);
+            self.held_modifier.take_if(|&mut (h, _)| h == i);
+        }
+    }
+
+    fn reset_pressed_keys(&mut self) {
+        for key in &mut self.keys {
+            if key.press == KeyPressState::Pressed {
+                key.press = KeyPressState::Neutral;
+            }
+        }
+    }
+
+    fn tmux_command(&mut self, command: &str, args: &[&str]) -> orfail::Result<()> {
+        self.tmux_client.send_command(command, args).or_fail()?;
+        Ok(())
+    }
+
+    fn handle_modifier_key_pressed(&mut self, i: usize) -> orfail::Result<()> {
+        self.reset_pressed_keys();
+
+        match self.keys[i].press {
+            KeyPressState::Neutral => {
+                self.keys[i].press = KeyPressState::OneshotActivated;
+            }
+            KeyPressState::Pressed => {
+                self.keys[i].press = KeyPressState::OneshotActivated;
+            }
+            KeyPressState::Activated => {
+                self.keys[i].press = KeyPressState::Neutral;
+            }
+            KeyPressState::OneshotActivated => {
+                self.keys[i].press = KeyPressState::Activated;
+            }
+        }
+
+        // Each newly latched modifier restarts the wait for the next key
+        if self.keys[i].press == KeyPressState::OneshotActivated {
+            self.oneshot_deadline = self.options.oneshot_timeout.map(|t| Instant::now() + t);
+        } else if !self
+            .keys
+            .iter()
+            .any(|k| k.press == KeyPressState::OneshotActivated)
+        {
+            self.oneshot_deadline = None;
+        }
+
+        Ok(())
+    }
+
+    fn handle_normal_key_pressed(
+        &mut self,
+        i: usize,
+        mouse_input: tuinix::MouseInput,
+    ) -> orfail::Result<()> {
+        // Clicking any other key cancels a pending confirmation
+        if let Some(pending) = self.pending_confirm.take()
+            && pending.index == i
+            && Instant::now() <= pending.deadline
+        {
+            self.keys[i].press = KeyPressState::Pressed;
+            self.start_press_animation(i);
+            let key = self.keys[i].key.clone();
+            self.send_key(&key, pending.modifiers).or_fail()?;
+            return Ok(());
+        }
+
+        let modifiers = self.consume_modifiers(mouse_input);
+        self.keys[i].press = KeyPressState::Pressed;
+        self.start_press_animation(i);
+
+        let key = self.keys[i].key.clone();
+        if let Some(queue) = &mut self.queue {
+            queue.push((key, modifiers));
+            return Ok(());
+        }
+        if let Some(timeout) = self.options.confirm_modified
+            && key.code.is_modifiable()
+            && (modifiers.ctrl || modifiers.alt)
+        {
+            self.pending_confirm = Some(PendingConfirm {
+                index: i,
+                modifiers,
+                deadline: Instant::now() + timeout,
+            });
+            self.notify(
+                format!("click again to send {}", modifiers.key_name(&key)),
+                timeout,
+            );
+            return Ok(());
+        }
+        self.send_key(&key, modifiers).or_fail()?;
+        Ok(())
+    }
+
+    fn handle_chord_candidate_pressed(
+        &mut self,
+        i: usize,
+        mouse_input: tuinix::MouseInput,
+    ) -> orfail::Result<()> {
+        let Some(chords) = self.active_chords().cloned() else {
+            return self.handle_normal_key_pressed(i, mouse_input);
+        };
+
+        if let Some(pending) = self.pending_chord.take() {
+            if Instant::now() <= pending.deadline
+                && let Some(output) =
+                    chords.find(&self.keys[pending.index].key.code, &self.keys[i].key.code)
+            {
+                let output = output.clone();
+                let modifiers = self.consume_modifiers(pending.mouse_input);
+                self.keys[pending.index].press = KeyPressState::Pressed;
+                self.keys[i].press = KeyPressState::Pressed;
+                self.start_press_animation(pending.index);
+                self.start_press_animation(i);
+
+                let key = Key::new(output, self.keys[i].key.region);
+                self.send_key(&key, modifiers).or_fail()?;
+                return Ok(());
+            }
+            self.handle_normal_key_pressed(pending.index, pending.mouse_input)
+                .or_fail()?;
+        }
+
+        if chords.contains(&self.keys[i].key.code) {
+            self.pending_chord = Some(PendingChord {
+                index: i,
+                mouse_input,
+                deadline: Instant::now() + chords.timeout,
+            });
+        } else {
+            self.handle_normal_key_pressed(i, mouse_input).or_fail()?;
+        }
+        Ok(())
+    }
+
+    fn flush_pending_chord(&mut self) -> orfail::Result<()> {
+        if let Some(pending) = self.pending_chord.take() {
+            self.handle_normal_key_pressed(pending.index, pending.mouse_input)
+                .or_fail()?;
+        }
+        Ok(())
+    }
+
+    fn active_chords(&self) -> Option<&Chords> {
+        self.layouts.get(&self.active_layout)?.chords.as_ref()
+    }
+
+    fn consume_modifiers(&mut self, mouse_input: tuinix::MouseInput) -> Modifiers {
+        // Clicking with a physical Ctrl/Alt held composes a one-off combination
+        // that neither uses nor consumes the on-screen modifier latches
+        if mouse_input.ctrl || mouse_input.alt {
+            self.reset_pressed_keys();
+            return Modifiers {
+                ctrl: mouse_input.ctrl,
+                alt: mouse_input.alt,
+                shift: mouse_input.shift,
+            };
+        }
+
+        for key in &mut self.keys {
+            match key.press {
+                KeyPressState::Neutral => {}
+                KeyPressState::Pressed => {
+                    key.press = KeyPressState::Neutral;
+                }
+                KeyPressState::Activated => {}
+                KeyPressState::OneshotActivated => {
+                    key.press = KeyPressState::Pressed;
+                }
+            }
+        }
+        self.oneshot_deadline = None;
+
+        // The cycle selection only applies to a single key
+        let (cycle, _) = MODIFIER_CYCLE[std::mem::take(&mut self.modifier_cycle)];
+        self.update_modifier_cycle_labels();
+
+        Modifiers {
+            ctrl: self.is_ctrl_pressed() || cycle.ctrl,
+            alt: self.is_alt_pressed() || cycle.alt,
+            shift: self.is_shift_pressed() || cycle.shift,
+        }
+    }
+
+    fn update_modifier_cycle_labels(&mut self) {
+        let (_, label) = MODIFIER_CYCLE[self.modifier_cycle];
+        for key in &mut self.keys {
+            if key.key.code == KeyCode::ModifierCycle {
+                key.label = Some(label.to_owned());
+            }
+        }
+    }
+
+    /// Shows the pane border status set by `TogglePaneBorders` keys, once known
+    fn update_pane_border_labels(&mut self) {
+        let Some(status) = self.pane_border_status else {
+            return;
+        };
+        let label = format!("Borders: {}", PANE_BORDER_STATUSES[status]);
+        for key in &mut self.keys {
+            if key.key.code == KeyCode::TogglePaneBorders {
+                key.label = Some(label.clone());
+            }
+        }
+    }
+
+    /// Shows the keys that toggle an app state as activated while the state is on
+    fn update_toggle_keys(&mut self) {
+        for key in &mut self.keys {
+            let active = match key.key.code {
+                KeyCode::Broadcast => self.broadcast,
+                KeyCode::NumberEntry => self.number_entry.is_some(),
+                KeyCode::QueueMode => self.queue.is_some(),
+                KeyCode::Tray => self.tray.is_some(),
+                _ => continue,
+            };
+            key.press = if active {
+                KeyPressState::Activated
+            } else {
+                KeyPressState::Neutral
+            };
+        }
+    }
+
+    fn send_key(&mut self, key: &Key, modifiers: Modifiers) -> orfail::Result<()> {
+        let mut code = key.code.clone();
+        let mut key_string = String::new();
+        let mut ctrl = false;
+        let mut alt = false;
+        if code.is_modifiable() {
+            if modifiers.ctrl {
+                key_string.push_str("C-");
+                ctrl = true;
+            }
+            if modifiers.alt {
+                key_string.push_str("M-");
+                alt = true;
+            }
+        }
+        if modifiers.shift {
+            code = key.shift_code.clone();
+        }
+        if self.options.swap_delete {
+            code = code.swap_delete();
+        }
+
+        if let Some(dead_index) = self.pending_dead_key.take() {
+            self.keys[dead_index].press = KeyPressState::Neutral;
+            if let KeyCode::Dead(accent) = self.keys[dead_index].key.code
+                && let KeyCode::Char(c) = code
+                && !ctrl
+                && !alt
+                && let Some(composed) = crate::compose::compose(accent, c)
+            {
+                if self
+                    .send_keys(key, &["-l", &composed.to_string()])
+                    .or_fail()?
+                    && let Some(preview) = &mut self.preview
+                {
+                    preview.on_key_sent(KeyCode::Char(composed), false, false);
+                }
+                return Ok(());
+            }
+
+            // The key cannot be accented, so send both as they are
+            self.send_pending_accent(dead_index, key).or_fail()?;
+        }
+
+        key_string.push_str(&code.to_string());
+
+        let repeat_args = key.repeat_args();
+        let mut args = repeat_args.iter().map(|a| a.as_str()).collect::<Vec<_>>();
+        args.push(&key_string);
+        if !self.send_keys(key, &args).or_fail()? {
+            return Ok(());
+        }
+
+        if let Some(preview) = &mut self.preview {
+            for _ in 0..key.repeat_count.get() {
+                preview.on_key_sent(code.clone(), ctrl, alt);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns `text` as it should be typed with `send-keys -l`
+    fn literal_text(&self, text: String) -> String {
+        if self.options.normalize_nfc {
+            crate::compose::to_nfc(&text)
+        } else {
+            text
+        }
+    }
+
+    /// Runs `send-keys` with `args` for the target of `key`, or for all other panes while broadcasting
+    ///
+    /// Returns `false` if the key could not be sent to its target pane.
+    fn send_keys(&mut self, key: &Key, args: &[&str]) -> orfail::Result<bool> {
+        if self.broadcast && key.target.is_none() {
+            let panes = self.list_target_panes().or_fail()?;
+            let mut sent = false;
+            for i in panes {
+                if i == self.pane_index {
+                    continue;
+                }
+                let target = PaneTarget::Index(i).to_string();
+                // A pane closing in the meantime should not keep the key from the others
+                sent |= self
+                    .tmux_command("send-keys", &[&["-t", &target], args].concat())
+                    .is_ok();
+            }
+            if !sent {
+                self.notify("no other panes to broadcast to", NOTIFICATION_TTL);
+                return Ok(false);
+            }
+            self.echo_sent_keys(args);
+            if let Some(keys) = args.last() {
+                self.set_title(keys);
+            }
+            return Ok(true);
+        }
+
+        let target = key.target.unwrap_or(PaneTarget::Index(self.pane_index));
+        if let Err(e) =
+            self.tmux_command("send-keys", &[&["-t", &target.to_string()], args].concat())
+        {
+            if key.target.is_some() {
+                self.notify(format!("cannot send to pane {target}"), NOTIFICATION_TTL);
+            } else {
+                self.recover_from_missing_pane(e).or_fail()?;
+            }
+            return Ok(false);
+        }
+        self.echo_sent_keys(args);
+        if let Some(keys) = args.last() {
+            self.set_title(keys);
+        }
+        Ok(true)
+    }
+
+    /// Shows the keys sent with `send-keys` `args` at `echo_target`, if any
+    fn echo_sent_keys(&mut self, args: &[&str]) {
+        let Some(echo_target) = self.options.echo_target else {
+            return;
+        };
+        let Some(keys) = args.last() else {
+            return;
+        };
+        let count = args
+            .iter()
+            .position(|&a| a == "-N")
+            .and_then(|i| args.get(i + 1))
+            .map(|count| format!(" ×{count}"))
+            .unwrap_or_default();
+        let entry = format!(
+            "[{:>7.1}s] → {keys}{count}",
+            self.started_at.elapsed().as_secs_f64()
+        );
+        match echo_target {
+            EchoTarget::Status => {
+                self.notify(entry, NOTIFICATION_TTL);
+            }
+            EchoTarget::Pane(i) => {
+                // Failing to echo should not get in the way of the keys themselves
+                let target = PaneTarget::Index(i).to_string();
+                let _ = self.tmux_command("send-keys", &["-t", &target, "-l", &entry]);
+                let _ = self.tmux_command("send-keys", &["-t", &target, "Enter"]);
+            }
+        }
+    }
+
+    /// Sets the terminal title to `title` if `title_shows_last_key` is enabled
+    ///
+    /// The escape sequence is written directly to stdout, which tuinix flushes after each frame,
+    /// so it never interleaves with a frame being drawn.
+    fn set_title(&self, title: &str) {
+        if !self.options.title_shows_last_key || self.terminal.is_none() {
+            return;
+        }
+        // The title is only cosmetic, so failing to set it is not worth stopping for
+        let mut stdout = std::io::stdout();
+        let _ = std::io::Write::write_all(&mut stdout, title_sequence(title).as_bytes());
+        let _ = std::io::Write::flush(&mut stdout);
+    }
+
+    /// Sends the accent of a dead key literally to the target of `key`
+    fn send_pending_accent(&mut self, dead_index: usize, key: &Key) -> orfail::Result<()> {
+        let KeyCode::Dead(accent) = self.keys[dead_index].key.code else {
+            return Ok(());
+        };
+        if self
+            .send_keys(key, &["-l", &accent.to_string()])
+            .or_fail()?
+            && let Some(preview) = &mut self.preview
+        {
+            preview.on_key_sent(KeyCode::Char(accent), false, false);
+        }
+        Ok(())
+    }
+
+    /// Falls back to the lowest available pane if the target pane has been closed
+    fn recover_from_missing_pane(&mut self, failure: orfail::Failure) -> orfail::Result<()> {
+        let fell_back = match self.fall_back_if_pane_missing() {
+            Ok(fell_back) => fell_back,
+            Err(e) => {
+                // A stalled tmux may respond to later commands, so this is not a reason to exit
+                self.notify(
+                    format!("cannot check pane {}: {}", self.pane_index, e.message),
+                    NOTIFICATION_TTL,
+                );
+                return Ok(());
+            }
+        };
+        if !fell_back {
+            // The pane still exists, so the failure has another cause
+            return Err(failure);
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if the target pane no longer exists
+    fn fall_back_if_pane_missing(&mut self) -> orfail::Result<bool> {
+        let panes = self.list_target_panes().or_fail()?;
+        if panes.contains(&self.pane_index) {
+            return Ok(false);
+        }
+
+        let Some(&lowest) = panes.iter().min() else {
+            self.notify(
+                format!("pane {} is gone", self.pane_index),
+                NOTIFICATION_TTL,
+            );
+            return Ok(true);
+        };
+        self.notify(
+            format!(
+                "pane {} is gone; switched to pane {lowest}",
+                self.pane_index
+            ),
+            NOTIFICATION_TTL,
+        );
+        self.pane_index = lowest;
+        Ok(true)
+    }
+
+    /// Targets the active pane of the current window, or another pane if tuke itself is active
+    fn follow_active_pane(&mut self) -> orfail::Result<()> {
+        let lines = self
+            .tmux_client
+            .query(
+                "display-message",
+                &["-p", "-t", ":", "#{pane_index} #{pane_id}"],
+            )
+            .or_fail()?;
+        let (index, id) = lines.first().or_fail()?.split_once(' ').or_fail()?;
+        if own_pane_id().as_deref() != Some(id) {
+            self.pane_index = index.parse::<usize>().or_fail()?;
+        } else if let Some(&lowest) = self.list_target_panes().or_fail()?.iter().min() {
+            self.pane_index = lowest;
+        }
+        Ok(())
+    }
+
+    /// Returns the indices of the panes in the current window, excluding tuke's own pane
+    fn list_target_panes(&mut self) -> orfail::Result<Vec<usize>> {
+        let own_pane_id = own_pane_id();
+        let lines = self
+            .tmux_client
+            .query("list-panes", &["-t", ":", "-F", "#{pane_index} #{pane_id}"])
+            .or_fail()?;
+
+        let mut panes = Vec::new();
+        for line in lines {
+            let (index, id) = line.split_once(' ').or_fail()?;
+            if own_pane_id.as_deref() == Some(id) {
+                continue;
+            }
+            panes.push(index.parse::<usize>().or_fail()?);
+        }
+        Ok(panes)
+    }
+
+    fn is_ctrl_pressed(&self) -> bool {
+        self.keys.iter().any(|k| {
+            k.key.code == KeyCode::Ctrl
+                && matches!(k.press, KeyPressState::Pressed | KeyPressState::Activated)
+        })
+    }
+
+    fn is_alt_pressed(&self) -> bool {
+        self.keys.iter().any(|k| {
+            k.key.code == KeyCode::Alt
+                && matches!(k.press, KeyPressState::Pressed | KeyPressState::Activated)
+        })
+    }
+
+    fn is_shift_pressed(&self) -> bool {
+        self.keys.iter().any(|k| {
+            k.key.code == KeyCode::Shift
+                && matches!(k.press, KeyPressState::Pressed | KeyPressState::Activated)
+        })
+    }
+
+    fn is_shift_active(&self) -> bool {
+        self.keys.iter().any(|k| {
+            k.key.code == KeyCode::Shift
+                && matches!(
+                    k.press,
+                    KeyPressState::OneshotActivated | KeyPressState::Activated
+                )
+        })
+    }
+
+    fn calculate_offset(&mut self) {
+        let terminal_size = self.terminal_size;
+        let mut actual_frame_size = tuinix::TerminalSize::default();
+
+        for key_state in &self.keys {
+            actual_frame_size.rows = actual_frame_size
+                .rows
+                .max(key_state.key.region.position.row + key_state.key.region.size.rows);
+            actual_frame_size.cols = actual_frame_size
+                .cols
+                .max(key_state.key.region.position.col + key_state.key.region.size.cols);
+        }
+
+        // Calculate centering offset
+        let offset_row = keyboard_offset_row(
+            actual_frame_size.rows,
+            terminal_size.rows,
+            self.options.status_overlap,
+        );
+        let offset_col = (terminal_size.cols.saturating_sub(actual_frame_size.cols)) / 2;
+
+        self.offset = tuinix::TerminalPosition::row_col(offset_row, offset_col);
+        self.h_offset = self.h_offset.min(self.max_h_offset());
+
+        let overlapping = keys_in_status_rows(
+            self.keys.iter().map(|k| k.key.region),
+            offset_row,
+            terminal_size.rows,
+        );
+        if self.options.status_overlap == StatusOverlap::Warn && overlapping > 0 {
+            self.notify(
+                format!(
+                    "{overlapping} key(s) overlap the status rows \
+                 (enlarge the pane or use --status-overlap avoid)"
+                ),
+                NOTIFICATION_TTL,
+            );
+        }
+    }
+
+    /// Draws layout coordinates in the margins above and to the left of the keyboard
+    ///
+    /// Nothing is drawn over the keys, so the rulers are omitted if there is no margin.
+    fn draw_debug_grid(&self, frame: &mut tuinix::TerminalFrame) -> orfail::Result<()> {
+        let terminal_size = self.terminal_size;
+        let (rows, cols) = self
+            .keys
+            .iter()
+            .map(|k| k.key.region)
+            .chain(self.preview.iter().map(|p| p.region))
+            .fold((0, 0), |(rows, cols), r| {
+                (
+                    rows.max(r.position.row + r.size.rows),
+                    cols.max(r.position.col + r.size.cols),
+                )
+            });
+        let style = tuinix::TerminalStyle::new().dim();
+
+        if self.offset.row > 0 {
+            // Column numbers every 10 columns, with a tick every 5
+            let visible_cols = cols
+                .saturating_sub(self.h_offset)
+                .min(terminal_size.cols.saturating_sub(self.offset.col));
+            let mut ruler = vec![' '; visible_cols];
+            let mut i = 0;
+            while i < visible_cols {
+                let col = i + self.h_offset;
+                if col.is_multiple_of(10) {
+                    for c in col.to_string().chars() {
+                        if let Some(cell) = ruler.get_mut(i) {
+                            *cell = c;
+                        }
+                        i += 1;
+                    }
+                    continue;
+                }
+                if col.is_multiple_of(5) {
+                    ruler[i] = '.';
+                }
+                i += 1;
+            }
+            let ruler = ruler.into_iter().collect::<String>();
+            let ruler_frame = text_frame(&ruler, style).or_fail()?;
+            let position = tuinix::TerminalPosition::row_col(self.offset.row - 1, self.offset.col);
+            frame.draw(position, &ruler_frame);
+        }
+
+        let number_width = rows.saturating_sub(1).to_string().len() + 1;
+        if self.offset.col >= number_width {
+            for row in 0..rows.min(terminal_size.rows.saturating_sub(self.offset.row)) {
+                let text = format!("{row:>width$}", width = number_width - 1);
+                let number_frame = text_frame(&text, style).or_fail()?;
+                let position = tuinix::TerminalPosition::row_col(
+                    self.offset.row + row,
+                    self.offset.col - number_width,
+                );
+                frame.draw(position, &number_frame);
+            }
+        }
+        Ok(())
+    }
+
+    fn max_h_offset(&self) -> usize {
+        let width = self
+            .keys
+            .iter()
+            .map(|k| k.key.region)
+            .chain(self.preview.iter().map(|p| p.region))
+            .map(|r| r.position.col + r.size.cols)
+            .max()
+            .unwrap_or_default();
+        width.saturating_sub(self.terminal_size.cols)
+    }
+
+    fn scroll_left(&mut self) {
+        self.h_offset = self.h_offset.saturating_sub(H_SCROLL_STEP);
+    }
+
+    fn scroll_right(&mut self) {
+        self.h_offset = (self.h_offset + H_SCROLL_STEP).min(self.max_h_offset());
+    }
+
+    /// Returns `false` for regions scrolled (even partially) out of the left edge
+    fn is_visible(&self, region: tuinix::TerminalRegion) -> bool {
+        region.position.col >= self.h_offset
+    }
+
+    /// Converts a layout position into a position within the scrolled view
+    fn scrolled(&self, position: tuinix::TerminalPosition) -> tuinix::TerminalPosition {
+        tuinix::TerminalPosition::row_col(position.row, position.col - self.h_offset)
+    }
+
+    fn render_options(&self) -> RenderOptions {
+        let layout = self.layouts.get(&self.active_layout);
+        let layout_border_style = layout.and_then(|l| l.border_style);
+        let border_style = if self.options.ascii_border {
+            BorderStyle::Ascii
+        } else {
+            layout_border_style.unwrap_or_else(BorderStyle::detect)
+        };
+        RenderOptions {
+            border_style,
+            label_overflow: layout.and_then(|l| l.label_overflow).unwrap_or_default(),
+            tint: (self.mode == AppMode::CopyMode).then(|| self.copy_mode_color()),
+            shared_borders: layout.is_some_and(|l| l.shared_borders),
+            glyph_labels: self.options.glyph_labels,
+            target_markers: self.options.show_targets,
+        }
+    }
+
+    fn copy_mode_color(&self) -> tuinix::TerminalColor {
+        self.layouts
+            .get(&self.active_layout)
+            .and_then(|l| l.copy_mode_color)
+            .unwrap_or(DEFAULT_COPY_MODE_COLOR)
+    }
+
+    /// Repaints every cell of the screen, recovering from output written over the keyboard
+    ///
+    /// The terminal only redraws the cells that changed since the last frame, so an empty frame
+    /// is drawn first to make the next one differ in size and be drawn in full.
+    pub fn force_redraw(&mut self) -> orfail::Result<()> {
+        if let Some(terminal) = &mut self.terminal {
+            let empty: tuinix::TerminalFrame =
+                tuinix::TerminalFrame::new(tuinix::TerminalSize::default());
+            terminal.draw(empty).or_fail()?;
+        }
+        self.render().or_fail()
+    }
+
+    /// Renders now, or later if the previous frame was rendered less than `min_frame_interval` ago
+    fn request_render(&mut self) -> orfail::Result<()> {
+        if self.frame_limiter.request(Instant::now()) {
+            self.render().or_fail()?;
+        }
+        Ok(())
+    }
+
+    fn render(&mut self) -> orfail::Result<()> {
+        let terminal_size = self.terminal_size;
+
+        if self.options.auto_resize {
+            let required_rows = self
+                .keys
+                .iter()
+                .map(|k| k.key.region)
+                .chain(self.preview.iter().map(|p| p.region))
+                .map(|r| r.bottom_left().row + 1)
+                .max()
+                .unwrap_or_default()
+                + match self.options.status_overlap {
+                    StatusOverlap::Warn => 0,
+                    StatusOverlap::Avoid => STATUS_ROWS,
+                };
+            if terminal_size.rows != required_rows {
+                self.tmux_command(
+                    "resize-pane",
+                    &["-t", ":0.1", "-y", &required_rows.to_string()],
+                )
+                .or_fail()?;
+            }
+        }
+
+        let mut frame: tuinix::TerminalFrame = tuinix::TerminalFrame::new(terminal_size);
+        let shift = self.is_shift_active();
+        let render_options = self.render_options();
+
+        let tray_start = self.tray.map_or(self.keys.len(), |(start, _)| start);
+        let tray_keys = &self.keys[tray_start..];
+        for (i, key_state) in self.keys.iter().enumerate() {
+            if !self.is_shown(&key_state.key) {
+                continue;
+            }
+            if i < tray_start
+                && tray_keys
+                    .iter()
+                    .any(|t| regions_overlap(t.key.region, key_state.key.region))
+            {
+                // Partly covered keys would show through the gaps between the tray keys
+                continue;
+            }
+            let focused = self.focused_key == Some(i);
+            let key_frame = key_state
+                .to_frame(shift, focused, &render_options)
+                .or_fail()?;
+            frame.draw(self.scrolled(key_state.key.region.position), &key_frame);
+        }
+
+        if let Some(preview) = &self.preview
+            && self.is_visible(preview.region)
+        {
+            let preview_frame = preview.to_frame().or_fail()?;
+            frame.draw(self.scrolled(preview.region.position), &preview_frame);
+        }
+
+        let mut centered_frame: tuinix::TerminalFrame = tuinix::TerminalFrame::new(terminal_size);
+        centered_frame.draw(self.offset, &frame);
+        if self.options.debug_grid {
+            self.draw_debug_grid(&mut centered_frame).or_fail()?;
+        }
+
+        if self.layouts.entries.len() > 1 {
+            let text = format!("[{}]", self.active_layout);
+            let style = tuinix::TerminalStyle::new().dim();
+            let indicator_frame = text_frame(&text, style).or_fail()?;
+            let position = Corner::BottomRight.position(terminal_size, indicator_frame.size());
+            centered_frame.draw(position, &indicator_frame);
+        }
+
+        // Stacked upwards from the bottom left corner, so that none hides another
+        for (i, (text, style)) in self.status_lines().into_iter().rev().enumerate() {
+            let line_frame = text_frame(&text, style).or_fail()?;
+            let mut position = Corner::BottomLeft.position(terminal_size, line_frame.size());
+            position.row = position.row.saturating_sub(i);
+            centered_frame.draw(position, &line_frame);
+        }
+
+        let mut indicators = Vec::new();
+        if self.mode == AppMode::CopyMode {
+            indicators.push(("-- COPY MODE --", self.copy_mode_color()));
+        }
+        if self.broadcast {
+            indicators.push(("-- BROADCAST --", tuinix::TerminalColor::RED));
+        }
+        for (row, (text, color)) in indicators.into_iter().enumerate() {
+            let style = tuinix::TerminalStyle::new().bold().fg_color(color);
+            let mode_frame = text_frame(text, style).or_fail()?;
+            let col = terminal_size.cols.saturating_sub(mode_frame.size().cols) / 2;
+            centered_frame.draw(tuinix::TerminalPosition::row_col(row, col), &mode_frame);
+        }
+
+        if let Some(region) = self.pane_map_region()
+            && let Some(map) = &self.pane_map
+        {
+            let map_frame = map
+                .to_frame(self.pane_index, own_pane_id().as_deref())
+                .or_fail()?;
+            centered_frame.draw(region.position, &map_frame);
+        }
+
+        if let Some(corner) = self.options.pane_badge {
+            let text = format!("[P{}]", self.pane_index);
+            let style = tuinix::TerminalStyle::new().bold().reverse();
+            let badge_frame = text_frame(&text, style).or_fail()?;
+            let position = corner.position(terminal_size, badge_frame.size());
+            centered_frame.draw(position, &badge_frame);
+        }
+        if let Some(terminal) = &mut self.terminal {
+            terminal.draw(centered_frame).or_fail()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for App {
+    fn drop(&mut self) {
+        // Reset the title and disable mouse reporting explicitly, both on normal exit and when
+        // an error unwinds the app, rather than relying on the drop order of the terminal
+        if let Some(cleanup) = &mut self.cleanup {
+            let _ = cleanup.run();
+        }
+
+        // Restore the terminal first so that the summary is not written to the alternate screen
+        self.terminal = None;
+        if let Some(counts) = self.press_counts.take()
+            && let Some(path) = self.options.stats_path.take()
+            && let Err(e) = write_press_counts(&path, counts)
+        {
+            eprintln!("Error: {}", e.message);
+        }
+    }
+}
+
+/// Reads the text typed by a `Snippet` key
+fn read_snippet(path: &Path) -> orfail::Result<String> {
+    let file = std::fs::File::open(path)
+        .or_fail_with(|e| format!("cannot read snippet {}: {e}", path.display()))?;
+    let mut text = String::new();
+    std::io::Read::read_to_string(
+        &mut std::io::Read::take(file, SNIPPET_MAX_BYTES + 1),
+        &mut text,
+    )
+    .or_fail_with(|e| format!("cannot read snippet {}: {e}", path.display()))?;
+    (text.len() as u64 <= SNIPPET_MAX_BYTES).or_fail_with(|()| {
+        format!(
+            "snippet {} is larger than {} KiB",
+            path.display(),
+            SNIPPET_MAX_BYTES / 1024
+        )
+    })?;
+    Ok(text)
+}
+
+/// Writes the `capture-pane` output `lines` to `path`, returning the number of lines
+fn write_capture(path: &Path, lines: &[String]) -> orfail::Result<usize> {
+    let mut text = String::new();
+    for line in lines {
+        text.push_str(line);
+        text.push('\n');
+    }
+    std::fs::write(path, text)
+        .or_fail_with(|e| format!("cannot save capture to {}: {e}", path.display()))?;
+    Ok(lines.len())
+}
+
+/// Flips or transposes the layouts as requested by `options`
+fn arrange_layouts(layouts: &mut Layouts, options: &AppOptions) {
+    for layout in &mut layouts.entries {
+        if options.mirror {
+            layout.mirror();
+        }
+        if options.orientation == Orientation::Portrait {
+            layout.transpose();
+        }
+    }
+}
+
+/// Returns the clients to show the pane numbers on, from `list-clients` output
+///
+/// These are the clients that are not in control mode, or only `only` if given.
+fn display_panes_clients<'a>(lines: &'a [String], only: Option<&str>) -> Vec<&'a str> {
+    lines
+        .iter()
+        .filter_map(|line| match line.split_once(' ') {
+            Some(("0", name)) if only.is_none_or(|only| only == name) => Some(name),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns when to wake up for the earliest of `deadlines`
+///
+/// Deadlines up to `slack` after the earliest one are waited for too, so that timers falling due
+/// close together share a single wakeup.
+fn wakeup_time(deadlines: impl IntoIterator<Item = Instant>, slack: Duration) -> Option<Instant> {
+    let deadlines = deadlines.into_iter().collect::<Vec<_>>();
+    let earliest = *deadlines.iter().min()?;
+    deadlines
+        .into_iter()
+        .filter(|&d| d <= earliest + slack)
+        .max()
+}
+
+/// Number of terminal rows where overlays are drawn: the top one (badges and mode indicators)
+/// and the bottom one (status messages and the layout name)
+const STATUS_ROWS: usize = 2;
+
+/// Returns the terminal row at which the top of a keyboard `keyboard_rows` tall is drawn
+fn keyboard_offset_row(
+    keyboard_rows: usize,
+    terminal_rows: usize,
+    overlap: StatusOverlap,
+) -> usize {
+    let spare_rows = terminal_rows.saturating_sub(keyboard_rows);
+    match overlap {
+        StatusOverlap::Warn => spare_rows / 2,
+        // With a single spare row, keep the top row free as it shows the badge and the mode
+        StatusOverlap::Avoid => spare_rows.div_ceil(2),
+    }
+}
+
+/// Returns the number of keys drawn in the top or bottom row of the terminal
+fn keys_in_status_rows(
+    regions: impl IntoIterator<Item = tuinix::TerminalRegion>,
+    offset_row: usize,
+    terminal_rows: usize,
+) -> usize {
+    let last_row = terminal_rows.saturating_sub(1);
+    regions
+        .into_iter()
+        .filter(|r| {
+            let top = offset_row + r.position.row;
+            let bottom = top + r.size.rows.saturating_sub(1);
+            top == 0 || (top..=bottom).contains(&last_row)
+        })
+        .count()
+}
+
+fn text_frame(text: &str, style: tuinix::TerminalStyle) -> orfail::Result<tuinix::TerminalFrame> {
+    let size = tuinix::TerminalSize::rows_cols(1, text.chars().count());
+    let mut frame: tuinix::TerminalFrame = tuinix::TerminalFrame::new(size);
+    let reset = tuinix::TerminalStyle::RESET;
+    write!(frame, "{style}{text}{reset}").or_fail()?;
+    Ok(frame)
+}
+
+/// Writes the number of presses of each key code, most pressed first
+fn write_press_counts(path: &Path, counts: HashMap<KeyCode, usize>) -> orfail::Result<()> {
+    let mut counts = counts.into_iter().collect::<Vec<_>>();
+    counts.sort_by(|a, b| {
+        b.1.cmp(&a.1)
+            .then_with(|| a.0.to_string().cmp(&b.0.to_string()))
+    });
+
+    let mut summary = String::new();
+    for (code, count) in counts {
+        writeln!(summary, "{count:>8} {:?}", code.to_string()).or_fail()?;
+    }
+    if path == Path::new("-") {
+        print!("{summary}");
+    } else {
+        std::fs::write(path, summary)
+            .or_fail_with(|e| format!("failed to write {}: {e}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Extends the panic hook of [`tuinix::Terminal`] to also restore what it does not
+///
+/// The terminal mode and screen are restored by tuinix, but mouse reporting and the cursor are not,
+/// which would leave the shell garbled by mouse escape sequences after a panic.
+/// The hook is installed only once, however many apps are created.
+fn install_panic_hook() {
+    static INSTALL: std::sync::Once = std::sync::Once::new();
+    INSTALL.call_once(|| {
+        let previous_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |panic_info| {
+            let _ = write_panic_cleanup(&mut std::io::stdout());
+            previous_hook(panic_info);
+        }));
+    });
+}
+
+/// Writes the escape sequences that disable mouse reporting and show the cursor again
+fn write_panic_cleanup(output: &mut impl std::io::Write) -> std::io::Result<()> {
+    output.write_all(MOUSE_OFF)?;
+    output.write_all(b"\x1b[?25h")?;
+    output.flush()
+}
+
+/// Returns the OSC sequence that sets the terminal title to `title`
+///
+/// Control characters (e.g. a literally sent ESC) would end the sequence early, so they are dropped.
+fn title_sequence(title: &str) -> String {
+    let title = title
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect::<String>();
+    format!("\x1b]2;{title}\x07")
+}
+
+/// Returns the ID of the pane that tuke itself is running in
+fn own_pane_id() -> Option<String> {
+    std::env::var("TMUX_PANE").ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn region(row: usize, rows: usize) -> tuinix::TerminalRegion {
+        tuinix::TerminalRegion {
+            position: tuinix::TerminalPosition::row(row),
+            size: tuinix::TerminalSize::rows_cols(rows, 3),
+        }
+    }
+
+    #[test]
+    fn keys_in_reserved_rows_are_detected() {
+        // A keyboard of two 3-row keys in a 10-row terminal, centered at row 2
+        let keys = [region(0, 3), region(3, 3)];
+        assert_eq!(keyboard_offset_row(6, 10, StatusOverlap::Warn), 2);
+        assert_eq!(keys_in_status_rows(keys, 2, 10), 0);
+
+        // A key placed in the reserved top row
+        assert_eq!(keys_in_status_rows([region(0, 3)], 0, 10), 1);
+
+        // A key reaching the reserved bottom row
+        assert_eq!(keys_in_status_rows(keys, 4, 10), 1);
+    }
+
+    #[test]
+    fn avoid_keeps_the_top_row_free() {
+        // One spare row: centering puts the keyboard on the top row
+        assert_eq!(keyboard_offset_row(9, 10, StatusOverlap::Warn), 0);
+        assert_eq!(keyboard_offset_row(9, 10, StatusOverlap::Avoid), 1);
+        assert_eq!(keys_in_status_rows([region(0, 9)], 1, 10), 1);
+
+        // Enough room: both modes center the keyboard
+        assert_eq!(keyboard_offset_row(6, 10, StatusOverlap::Avoid), 2);
+        assert_eq!(keyboard_offset_row(12, 10, StatusOverlap::Avoid), 0);
+    }
+
+    /// Backend that records the commands sent to tmux instead of running them
+    #[derive(Debug, Default)]
+    struct MockTmux {
+        commands: std::rc::Rc<std::cell::RefCell<Vec<String>>>,
+
+        /// Output of the queries with matching command lines, or `None` to make them fail
+        replies: std::rc::Rc<std::cell::RefCell<HashMap<String, Option<Vec<String>>>>>,
+
+        /// Notifications taken by the next [`TmuxBackend::take_notifications()`] call
+        notifications: std::rc::Rc<std::cell::RefCell<Vec<Notification>>>,
+    }
+
+    impl TmuxBackend for MockTmux {
+        fn query(&mut self, command: &str, args: &[&str]) -> orfail::Result<Vec<String>> {
+            let mut line = command.to_owned();
+            for arg in args {
+                line.push(' ');
+                line.push_str(arg);
+            }
+            let reply = self.replies.borrow().get(&line).cloned();
+            self.commands.borrow_mut().push(line);
+            match reply {
+                None => Ok(Vec::new()),
+                Some(reply) => reply.or_fail_with(|()| "mock failure".to_owned()),
+            }
+        }
+
+        fn fd(&self) -> std::os::fd::RawFd {
+            -1
+        }
+
+        fn has_pending_notifications(&self) -> bool {
+            !self.notifications.borrow().is_empty()
+        }
+
+        fn take_notifications(&mut self) -> orfail::Result<Vec<Notification>> {
+            Ok(self.notifications.take())
+        }
+    }
+
+    fn dry_run_options() -> AppOptions {
+        AppOptions {
+            cursor_refresh_interval: Duration::from_secs(1),
+            auto_resize: false,
+            tmux_path: PathBuf::from("tmux"),
+            tmux_socket: TmuxSocket::Default,
+            tmux_session: String::new(),
+            tmux_client: None,
+            tmux_timeout: Duration::from_secs(1),
+            min_frame_interval: Duration::ZERO,
+            timer_slack: Duration::ZERO,
+            pane_badge: None,
+            pane_map: None,
+            status_overlap: StatusOverlap::Warn,
+            empty_click_action: EmptyClickAction::None,
+            oneshot_timeout: None,
+            confirm_modified: None,
+            press_animation: false,
+            ascii_border: false,
+            no_mouse: false,
+            normalize_nfc: false,
+            queue_keys: false,
+            drag_to_cancel: false,
+            mirror: false,
+            swap_delete: false,
+            orientation: Orientation::Landscape,
+            glyph_labels: false,
+            show_targets: false,
+            stats_path: None,
+            debug_grid: false,
+            allow_broadcast: false,
+            on_press_command: None,
+            echo_target: None,
+            title_shows_last_key: false,
+        }
+    }
+
+    /// Makes an app for a 40x120 terminal and returns it with the commands it sends to tmux
+    fn dry_run_app(
+        layouts: Layouts,
+        options: AppOptions,
+    ) -> orfail::Result<(App, std::rc::Rc<std::cell::RefCell<Vec<String>>>)> {
+        dry_run_app_with(MockTmux::default(), layouts, options)
+    }
+
+    /// Like [`dry_run_app`], but with a `tmux` whose replies are already set up
+    fn dry_run_app_with(
+        tmux: MockTmux,
+        layouts: Layouts,
+        options: AppOptions,
+    ) -> orfail::Result<(App, std::rc::Rc<std::cell::RefCell<Vec<String>>>)> {
+        let commands = tmux.commands.clone();
+        let app = App::dry_run(
+            tuinix::TerminalSize::rows_cols(40, 120),
+            layouts,
+            tmux,
+            options,
+        )?;
+        Ok((app, commands))
+    }
+
+    /// Presses and releases the mouse button at the center of the first key with `token`
+    fn click(app: &mut App, token: &str) -> orfail::Result<()> {
+        let code = KeyCode::from_token(token).or_fail()?;
+        let i = app
+            .keys
+            .iter()
+            .position(|k| k.key.code == code)
+            .or_fail_with(|()| format!("no {token:?} key"))?;
+        click_key(app, i)
+    }
+
+    /// Presses and releases the mouse button at the center of the `i`-th key
+    fn click_key(app: &mut App, i: usize) -> orfail::Result<()> {
+        drag_key(app, i, i)
+    }
+
+    /// Presses the mouse button over the `from`-th key and releases it over the `to`-th key
+    fn drag_key(app: &mut App, from: usize, to: usize) -> orfail::Result<()> {
+        mouse_event(app, tuinix::MouseEvent::LeftPress, from)?;
+        mouse_event(app, tuinix::MouseEvent::LeftRelease, to)
+    }
+
+    /// Sends a mouse `event` at the center of the key at `i`
+    fn mouse_event(app: &mut App, event: tuinix::MouseEvent, i: usize) -> orfail::Result<()> {
+        let region = app.keys[i].key.region;
+        let position = tuinix::TerminalPosition::row_col(
+            app.offset.row + region.position.row + region.size.rows / 2,
+            app.offset.col + region.position.col + region.size.cols / 2 - app.h_offset,
+        );
+        app.handle_mouse_input(tuinix::MouseInput {
+            event,
+            position,
+            ctrl: false,
+            alt: false,
+            shift: false,
+        })
+    }
+
+    /// Clicks the keys with the given tokens on the default layout and returns the commands sent
+    fn dry_run(tokens: &[&str]) -> orfail::Result<Vec<String>> {
+        dry_run_with(dry_run_options(), tokens)
+    }
+
+    fn dry_run_with(options: AppOptions, tokens: &[&str]) -> orfail::Result<Vec<String>> {
+        let (mut app, commands) = dry_run_app(Layouts::embedded()?, options)?;
+        for token in tokens {
+            click(&mut app, token)?;
+        }
+        Ok(commands.take())
+    }
+
+    #[test]
+    fn dry_run_plain_letter() -> orfail::Result<()> {
+        assert_eq!(dry_run(&["a"])?, ["send-keys -t :.0 a"]);
+        Ok(())
+    }
+
+    #[test]
+    fn dry_run_oneshot_shift() -> orfail::Result<()> {
+        // The one-shot Shift only applies to the next key
+        assert_eq!(
+            dry_run(&["S-", "a", "a"])?,
+            ["send-keys -t :.0 A", "send-keys -t :.0 a"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dry_run_locked_ctrl() -> orfail::Result<()> {
+        // Clicking Ctrl twice locks it until it is clicked again
+        assert_eq!(
+            dry_run(&["C-", "C-", "a", "a", "C-", "a"])?,
+            [
+                "send-keys -t :.0 C-a",
+                "send-keys -t :.0 C-a",
+                "send-keys -t :.0 a"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dry_run_special_key() -> orfail::Result<()> {
+        assert_eq!(dry_run(&["Enter"])?, ["send-keys -t :.0 Enter"]);
+
+        // Ctrl is not applied to Enter, but still consumed by it
+        assert_eq!(
+            dry_run(&["C-", "Enter", "a"])?,
+            ["send-keys -t :.0 Enter", "send-keys -t :.0 a"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dry_run_portrait() -> orfail::Result<()> {
+        let options = AppOptions {
+            orientation: Orientation::Portrait,
+            ..dry_run_options()
+        };
+        assert_eq!(
+            dry_run_with(options, &["S-", "a", "Enter"])?,
+            ["send-keys -t :.0 A", "send-keys -t :.0 Enter"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn conditional_keys_follow_the_app_state() -> orfail::Result<()> {
+        let layouts: Layouts = crate::jsonc::load_str(
+            "when.jsonc",
+            r#"[{"key": "a"}, {"key": "q", "when": "copy_mode"},
+                {"key": "y", "when": "has_selection"}, {"key": "b", "when": "pane:1"}]"#,
+        )
+        .or_fail()?;
+        let (mut app, commands) = dry_run_app(layouts, dry_run_options())?;
+        let sent = |app: &mut App, token| -> orfail::Result<bool> {
+            click(app, token)?;
+            Ok(!commands.take().is_empty())
+        };
+
+        assert!(sent(&mut app, "a")?);
+        assert!(!sent(&mut app, "q")?);
+        assert!(!sent(&mut app, "y")?);
+        assert!(!sent(&mut app, "b")?);
+
+        app.mode = AppMode::CopyMode;
+        assert!(sent(&mut app, "q")?);
+        assert!(!sent(&mut app, "y")?);
+        app.has_selection = true;
+        assert!(sent(&mut app, "y")?);
+
+        app.mode = AppMode::Normal;
+        app.has_selection = false;
+        assert!(!sent(&mut app, "q")?);
+        app.pane_index = 1;
+        assert!(sent(&mut app, "b")?);
+        Ok(())
+    }
+
+    #[test]
+    fn redraw_key_sends_nothing() -> orfail::Result<()> {
+        assert!(dry_run(&["Redraw"])?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn pane_numbers_are_shown_on_the_chosen_client() {
+        let lines = ["0 /dev/pts/1", "1 client-42", "0 /dev/pts/3"].map(|s| s.to_owned());
+        assert_eq!(
+            display_panes_clients(&lines, None),
+            ["/dev/pts/1", "/dev/pts/3"]
+        );
+        assert_eq!(
+            display_panes_clients(&lines, Some("/dev/pts/3")),
+            ["/dev/pts/3"]
+        );
+        assert!(display_panes_clients(&lines, Some("client-42")).is_empty());
+    }
+
+    #[test]
+    fn unused_oneshot_modifier_clears_after_timeout() -> orfail::Result<()> {
+        let options = AppOptions {
+            oneshot_timeout: Some(Duration::from_secs(2)),
+            ..dry_run_options()
+        };
+        let (mut app, commands) = dry_run_app(Layouts::embedded()?, options)?;
+        let shift = app
+            .keys
+            .iter()
+            .position(|k| k.key.code == KeyCode::Shift)
+            .or_fail()?;
+
+        let before = Instant::now();
+        click(&mut app, "S-")?;
+        let deadline = app.oneshot_deadline.or_fail()?;
+        assert!(deadline >= before + Duration::from_secs(2));
+        assert!(deadline <= Instant::now() + Duration::from_secs(2));
+        assert_eq!(app.next_deadline(), Some(deadline));
+
+        // Not yet due
+        app.handle_timers()?;
+        assert_eq!(app.keys[shift].press, KeyPressState::OneshotActivated);
+
+        app.oneshot_deadline = Some(Instant::now());
+        app.handle_timers()?;
+        assert_eq!(app.keys[shift].press, KeyPressState::Neutral);
+        assert_eq!(app.oneshot_deadline, None);
+        click(&mut app, "a")?;
+        assert_eq!(commands.take(), ["send-keys -t :.0 a"]);
+        Ok(())
+    }
+
+    #[test]
+    fn using_a_oneshot_modifier_stops_its_timeout() -> orfail::Result<()> {
+        let options = AppOptions {
+            oneshot_timeout: Some(Duration::from_secs(2)),
+            ..dry_run_options()
+        };
+        let (mut app, commands) = dry_run_app(Layouts::embedded()?, options)?;
+        click(&mut app, "S-")?;
+        click(&mut app, "a")?;
+        assert_eq!(app.oneshot_deadline, None);
+        assert_eq!(commands.take(), ["send-keys -t :.0 A"]);
+
+        // Locking the modifier stops the timeout too
+        click(&mut app, "C-")?;
+        assert!(app.oneshot_deadline.is_some());
+        click(&mut app, "C-")?;
+        assert_eq!(app.oneshot_deadline, None);
+        Ok(())
+    }
+
+    #[test]
+    fn wakeup_time_combines_timers() {
+        let t0 = Instant::now();
+        let ms = Duration::from_millis;
+        assert_eq!(wakeup_time([], ms(100)), None);
+        assert_eq!(
+            wakeup_time([t0 + ms(80)], Duration::ZERO),
+            Some(t0 + ms(80))
+        );
+
+        // Without slack, the earliest timer wins regardless of order
+        let deadlines = [t0 + ms(800), t0 + ms(80), t0 + ms(120)];
+        assert_eq!(wakeup_time(deadlines, Duration::ZERO), Some(t0 + ms(80)));
+
+        // Timers within the slack of the earliest one are waited for together
+        assert_eq!(wakeup_time(deadlines, ms(50)), Some(t0 + ms(120)));
+        assert_eq!(wakeup_time(deadlines, ms(30)), Some(t0 + ms(80)));
+        assert_eq!(wakeup_time(deadlines, ms(1000)), Some(t0 + ms(800)));
+    }
+
+    #[test]
+    fn idle_app_waits_indefinitely() -> orfail::Result<()> {
+        let (mut app, _) = dry_run_app(Layouts::embedded()?, dry_run_options())?;
+        let now = Instant::now();
+        assert_eq!(app.next_timeout(now), None);
+
+        app.pane_number_deadline = Some(now + Duration::from_secs(3));
+        app.animation_deadline = Some(now + Duration::from_millis(80));
+        assert_eq!(app.next_timeout(now), Some(Duration::from_millis(80)));
+
+        // Overdue timers are handled right away
+        assert_eq!(
+            app.next_timeout(now + Duration::from_secs(1)),
+            Some(Duration::ZERO)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn backspace_and_delete_send_their_own_keys() -> orfail::Result<()> {
+        assert_eq!(
+            dry_run(&["BSpace", "Delete", "S-", "BSpace", "S-", "Delete"])?,
+            [
+                "send-keys -t :.0 BSpace",
+                "send-keys -t :.0 Delete",
+                "send-keys -t :.0 BSpace",
+                "send-keys -t :.0 Delete"
+            ]
+        );
+
+        let options = AppOptions {
+            swap_delete: true,
+            ..dry_run_options()
+        };
+        assert_eq!(
+            dry_run_with(options, &["BSpace", "Delete"])?,
+            ["send-keys -t :.0 Delete", "send-keys -t :.0 BSpace"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn picker_loads_the_chosen_layout() -> orfail::Result<()> {
+        let dir = TempDir::new("app-picker")?;
+        std::fs::write(dir.join("arrows.jsonc"), r#"[{"key": "Up"}]"#).or_fail()?;
+        std::fs::write(dir.join("broken.jsonc"), "[{").or_fail()?;
+        std::fs::write(dir.join("letters.jsonc"), r#"[{"key": "x"}]"#).or_fail()?;
+        let paths = Layouts::find_files(&dir)?;
+        let (mut app, commands) = dry_run_app(Layouts::picker(&paths), dry_run_options())?;
+        assert_eq!(app.keys.len(), 3);
+
+        // A broken file keeps the picker open and shows why
+        click_key(&mut app, 1)?;
+        assert_eq!(app.keys.len(), 3);
+        assert!(app.notification.is_some());
+
+        click_key(&mut app, 2)?;
+        assert_eq!(app.active_layout, crate::layout::DEFAULT_LAYOUT_NAME);
+        click(&mut app, "x")?;
+        assert_eq!(commands.take(), ["send-keys -t :.0 x"]);
+        Ok(())
+    }
+
+    #[test]
+    fn title_sequence_drops_control_characters() {
+        assert_eq!(title_sequence("C-a"), "\x1b]2;C-a\x07");
+        assert_eq!(title_sequence("\x1b[A\x07"), "\x1b]2;[A\x07");
+        assert_eq!(title_sequence(""), "\x1b]2;\x07");
+    }
+
+    #[test]
+    fn momentary_key_reverts_after_its_timeout() -> orfail::Result<()> {
+        let layouts: Layouts = crate::jsonc::load_str(
+            "revert.jsonc",
+            r#"[{"key": "ScrollUp", "revert": {"key": "q", "after_ms": 1500}}, {"key": "a"}]"#,
+        )
+        .or_fail()?;
+        let (mut app, commands) = dry_run_app(layouts, dry_run_options())?;
+
+        let before = Instant::now();
+        click_key(&mut app, 0)?;
+        let sent = commands.take();
+        assert!(sent.contains(&"send-keys -X -t :.0 halfpage-up".to_owned()));
+        assert_eq!(app.keys[0].press, KeyPressState::Pressed);
+        let deadline = app.pending_revert.as_ref().or_fail()?.deadline;
+        assert!(deadline >= before + Duration::from_millis(1500));
+        assert_eq!(app.next_deadline(), Some(deadline));
+
+        // Not yet due
+        app.handle_timers()?;
+        assert!(!commands.take().contains(&"send-keys -t :.0 q".to_owned()));
+        assert_eq!(app.keys[0].press, KeyPressState::Pressed);
+
+        app.pending_revert.as_mut().or_fail()?.deadline = Instant::now();
+        app.handle_timers()?;
+        assert_eq!(commands.take()[0], "send-keys -t :.0 q");
+        assert_eq!(app.keys[0].press, KeyPressState::Neutral);
+        assert!(app.pending_revert.is_none());
+
+        // The revert is sent only once
+        app.handle_timers()?;
+        assert!(!commands.take().contains(&"send-keys -t :.0 q".to_owned()));
+        Ok(())
+    }
+
+    #[test]
+    fn snippet_key_types_the_file_contents() -> orfail::Result<()> {
+        let dir = TempDir::new("snippet")?;
+        let path = dir.join("hello.txt");
+        let large_path = dir.join("large.txt");
+        std::fs::write(&large_path, "x".repeat(SNIPPET_MAX_BYTES as usize + 1)).or_fail()?;
+        let layout = format!(
+            r#"[{{"key": "Snippet", "path": {:?}}}, {{"key": "Snippet", "path": {:?}}}]"#,
+            path.display().to_string(),
+            large_path.display().to_string()
+        );
+        let layouts: Layouts = crate::jsonc::load_str("snippet.jsonc", &layout).or_fail()?;
+        let (mut app, commands) = dry_run_app(layouts, dry_run_options())?;
+
+        // A missing file is reported rather than stopping tuke
+        click_key(&mut app, 0)?;
+        assert!(commands.take().is_empty());
+        assert!(app.notification.take().is_some());
+
+        // The file is read on each press, so edits take effect immediately
+        std::fs::write(&path, "Hello, $USER").or_fail()?;
+        click_key(&mut app, 0)?;
+        assert_eq!(commands.take(), ["send-keys -t :.0 -l Hello, $USER"]);
+
+        // Decomposed accents are sent as they are unless normalization is enabled
+        std::fs::write(&path, "cafe\u{301}").or_fail()?;
+        click_key(&mut app, 0)?;
+        assert_eq!(commands.take(), ["send-keys -t :.0 -l cafe\u{301}"]);
+        app.options.normalize_nfc = true;
+        click_key(&mut app, 0)?;
+        assert_eq!(commands.take(), ["send-keys -t :.0 -l café"]);
+
+        click_key(&mut app, 1)?;
+        assert!(commands.take().is_empty());
+        assert!(
+            app.notification_text()
+                .is_some_and(|m| m.contains("larger than"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn modified_key_is_sent_when_confirmed() -> orfail::Result<()> {
+        let options = AppOptions {
+            confirm_modified: Some(Duration::from_secs(2)),
+            ..dry_run_options()
+        };
+        let (mut app, commands) = dry_run_app(Layouts::embedded()?, options)?;
+
+        click(&mut app, "C-")?;
+        click(&mut app, "c")?;
+        assert!(commands.take().is_empty());
+        assert_eq!(app.notification_text(), Some("click again to send C-c"));
+
+        click(&mut app, "c")?;
+        assert_eq!(commands.take(), ["send-keys -t :.0 C-c"]);
+        assert!(app.pending_confirm.is_none());
+
+        // Keys without Ctrl or Alt are sent right away
+        click(&mut app, "a")?;
+        assert_eq!(commands.take(), ["send-keys -t :.0 a"]);
+        Ok(())
+    }
+
+    #[test]
+    fn unconfirmed_modified_key_is_dropped() -> orfail::Result<()> {
+        let options = AppOptions {
+            confirm_modified: Some(Duration::from_secs(2)),
+            ..dry_run_options()
+        };
+        let (mut app, commands) = dry_run_app(Layouts::embedded()?, options)?;
+
+        click(&mut app, "C-")?;
+        click(&mut app, "c")?;
+        let deadline = app.pending_confirm.as_ref().or_fail()?.deadline;
+        assert_eq!(app.next_deadline(), Some(deadline));
+
+        // Not yet due
+        app.handle_timers()?;
+        assert!(app.pending_confirm.is_some());
+
+        app.pending_confirm.as_mut().or_fail()?.deadline = Instant::now();
+        app.handle_timers()?;
+        assert!(app.pending_confirm.is_none());
+        assert_eq!(app.notification_text(), None);
+
+        // The Ctrl was used up by the dropped key
+        click(&mut app, "c")?;
+        assert_eq!(commands.take(), ["send-keys -t :.0 c"]);
+
+        // Clicking another key also cancels
+        click(&mut app, "C-")?;
+        click(&mut app, "c")?;
+        click(&mut app, "a")?;
+        click(&mut app, "c")?;
+        assert_eq!(
+            commands.take(),
+            ["send-keys -t :.0 a", "send-keys -t :.0 c"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn format_labels_show_tmux_state() -> orfail::Result<()> {
+        let layouts: Layouts = crate::jsonc::load_str(
+            "label_format.jsonc",
+            r##"[{"key": "NextWindow", "label_format": "#{window_name}"}]"##,
+        )
+        .or_fail()?;
+        let tmux = MockTmux::default();
+        let replies = tmux.replies.clone();
+        let query = "display-message -p -t :.0 #{window_name}".to_owned();
+        replies
+            .borrow_mut()
+            .insert(query.clone(), Some(vec!["vim".to_owned()]));
+        let (mut app, _) = dry_run_app_with(tmux, layouts, dry_run_options())?;
+
+        assert!(app.refresh_format_labels());
+        assert_eq!(app.keys[0].label.as_deref(), Some("vim"));
+        assert!(!app.refresh_format_labels());
+        let deadline = app.format_label_deadline.or_fail()?;
+        assert!(deadline > Instant::now());
+
+        // Not yet due
+        replies
+            .borrow_mut()
+            .insert(query.clone(), Some(vec!["htop".to_owned()]));
+        app.handle_timers()?;
+        assert_eq!(app.keys[0].label.as_deref(), Some("vim"));
+
+        app.format_label_deadline = Some(Instant::now());
+        app.handle_timers()?;
+        assert_eq!(app.keys[0].label.as_deref(), Some("htop"));
+
+        replies.borrow_mut().insert(query, None);
+        app.refresh_format_labels();
+        assert_eq!(app.keys[0].label.as_deref(), Some(FORMAT_LABEL_PLACEHOLDER));
+        Ok(())
+    }
+
+    #[test]
+    fn capture_pane_key_saves_the_pane_contents() -> orfail::Result<()> {
+        let dir = TempDir::new("capture")?;
+        let path = dir.join("capture.txt");
+        let layout = format!(
+            r#"[{{"key": "CapturePane", "path": {:?}, "start": -100}},
+                {{"key": "CapturePane", "path": {:?}}}]"#,
+            path.display().to_string(),
+            dir.join("missing/capture.txt").display().to_string()
+        );
+        let layouts: Layouts = crate::jsonc::load_str("capture.jsonc", &layout).or_fail()?;
+        let tmux = MockTmux::default();
+        tmux.replies.borrow_mut().insert(
+            "capture-pane -p -t :.0 -S -100".to_owned(),
+            Some(vec!["$ ls".to_owned(), "Cargo.toml  src".to_owned()]),
+        );
+        let (mut app, commands) = dry_run_app_with(tmux, layouts, dry_run_options())?;
+
+        click_key(&mut app, 0)?;
+        assert_eq!(commands.take(), ["capture-pane -p -t :.0 -S -100"]);
+        let text = std::fs::read_to_string(&path).or_fail()?;
+        assert_eq!(text, "$ ls\nCargo.toml  src\n");
+        assert!(
+            app.notification
+                .take()
+                .is_some_and(|n| n.text.starts_with("saved 2 lines"))
+        );
+
+        // A file that cannot be written is reported
+        click_key(&mut app, 1)?;
+        assert!(
+            app.notification
+                .take()
+                .is_some_and(|n| n.text.starts_with("cannot save"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dragging_off_a_key_cancels_it() -> orfail::Result<()> {
+        let options = AppOptions {
+            drag_to_cancel: true,
+            ..dry_run_options()
+        };
+        let (mut app, commands) = dry_run_app(Layouts::embedded()?, options)?;
+        let position = |app: &App, token| {
+            let code = KeyCode::from_token(token).or_fail()?;
+            app.keys.iter().position(|k| k.key.code == code).or_fail()
+        };
+        let (a, s) = (position(&app, "a")?, position(&app, "s")?);
+
+        drag_key(&mut app, a, s)?;
+        assert!(commands.take().is_empty());
+
+        drag_key(&mut app, a, a)?;
+        assert_eq!(commands.take(), ["send-keys -t :.0 a"]);
+
+        // Without the option, the key under the release point is sent as before
+        let (mut app, commands) = dry_run_app(Layouts::embedded()?, dry_run_options())?;
+        drag_key(&mut app, a, s)?;
+        assert_eq!(commands.take(), ["send-keys -t :.0 s"]);
+        Ok(())
+    }
+
+    #[test]
+    fn binding_key_runs_the_bound_command() -> orfail::Result<()> {
+        let layouts: Layouts = crate::jsonc::load_str(
+            "binding.jsonc",
+            r#"[{"key": "Binding", "binding": "c"}, {"key": "Binding", "binding": "z"}]"#,
+        )
+        .or_fail()?;
+        let tmux = MockTmux::default();
+        let mut replies = tmux.replies.borrow_mut();
+        replies.insert(
+            "list-keys -N -T prefix c".to_owned(),
+            Some(vec!["c Create a new window".to_owned()]),
+        );
+        replies.insert(
+            "list-keys -T prefix c".to_owned(),
+            Some(vec!["bind-key -T prefix c new-window".to_owned()]),
+        );
+        replies.insert("list-keys -T prefix z".to_owned(), None);
+        replies.insert(
+            "display-message -p -t : #{pane_index} #{pane_id}".to_owned(),
+            Some(vec!["1 %1".to_owned()]),
+        );
+        drop(replies);
+        let (mut app, commands) = dry_run_app_with(tmux, layouts, dry_run_options())?;
+
+        app.update_binding_labels();
+        assert_eq!(app.keys[0].label.as_deref(), Some("Create a new window"));
+        assert_eq!(app.keys[1].label, None);
+        commands.take();
+
+        click_key(&mut app, 0)?;
+        assert_eq!(
+            commands.take()[..2],
+            ["list-keys -T prefix c", "new-window"]
+        );
+        // new-window selects the new window's pane
+        assert_eq!(app.pane_index, 1);
+
+        click_key(&mut app, 1)?;
+        assert_eq!(
+            app.notification_text(),
+            Some("z is not bound in the prefix table")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn queued_keys_are_sent_on_flush() -> orfail::Result<()> {
+        let layouts: Layouts = crate::jsonc::load_str(
+            "queue.jsonc",
+            r#"[{"key": "QueueMode"}, {"key": "FlushQueue"}, {"key": "Unqueue"},
+                {"key": "C-"}, {"key": "l"}, {"key": "s"}, {"key": "x"}, {"key": "Enter"}]"#,
+        )
+        .or_fail()?;
+        let (mut app, commands) = dry_run_app(layouts, dry_run_options())?;
+
+        for token in ["QueueMode", "l", "s", "x", "Unqueue", "Enter", "C-", "l"] {
+            click(&mut app, token)?;
+        }
+        assert!(commands.take().is_empty());
+        assert_eq!(app.keys[0].press, KeyPressState::Activated);
+        let names = app.queue.as_ref().or_fail()?.iter();
+        let names = names.map(|(k, m)| m.key_name(k)).collect::<Vec<_>>();
+        assert_eq!(names, ["l", "s", "Enter", "C-l"]);
+
+        click(&mut app, "FlushQueue")?;
+        assert_eq!(
+            commands.take(),
+            [
+                "send-keys -t :.0 l",
+                "send-keys -t :.0 s",
+                "send-keys -t :.0 Enter",
+                "send-keys -t :.0 C-l"
+            ]
+        );
+
+        // Still queueing after a flush, until queue mode is left
+        click(&mut app, "x")?;
+        click(&mut app, "QueueMode")?;
+        assert_eq!(app.notification_text(), Some("discarded 1 queued keys"));
+        assert_eq!(app.keys[0].press, KeyPressState::Neutral);
+        click(&mut app, "x")?;
+        assert_eq!(commands.take(), ["send-keys -t :.0 x"]);
+        Ok(())
+    }
+
+    #[test]
+    fn status_lines_are_stacked() -> orfail::Result<()> {
+        let layouts: Layouts = crate::jsonc::load_str(
+            "queue.jsonc",
+            r#"[{"key": "QueueMode"}, {"key": "NumberEntry"}, {"key": "4"}, {"key": "x"}]"#,
+        )
+        .or_fail()?;
+        let (mut app, _commands) = dry_run_app(layouts, dry_run_options())?;
+        for token in ["QueueMode", "x", "NumberEntry", "4"] {
+            click(&mut app, token)?;
+        }
+        app.notify("hello", NOTIFICATION_TTL);
+
+        let lines = app.status_lines().into_iter().map(|(text, _)| text);
+        assert_eq!(
+            lines.collect::<Vec<_>>(),
+            ["hello", "Queue: x_", "Number: 4_"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn entered_numbers_keep_their_place_in_the_queue() -> orfail::Result<()> {
+        let layouts: Layouts = crate::jsonc::load_str(
+            "queue.jsonc",
+            r#"[{"key": "QueueMode"}, {"key": "FlushQueue"}, {"key": "NumberEntry"},
+                {"key": "4"}, {"key": "2"}, {"key": "d"}, {"key": "x"}, {"key": "Enter"}]"#,
+        )
+        .or_fail()?;
+        let (mut app, commands) = dry_run_app(layouts, dry_run_options())?;
+
+        for token in ["QueueMode", "x", "NumberEntry", "4", "2", "d"] {
+            click(&mut app, token)?;
+        }
+        for token in ["NumberEntry", "2", "Enter"] {
+            click(&mut app, token)?;
+        }
+        assert!(commands.take().is_empty());
+
+        click(&mut app, "FlushQueue")?;
+        assert_eq!(
+            commands.take(),
+            [
+                "send-keys -t :.0 x",
+                "send-keys -t :.0 4",
+                "send-keys -t :.0 2",
+                "send-keys -t :.0 d",
+                "send-keys -t :.0 2"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn notifications_expire() -> orfail::Result<()> {
+        let (mut app, _commands) = dry_run_app(Layouts::embedded()?, dry_run_options())?;
+
+        app.notify("hello", Duration::from_secs(3));
+        let deadline = app.notification.as_ref().or_fail()?.deadline;
+        assert_eq!(app.next_deadline(), Some(deadline));
+        app.render()?;
+
+        // Not yet due
+        app.handle_timers()?;
+        assert_eq!(app.notification_text(), Some("hello"));
+
+        app.notification.as_mut().or_fail()?.deadline = Instant::now();
+        app.handle_timers()?;
+        assert_eq!(app.notification_text(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn middle_click_releases_only_that_modifier() -> orfail::Result<()> {
+        let (mut app, commands) = dry_run_app(Layouts::embedded()?, dry_run_options())?;
+        let index = |app: &App, token: &str| {
+            let code = KeyCode::from_token(token).or_fail()?;
+            app.keys.iter().position(|k| k.key.code == code).or_fail()
+        };
+        let (ctrl, alt, c) = (index(&app, "C-")?, index(&app, "M-")?, index(&app, "c")?);
+
+        // Lock Ctrl and latch Alt
+        click_key(&mut app, ctrl)?;
+        click_key(&mut app, ctrl)?;
+        click_key(&mut app, alt)?;
+        assert_eq!(app.keys[ctrl].press, KeyPressState::Activated);
+
+        mouse_event(&mut app, tuinix::MouseEvent::MiddleRelease, ctrl)?;
+        assert_eq!(app.keys[ctrl].press, KeyPressState::Neutral);
+        assert_eq!(app.keys[alt].press, KeyPressState::OneshotActivated);
+
+        // Other keys ignore middle clicks
+        mouse_event(&mut app, tuinix::MouseEvent::MiddleRelease, c)?;
+        assert!(commands.take().is_empty());
+
+        click_key(&mut app, c)?;
+        assert_eq!(commands.take(), ["send-keys -t :.0 M-c"]);
+        Ok(())
+    }
+
+    #[test]
+    fn tray_collapses_after_a_key_is_chosen() -> orfail::Result<()> {
+        let layouts: Layouts = crate::jsonc::load_str(
+            "tray.jsonc",
+            r#"[{"key": "a"}, {"key": "b"}, {"key": "Tray"}, {"newline": 1}, {"key": "c"},
+                {"tray": [{"key": "x"}, {"key": "y"}]}]"#,
+        )
+        .or_fail()?;
+        let (mut app, commands) = dry_run_app(layouts, dry_run_options())?;
+        let tray = 2;
+
+        // Expands over the keyboard, moved left so that it fits
+        click(&mut app, "Tray")?;
+        assert_eq!(app.keys.len(), 6);
+        assert_eq!(app.keys[tray].press, KeyPressState::Activated);
+        assert_eq!(
+            app.keys[4].key.region.position,
+            tuinix::TerminalPosition::row_col(0, 3)
+        );
+
+        // The tray keys take the clicks on the keys under them
+        click_key(&mut app, 1)?;
+        assert_eq!(commands.take(), ["send-keys -t :.0 x"]);
+        assert_eq!(app.keys.len(), 4);
+        assert_eq!(app.keys[tray].press, KeyPressState::Neutral);
+
+        // Clicking outside of the tray or waiting collapses it without sending anything
+        click(&mut app, "Tray")?;
+        click(&mut app, "c")?;
+        assert_eq!(app.keys.len(), 4);
+        click(&mut app, "Tray")?;
+        let deadline = app.tray.or_fail()?.1;
+        assert_eq!(app.next_deadline(), Some(deadline));
+        app.tray = app.tray.map(|(start, _)| (start, Instant::now()));
+        app.handle_timers()?;
+        assert_eq!(app.keys.len(), 4);
+        assert!(commands.take().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn tray_keys_leave_no_stale_indices() -> orfail::Result<()> {
+        let text = r#"[{"key": "C-"}, {"key": "a"}, {"key": "Tray"},
+                       {"chords": {"entries": [{"keys": ["x", "a"], "output": "Escape"}]}},
+                       {"tray": [{"key": "x"}, {"key": "y"}]}]"#;
+        let layouts: Layouts = crate::jsonc::load_str("tray.jsonc", text).or_fail()?;
+        let options = AppOptions {
+            confirm_modified: Some(Duration::from_secs(2)),
+            ..dry_run_options()
+        };
+        let (mut app, commands) = dry_run_app(layouts, options)?;
+
+        // A tray key is sent right away rather than waiting for a chord
+        click(&mut app, "Tray")?;
+        click(&mut app, "x")?;
+        assert_eq!(commands.take(), ["send-keys -t :.0 x"]);
+        assert!(app.pending_chord.is_none());
+        assert_eq!(app.keys.len(), 3);
+        app.handle_timers()?;
+
+        // The tray stays open until a modified tray key is confirmed
+        click(&mut app, "C-")?;
+        click(&mut app, "Tray")?;
+        click(&mut app, "y")?;
+        assert!(commands.take().is_empty());
+        assert_eq!(app.keys.len(), 5);
+        click(&mut app, "y")?;
+        assert_eq!(commands.take(), ["send-keys -t :.0 C-y"]);
+        assert_eq!(app.keys.len(), 3);
+
+        // Collapsing drops a pending confirmation of a tray key
+        click(&mut app, "C-")?;
+        click(&mut app, "Tray")?;
+        click(&mut app, "y")?;
+        app.close_tray();
+        assert!(app.pending_confirm.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn q_quits_and_other_letters_activate_mnemonics() -> orfail::Result<()> {
+        let layouts: Layouts =
+            crate::jsonc::load_str("mnemonic.jsonc", r#"[{"key": "x", "mnemonic": "x"}]"#)
+                .or_fail()?;
+        let (mut app, commands) = dry_run_app(layouts, dry_run_options())?;
+        let type_char = |app: &mut App, c| {
+            app.handle_key_input(tuinix::KeyInput {
+                ctrl: false,
+                alt: false,
+                code: tuinix::KeyCode::Char(c),
+            })
+        };
+
+        type_char(&mut app, 'x')?;
+        assert_eq!(commands.take(), ["send-keys -t :.0 x"]);
+        type_char(&mut app, 'y')?;
+        assert!(!app.exit);
+        type_char(&mut app, 'q')?;
+        assert!(app.exit);
+        Ok(())
+    }
+
+    #[test]
+    fn momentary_modifier_applies_while_held() -> orfail::Result<()> {
+        let layouts: Layouts = crate::jsonc::load_str(
+            "momentary.jsonc",
+            r#"[{"key": "C-", "modifier_mode": "momentary"}, {"key": "a"}]"#,
+        )
+        .or_fail()?;
+        let (mut app, commands) = dry_run_app(layouts, dry_run_options())?;
+
+        drag_key(&mut app, 0, 1)?;
+        assert_eq!(commands.take(), ["send-keys -t :.0 C-a"]);
+
+        // Released, the modifier no longer applies
+        click_key(&mut app, 1)?;
+        assert_eq!(commands.take(), ["send-keys -t :.0 a"]);
+        Ok(())
+    }
+
+    #[test]
+    fn latched_modifier_applies_to_the_next_key() -> orfail::Result<()> {
+        let layouts: Layouts =
+            crate::jsonc::load_str("latch.jsonc", r#"[{"key": "C-"}, {"key": "a"}]"#).or_fail()?;
+        let (mut app, commands) = dry_run_app(layouts, dry_run_options())?;
+
+        click_key(&mut app, 0)?;
+        click_key(&mut app, 1)?;
+        click_key(&mut app, 1)?;
+        assert_eq!(
+            commands.take(),
+            ["send-keys -t :.0 C-a", "send-keys -t :.0 a"]
+        );
+
+        // Clicked twice, it stays locked until clicked again
+        click_key(&mut app, 0)?;
+        click_key(&mut app, 0)?;
+        click_key(&mut app, 1)?;
+        click_key(&mut app, 1)?;
+        click_key(&mut app, 0)?;
+        click_key(&mut app, 1)?;
+        assert_eq!(
+            commands.take(),
+            [
+                "send-keys -t :.0 C-a",
+                "send-keys -t :.0 C-a",
+                "send-keys -t :.0 a"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn shift_tab_sends_btab() -> orfail::Result<()> {
+        assert_eq!(
+            dry_run_with(dry_run_options(), &["S-", "Tab"])?,
+            ["send-keys -t :.0 BTab"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn labeled_space_bar_sends_a_space() -> orfail::Result<()> {
+        let layouts: Layouts = crate::jsonc::load_str(
+            "space.jsonc",
+            r#"[{"key": "C-"}, {"key": "Space", "label": "␣", "size": {"width": 15, "height": 3}}]"#,
+        )
+        .or_fail()?;
+        let (mut app, commands) = dry_run_app(layouts, dry_run_options())?;
+
+        // The label only changes what is drawn
+        click_key(&mut app, 1)?;
+        click_key(&mut app, 0)?;
+        click_key(&mut app, 1)?;
+        assert_eq!(
+            commands.take(),
+            ["send-keys -t :.0  ", "send-keys -t :.0 C- "]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn closed_target_pane_falls_back_to_the_lowest_pane() -> orfail::Result<()> {
+        let tmux = MockTmux::default();
+        let replies = tmux.replies.clone();
+        let list_panes = "list-panes -t : -F #{pane_index} #{pane_id}".to_owned();
+        replies
+            .borrow_mut()
+            .insert("send-keys -t :.0 a".to_owned(), None);
+        replies.borrow_mut().insert(
+            list_panes.clone(),
+            Some(vec!["3 %93".to_owned(), "1 %91".to_owned()]),
+        );
+        let (mut app, commands) = dry_run_app_with(tmux, Layouts::embedded()?, dry_run_options())?;
+
+        click(&mut app, "a")?;
+        assert_eq!(commands.take(), ["send-keys -t :.0 a", list_panes.as_str()]);
+        assert_eq!(app.pane_index, 1);
+        assert_eq!(
+            app.notification_text(),
+            Some("pane 0 is gone; switched to pane 1")
+        );
+        click(&mut app, "a")?;
+        assert_eq!(commands.take(), ["send-keys -t :.1 a"]);
+
+        // Failing to list the panes is reported rather than ending the app
+        replies
+            .borrow_mut()
+            .insert("send-keys -t :.1 a".to_owned(), None);
+        replies.borrow_mut().insert(list_panes, None);
+        click(&mut app, "a")?;
+        assert_eq!(app.pane_index, 1);
+        assert!(
+            app.notification_text()
+                .is_some_and(|t| t.starts_with("cannot check pane 1"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn mirrored_layout_takes_clicks_at_the_mirrored_keys() -> orfail::Result<()> {
+        let text = r#"[{"key": "a", "size": {"width": 5, "height": 3}},
+                       {"key": "b", "size": {"width": 7, "height": 3}}]"#;
+        let options = AppOptions {
+            mirror: true,
+            ..dry_run_options()
+        };
+        let layouts: Layouts = crate::jsonc::load_str("mirror.jsonc", text).or_fail()?;
+        let (mut app, commands) = dry_run_app(layouts, options)?;
+
+        // The leftmost cell is now part of the last key
+        for event in [
+            tuinix::MouseEvent::LeftPress,
+            tuinix::MouseEvent::LeftRelease,
+        ] {
+            app.handle_mouse_input(tuinix::MouseInput {
+                event,
+                position: tuinix::TerminalPosition::row_col(app.offset.row + 1, app.offset.col),
+                ctrl: false,
+                alt: false,
+                shift: false,
+            })?;
+        }
+        assert_eq!(commands.take(), ["send-keys -t :.0 b"]);
+        Ok(())
+    }
+
+    #[test]
+    fn physical_modifiers_leave_the_latches_alone() -> orfail::Result<()> {
+        let (mut app, commands) = dry_run_app(Layouts::embedded()?, dry_run_options())?;
+        let a = app
+            .keys
+            .iter()
+            .position(|k| k.key.code == KeyCode::Char('a'))
+            .or_fail()?;
+        let click_held = |app: &mut App, ctrl, alt| {
+            let region = app.keys[a].key.region;
+            let position = tuinix::TerminalPosition::row_col(
+                app.offset.row + region.position.row + 1,
+                app.offset.col + region.position.col + 1 - app.h_offset,
+            );
+            for event in [
+                tuinix::MouseEvent::LeftPress,
+                tuinix::MouseEvent::LeftRelease,
+            ] {
+                app.handle_mouse_input(tuinix::MouseInput {
+                    event,
+                    position,
+                    ctrl,
+                    alt,
+                    shift: false,
+                })?;
+            }
+            Ok::<_, orfail::Failure>(())
+        };
+
+        click(&mut app, "M-")?;
+        click_held(&mut app, true, false)?;
+        click_held(&mut app, false, true)?;
+        assert_eq!(
+            commands.take(),
+            ["send-keys -t :.0 C-a", "send-keys -t :.0 M-a"]
+        );
+
+        // The latched Alt is still waiting for a key
+        click(&mut app, "a")?;
+        click(&mut app, "a")?;
+        assert_eq!(
+            commands.take(),
+            ["send-keys -t :.0 M-a", "send-keys -t :.0 a"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn tmux_notifications_update_the_target_pane() -> orfail::Result<()> {
+        let tmux = MockTmux::default();
+        let notifications = tmux.notifications.clone();
+        tmux.replies.borrow_mut().insert(
+            "display-message -p -t %95 #{pane_index}".to_owned(),
+            Some(vec!["2".to_owned()]),
+        );
+        tmux.replies.borrow_mut().insert(
+            "list-panes -t : -F #{pane_index} #{pane_id}".to_owned(),
+            Some(vec!["1 %91".to_owned()]),
+        );
+        let (mut app, commands) = dry_run_app_with(tmux, Layouts::embedded()?, dry_run_options())?;
+
+        // A pane selected outside of tuke becomes the target
+        notifications
+            .borrow_mut()
+            .push(Notification::WindowPaneChanged {
+                pane_id: "%95".to_owned(),
+            });
+        assert!(app.tmux_client.has_pending_notifications());
+        app.handle_tmux_notifications()?;
+        assert_eq!(app.pane_index, 2);
+        assert!(!app.tmux_client.has_pending_notifications());
+
+        // Closing it falls back to another pane
+        notifications.borrow_mut().push(Notification::LayoutChange);
+        app.handle_tmux_notifications()?;
+        assert_eq!(app.pane_index, 1);
+        assert_eq!(
+            app.notification_text(),
+            Some("pane 2 is gone; switched to pane 1")
+        );
+
+        commands.take();
+        click(&mut app, "a")?;
+        assert_eq!(commands.take(), ["send-keys -t :.1 a"]);
+        Ok(())
+    }
+
+    #[test]
+    fn panic_cleanup_disables_the_mouse_and_shows_the_cursor() -> orfail::Result<()> {
+        let mut output = Vec::new();
+        write_panic_cleanup(&mut output).or_fail()?;
+        assert_eq!(
+            output,
+            b"\x1b[?1006l\x1b[?1015l\x1b[?1002l\x1b[?1000l\x1b[?25h"
+        );
+        Ok(())
+    }
+
+    /// Output that can still be inspected after its writer is gone
+    #[derive(Debug, Default, Clone)]
+    struct SharedOutput(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl std::io::Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn cleanup_runs_on_normal_and_error_exits() -> orfail::Result<()> {
+        let run = |reply: Option<Vec<String>>| {
+            let tmux = MockTmux::default();
+            tmux.replies
+                .borrow_mut()
+                .insert("send-keys -t :.0 a".to_owned(), reply);
+            // The target pane exists, so a failure to send to it is fatal
+            tmux.replies.borrow_mut().insert(
+                "list-panes -t : -F #{pane_index} #{pane_id}".to_owned(),
+                Some(vec!["0 %90".to_owned()]),
+            );
+            let (mut app, _) = dry_run_app_with(tmux, Layouts::embedded()?, dry_run_options())?;
+            let output = SharedOutput::default();
+            app.cleanup = Some(ExitCleanup {
+                output: Box::new(output.clone()),
+                mouse: true,
+                title: true,
+            });
+            let result = click(&mut app, "a");
+            drop(app);
+            Ok::<_, orfail::Failure>((result.is_ok(), output.0.take()))
+        };
+        let restored = [title_sequence("").as_bytes(), MOUSE_OFF].concat();
+        assert_eq!(run(Some(Vec::new()))?, (true, restored.clone()));
+        assert_eq!(run(None)?, (false, restored));
+        Ok(())
+    }
+
+    #[test]
+    fn broadcast_covers_typed_text_and_echoes() -> orfail::Result<()> {
+        let dir = TempDir::new("broadcast")?;
+        let path = dir.join("hello.txt");
+        std::fs::write(&path, "hi").or_fail()?;
+        let layout = format!(
+            r#"[{{"key": "Broadcast"}}, {{"key": "a"}}, {{"key": "Snippet", "path": {:?}}}]"#,
+            path.display().to_string()
+        );
+        let layouts: Layouts = crate::jsonc::load_str("broadcast.jsonc", &layout).or_fail()?;
+        let tmux = MockTmux::default();
+        tmux.replies.borrow_mut().insert(
+            "list-panes -t : -F #{pane_index} #{pane_id}".to_owned(),
+            Some(vec!["0 %90".to_owned(), "1 %91".to_owned()]),
+        );
+        let options = AppOptions {
+            allow_broadcast: true,
+            echo_target: Some(EchoTarget::Status),
+            ..dry_run_options()
+        };
+        let (mut app, commands) = dry_run_app_with(tmux, layouts, options)?;
+
+        click_key(&mut app, 0)?;
+        click_key(&mut app, 1)?;
+        assert!(app.notification_text().is_some_and(|t| t.ends_with("→ a")));
+        click_key(&mut app, 2)?;
+        assert!(app.notification_text().is_some_and(|t| t.ends_with("→ hi")));
+
+        let sent = commands.take();
+        let sent = sent
+            .iter()
+            .filter(|c| c.starts_with("send-keys"))
+            .collect::<Vec<_>>();
+        assert_eq!(sent, ["send-keys -t :.1 a", "send-keys -t :.1 -l hi"]);
+        Ok(())
+    }
+
+    #[test]
+    fn press_counts_are_written_on_error_exits_too() -> orfail::Result<()> {
+        let dir = TempDir::new("stats")?;
+        let path = dir.join("stats.txt");
+        let tmux = MockTmux::default();
+        tmux.replies
+            .borrow_mut()
+            .insert("send-keys -t :.0 b".to_owned(), None);
+        tmux.replies.borrow_mut().insert(
+            "list-panes -t : -F #{pane_index} #{pane_id}".to_owned(),
+            Some(vec!["0 %90".to_owned()]),
+        );
+        let options = AppOptions {
+            stats_path: Some(path.clone()),
+            ..dry_run_options()
+        };
+        let (mut app, _) = dry_run_app_with(tmux, Layouts::embedded()?, options)?;
+
+        click(&mut app, "a")?;
+        click(&mut app, "a")?;
+        assert!(click(&mut app, "b").is_err());
+        drop(app);
+        let summary = std::fs::read_to_string(&path).or_fail()?;
+        assert_eq!(summary, "       2 \"a\"\n       1 \"b\"\n");
+        Ok(())
+    }
+
+    #[test]
+    fn switching_to_a_missing_layout_is_reported() -> orfail::Result<()> {
+        let layout = crate::layout::Layout::builder()
+            .key(KeyCode::SwitchLayout("numbers".to_owned()))
+            .key(KeyCode::Char('a'))
+            .build()?;
+        let layouts = Layouts {
+            entries: vec![layout],
+        };
+        let (mut app, commands) = dry_run_app(layouts, dry_run_options())?;
+
+        click_key(&mut app, 0)?;
+        assert_eq!(
+            app.notification_text(),
+            Some("unknown layout name: \"numbers\"")
+        );
+        click_key(&mut app, 1)?;
+        assert_eq!(commands.take(), ["send-keys -t :.0 a"]);
         Ok(())
     }
 }
@@ -0,0 +1,108 @@
+//! Composition of accented characters for dead keys
+
+/// Accents that can be used with `Dead` keys, by the name used in layout files
+pub const ACCENTS: &[(&str, char)] = &[
+    ("acute", '´'),
+    ("grave", '`'),
+    ("circumflex", '^'),
+    ("tilde", '~'),
+    ("diaeresis", '¨'),
+    ("cedilla", '¸'),
+];
+
+const COMPOSE_TABLE: &[(char, &[(char, char)])] = &[
+    (
+        '´',
+        &[
+            ('a', 'á'),
+            ('e', 'é'),
+            ('i', 'í'),
+            ('o', 'ó'),
+            ('u', 'ú'),
+            ('y', 'ý'),
+        ],
+    ),
+    (
+        '`',
+        &[('a', 'à'), ('e', 'è'), ('i', 'ì'), ('o', 'ò'), ('u', 'ù')],
+    ),
+    (
+        '^',
+        &[('a', 'â'), ('e', 'ê'), ('i', 'î'), ('o', 'ô'), ('u', 'û')],
+    ),
+    ('~', &[('a', 'ã'), ('n', 'ñ'), ('o', 'õ')]),
+    (
+        '¨',
+        &[
+            ('a', 'ä'),
+            ('e', 'ë'),
+            ('i', 'ï'),
+            ('o', 'ö'),
+            ('u', 'ü'),
+            ('y', 'ÿ'),
+        ],
+    ),
+    ('¸', &[('c', 'ç')]),
+];
+
+/// Combining marks and the accents they correspond to in the compose table
+const COMBINING_MARKS: &[(char, char)] = &[
+    ('\u{301}', '´'),
+    ('\u{300}', '`'),
+    ('\u{302}', '^'),
+    ('\u{303}', '~'),
+    ('\u{308}', '¨'),
+    ('\u{327}', '¸'),
+];
+
+/// Returns the character made by applying `accent` to `base`, if there is one
+///
+/// The case of `base` is preserved (e.g. `´` + `E` gives `É`).
+pub fn compose(accent: char, base: char) -> Option<char> {
+    let (_, entries) = COMPOSE_TABLE.iter().find(|(a, _)| *a == accent)?;
+    let lower = base.to_lowercase().next()?;
+    let (_, composed) = entries.iter().find(|(b, _)| *b == lower)?;
+    if base.is_uppercase() {
+        composed.to_uppercase().next()
+    } else {
+        Some(*composed)
+    }
+}
+
+/// Converts `text` to Unicode NFC for the accented characters in the compose table
+///
+/// A base character followed by a combining mark becomes the precomposed character,
+/// and everything else is kept as it is.
+pub fn to_nfc(text: &str) -> String {
+    let mut normalized = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let composed = chars.peek().and_then(|next| {
+            let (_, accent) = COMBINING_MARKS.iter().find(|(mark, _)| mark == next)?;
+            compose(*accent, c)
+        });
+        if let Some(composed) = composed {
+            normalized.push(composed);
+            chars.next();
+        } else {
+            normalized.push(c);
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decomposed_text_is_normalized() {
+        assert_eq!(to_nfc("cafe\u{301}"), "café");
+        assert_eq!(to_nfc("E\u{300}re"), "Ère");
+        assert_eq!(to_nfc("n\u{303}o\u{308}c\u{327}"), "ñöç");
+
+        // Precomposed text and marks with nothing to compose with are left alone
+        assert_eq!(to_nfc("café"), "café");
+        assert_eq!(to_nfc("\u{301}x\u{301}"), "\u{301}x\u{301}");
+    }
+}
@@ -0,0 +1,133 @@
+//! Coalescing of render requests that arrive faster than the frame rate cap
+use std::time::{Duration, Instant};
+
+/// Limits rendering to at most one frame per interval without dropping the last frame
+///
+/// Requests made too soon after the previous frame are merged into a single pending frame,
+/// which is rendered when [`FrameLimiter::deadline()`] has passed.
+#[derive(Debug)]
+pub struct FrameLimiter {
+    min_interval: Duration,
+    last_render: Option<Instant>,
+    pending: bool,
+}
+
+impl FrameLimiter {
+    pub fn new(min_interval: Duration) -> Self {
+        Self {
+            min_interval,
+            last_render: None,
+            pending: false,
+        }
+    }
+
+    /// Returns `true` if a frame should be rendered now, or defers it otherwise
+    pub fn request(&mut self, now: Instant) -> bool {
+        if self
+            .last_render
+            .is_some_and(|last| now < last + self.min_interval)
+        {
+            self.pending = true;
+            return false;
+        }
+        self.pending = false;
+        self.last_render = Some(now);
+        true
+    }
+
+    /// Returns when the deferred frame is due, if any
+    pub fn deadline(&self) -> Option<Instant> {
+        self.last_render
+            .filter(|_| self.pending)
+            .map(|last| last + self.min_interval)
+    }
+
+    /// Returns `true` if the deferred frame is due and should be rendered now
+    pub fn poll(&mut self, now: Instant) -> bool {
+        if self.deadline().is_some_and(|deadline| deadline <= now) {
+            self.pending = false;
+            self.last_render = Some(now);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_millis(16);
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn first_request_renders_immediately() {
+        let t0 = Instant::now();
+        let mut limiter = FrameLimiter::new(INTERVAL);
+        assert!(limiter.request(t0));
+        assert_eq!(limiter.deadline(), None);
+    }
+
+    #[test]
+    fn rapid_requests_are_coalesced_into_one_frame() {
+        let t0 = Instant::now();
+        let mut limiter = FrameLimiter::new(INTERVAL);
+        assert!(limiter.request(t0));
+        assert!(!limiter.request(t0 + ms(1)));
+        assert!(!limiter.request(t0 + ms(5)));
+        assert!(!limiter.request(t0 + ms(10)));
+        assert_eq!(limiter.deadline(), Some(t0 + INTERVAL));
+
+        assert!(!limiter.poll(t0 + ms(15)));
+        assert!(limiter.poll(t0 + INTERVAL));
+
+        // The pending frame has been rendered exactly once
+        assert!(!limiter.poll(t0 + ms(100)));
+        assert_eq!(limiter.deadline(), None);
+    }
+
+    #[test]
+    fn last_request_is_never_dropped() {
+        let t0 = Instant::now();
+        let mut limiter = FrameLimiter::new(INTERVAL);
+        assert!(limiter.request(t0));
+        assert!(!limiter.request(t0 + ms(15)));
+
+        // Even if the event loop wakes up late, the pending frame is still due
+        assert!(limiter.poll(t0 + ms(500)));
+    }
+
+    #[test]
+    fn requests_after_the_interval_render_immediately() {
+        let t0 = Instant::now();
+        let mut limiter = FrameLimiter::new(INTERVAL);
+        assert!(limiter.request(t0));
+        assert!(limiter.request(t0 + INTERVAL));
+        assert!(limiter.request(t0 + INTERVAL * 3));
+        assert_eq!(limiter.deadline(), None);
+    }
+
+    #[test]
+    fn interval_restarts_from_the_deferred_frame() {
+        let t0 = Instant::now();
+        let mut limiter = FrameLimiter::new(INTERVAL);
+        assert!(limiter.request(t0));
+        assert!(!limiter.request(t0 + ms(8)));
+        assert!(limiter.poll(t0 + ms(20)));
+        assert!(!limiter.request(t0 + ms(30)));
+        assert_eq!(limiter.deadline(), Some(t0 + ms(20) + INTERVAL));
+    }
+
+    #[test]
+    fn zero_interval_never_defers() {
+        let t0 = Instant::now();
+        let mut limiter = FrameLimiter::new(Duration::ZERO);
+        assert!(limiter.request(t0));
+        assert!(limiter.request(t0));
+        assert_eq!(limiter.deadline(), None);
+    }
+}
@@ -4,10 +4,23 @@ pub fn load_file<P: AsRef<Path>, T>(path: P) -> Result<T, LoadError>
 where
     T: for<'text, 'raw> TryFrom<nojson::RawJsonValue<'text, 'raw>, Error = nojson::JsonParseError>,
 {
-    let text = std::fs::read_to_string(&path).map_err(|e| LoadError::Io {
+    let bytes = std::fs::read(&path).map_err(|e| LoadError::Io {
         path: path.as_ref().to_path_buf(),
         error: e,
     })?;
+    let text = String::from_utf8(bytes).map_err(|e| {
+        let offset = e.utf8_error().valid_up_to();
+        let line = e.as_bytes()[..offset]
+            .iter()
+            .filter(|&&b| b == b'\n')
+            .count()
+            + 1;
+        LoadError::InvalidUtf8 {
+            path: path.as_ref().to_path_buf(),
+            offset,
+            line,
+        }
+    })?;
     load_str(&path.as_ref().display().to_string(), &text)
 }
 
@@ -32,6 +45,17 @@ pub enum LoadError {
         text: String,
         error: nojson::JsonParseError,
     },
+
+    /// The file is not UTF-8 encoded (e.g. saved as Latin-1 or UTF-16)
+    InvalidUtf8 {
+        path: PathBuf,
+
+        /// Byte offset of the first invalid byte
+        offset: usize,
+
+        /// Line number (1-based) of the first invalid byte
+        line: usize,
+    },
 }
 
 impl LoadError {
@@ -51,6 +75,12 @@ impl std::fmt::Display for LoadError {
                 write!(f, "failed to read file '{}': {error}", path.display())
             }
             LoadError::Json { path, error, text } => format_json_error(f, path, error, text),
+            LoadError::InvalidUtf8 { path, offset, line } => write!(
+                f,
+                "file '{}' is not valid UTF-8 (invalid byte at offset {offset}, line {line}); \
+                 please save it with UTF-8 encoding",
+                path.display()
+            ),
         }
     }
 }
@@ -110,3 +140,105 @@ fn format_line_around_position(line: &str, column_pos: usize) -> (String, usize)
 
     (result, new_column_pos)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the character that the caret points to in the formatted line
+    fn char_at_caret((line, column): &(String, usize)) -> Option<char> {
+        line.chars().nth(column - 1)
+    }
+
+    fn long_line() -> String {
+        (0..200)
+            .map(|i| char::from(b'a' + (i % 26) as u8))
+            .collect()
+    }
+
+    #[test]
+    fn error_at_first_column() {
+        assert_eq!(format_line_around_position("abc", 1), ("abc".to_owned(), 1));
+
+        let line = long_line();
+        let formatted = format_line_around_position(&line, 1);
+        assert_eq!(formatted.0, format!("{}...", &line[..41]));
+        assert_eq!(formatted.1, 1);
+    }
+
+    #[test]
+    fn error_at_last_column() {
+        let formatted = format_line_around_position("abc", 3);
+        assert_eq!(formatted, ("abc".to_owned(), 3));
+        assert_eq!(char_at_caret(&formatted), Some('c'));
+
+        // Errors at the end of the text point just past the last character
+        assert_eq!(format_line_around_position("abc", 4), ("abc".to_owned(), 4));
+
+        let line = long_line();
+        let formatted = format_line_around_position(&line, 200);
+        assert_eq!(formatted.0, format!("...{}", &line[159..]));
+        assert_eq!(formatted.1, 44);
+        assert_eq!(char_at_caret(&formatted), line.chars().nth(199));
+    }
+
+    #[test]
+    fn long_line_truncated_on_both_sides() {
+        let line = long_line();
+        let formatted = format_line_around_position(&line, 101);
+        assert_eq!(formatted.0, format!("...{}...", &line[60..141]));
+        assert_eq!(formatted.1, 44);
+        assert_eq!(char_at_caret(&formatted), line.chars().nth(100));
+    }
+
+    #[test]
+    fn short_line_is_not_truncated() {
+        let line = "[{\"key\": \"a\"}, {\"key\": 1}]";
+        let formatted = format_line_around_position(line, 24);
+        assert_eq!(formatted, (line.to_owned(), 24));
+        assert_eq!(char_at_caret(&formatted), Some('1'));
+    }
+
+    #[test]
+    fn multi_byte_characters_before_error() {
+        // Columns count characters (as nojson does), not bytes
+        let formatted = format_line_around_position("ééé x", 5);
+        assert_eq!(formatted, ("ééé x".to_owned(), 5));
+        assert_eq!(char_at_caret(&formatted), Some('x'));
+
+        let line = format!("{}x", "é".repeat(100));
+        let formatted = format_line_around_position(&line, 101);
+        assert_eq!(formatted.0, format!("...{}x", "é".repeat(40)));
+        assert_eq!(char_at_caret(&formatted), Some('x'));
+    }
+
+    #[test]
+    fn column_numbers_from_nojson_are_character_based() {
+        let text = "[\"é\", x]";
+        let error = nojson::RawJson::parse_jsonc(text).expect_err("invalid JSON");
+        let (_, column) = error.get_line_and_column_numbers(text).expect("in bounds");
+        let formatted = format_line_around_position(text, column.get());
+        assert_eq!(char_at_caret(&formatted), Some('x'));
+    }
+
+    #[test]
+    fn non_utf8_file_is_reported_with_offset() {
+        let path = std::env::temp_dir().join(format!("tuke-non-utf8-{}.jsonc", std::process::id()));
+        std::fs::write(&path, b"[\n  {\"key\": \"\xe9\"}\n]").expect("write temp file");
+        let result = load_file::<_, crate::layout::Layout>(&path);
+        let _ = std::fs::remove_file(&path);
+
+        let error = result.expect_err("invalid UTF-8");
+        assert!(matches!(
+            error,
+            LoadError::InvalidUtf8 {
+                offset: 13,
+                line: 2,
+                ..
+            }
+        ));
+        let message = error.to_string();
+        assert!(message.contains("is not valid UTF-8"), "{message}");
+        assert!(message.contains("offset 13, line 2"), "{message}");
+    }
+}
@@ -1,24 +1,532 @@
 use std::fmt::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use orfail::OrFail;
 
+use crate::tap_hold::TapHoldConfig;
+
+pub const DEFAULT_LAYOUT_NAME: &str = "default";
+
+/// Latest layout format version understood by this build
+///
+/// Layouts without a `"version"` directive are treated as this version.
+pub const LAYOUT_FORMAT_VERSION: u64 = 1;
+
+/// Smallest key size that leaves room for the borders and a one-character label
+///
+/// Layouts can raise the minimum for their keys via the `"min_key_size"` directive, but not lower it.
+pub const MIN_KEY_SIZE: tuinix::TerminalSize = tuinix::TerminalSize { rows: 3, cols: 3 };
+
+/// Key table of `Binding` keys without a `"table"` member
+pub const DEFAULT_BINDING_TABLE: &str = "prefix";
+
+/// Delay of a `"revert"` when `after_ms` is omitted
+pub const DEFAULT_REVERT_AFTER: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Name of the layout of the picker shown by [`Layouts::picker()`]
+pub const PICKER_LAYOUT_NAME: &str = "layouts";
+
+/// Minimum width of the buttons of the layout picker
+const PICKER_MIN_WIDTH: usize = 24;
+
+/// Named layouts that can be switched at runtime via `SwitchLayout` keys
+///
+/// A layout file is either a single layout (a JSON array) or an object whose members are named layouts.
+/// The first layout is the initially active one.
 #[derive(Debug)]
+pub struct Layouts {
+    pub entries: Vec<Layout>,
+}
+
+impl Layouts {
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> orfail::Result<Self> {
+        crate::jsonc::load_file(path).or_fail()
+    }
+
+    /// Loads a single layout file, or merges several layout fragments in order
+    ///
+    /// Each fragment must be a single layout (a JSON array). A fragment continues below the
+    /// previous ones unless it starts with a `base_position` directive, in which case its keys
+    /// stay where they are placed.
+    pub fn load_from_files<P: AsRef<Path>>(paths: &[P]) -> orfail::Result<Self> {
+        let [first, rest @ ..] = paths else {
+            return Self::embedded().or_fail();
+        };
+        if rest.is_empty() {
+            return Self::load_from_file(first).or_fail();
+        }
+
+        let mut merged = crate::jsonc::load_file::<_, LayoutFragment>(first)
+            .or_fail()?
+            .layout;
+        for path in rest {
+            let fragment: LayoutFragment = crate::jsonc::load_file(path).or_fail()?;
+            let mut layout = fragment.layout;
+            if !fragment.positioned {
+                layout.move_down(merged.bottom());
+            }
+            merged.merge(layout).map_err(|e| {
+                orfail::Failure::new(format!("{}: {}", path.as_ref().display(), e.message))
+            })?;
+        }
+        let entries = vec![merged];
+        if let Some(name) = unknown_switch_target(&entries[0], &entries) {
+            return Err(orfail::Failure::new(format!(
+                "unknown layout name: {name:?}"
+            )));
+        }
+        Ok(Self { entries })
+    }
+
+    /// Returns the `.jsonc` layout files in `dir` sorted by name, or none if `dir` does not exist
+    pub fn find_files<P: AsRef<Path>>(dir: P) -> orfail::Result<Vec<PathBuf>> {
+        let dir = dir.as_ref();
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(orfail::Failure::new(format!(
+                    "failed to read layout directory '{}': {e}",
+                    dir.display()
+                )));
+            }
+        };
+        let mut paths = Vec::new();
+        for entry in entries {
+            let path = entry.or_fail()?.path();
+            if path.extension().is_some_and(|ext| ext == "jsonc") && path.is_file() {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Returns a layout with a button for each of `paths`, which loads that layout file when clicked
+    pub fn picker(paths: &[PathBuf]) -> Self {
+        let codes = paths
+            .iter()
+            .map(|path| KeyCode::LoadLayoutFile(path.clone()))
+            .collect::<Vec<_>>();
+        let width = codes
+            .iter()
+            .map(|code| code.to_string().chars().count() + 4)
+            .max()
+            .unwrap_or_default()
+            .max(PICKER_MIN_WIDTH);
+        let size = tuinix::TerminalSize::rows_cols(3, width);
+        let mut keys = codes
+            .into_iter()
+            .enumerate()
+            .map(|(i, code)| {
+                let position = tuinix::TerminalPosition::row(i * size.rows);
+                Key::new(code, tuinix::TerminalRegion { position, size })
+            })
+            .collect::<Vec<_>>();
+        update_neighbors(&mut keys);
+        Self {
+            entries: vec![Layout {
+                name: PICKER_LAYOUT_NAME.to_owned(),
+                keys,
+                preview: None,
+                chords: None,
+                border_style: None,
+                label_overflow: None,
+                copy_mode_color: None,
+                shared_borders: false,
+                hit_padding: 0,
+                tray: Vec::new(),
+            }],
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Layout> {
+        self.entries.iter().find(|l| l.name == name)
+    }
+}
+
+/// Layout file loaded as a part of a merged layout (see [`Layouts::load_from_files()`])
+struct LayoutFragment {
+    layout: Layout,
+
+    /// Whether the fragment starts with a `base_position` directive
+    positioned: bool,
+}
+
+impl<'text, 'raw> TryFrom<nojson::RawJsonValue<'text, 'raw>> for LayoutFragment {
+    type Error = nojson::JsonParseError;
+
+    fn try_from(value: nojson::RawJsonValue<'text, 'raw>) -> Result<Self, Self::Error> {
+        if value.kind() == nojson::JsonValueKind::Object {
+            return Err(value.invalid(
+                "layout fragments to merge must be single layouts (JSON arrays), not named layouts",
+            ));
+        }
+        let mut positioned = false;
+        for element in value.to_array()? {
+            if element.to_member("version")?.get().is_none() {
+                positioned = element.to_member("base_position")?.get().is_some();
+                break;
+            }
+        }
+        let layout = Layout::try_from(value)?;
+        Ok(Self { layout, positioned })
+    }
+}
+
+impl Layouts {
+    /// Returns the layout bundled with tuke (`layouts/default.jsonc`)
+    pub fn embedded() -> orfail::Result<Self> {
+        Ok(Self {
+            entries: vec![Layout::embedded().or_fail()?],
+        })
+    }
+}
+
+impl<'text, 'raw> TryFrom<nojson::RawJsonValue<'text, 'raw>> for Layouts {
+    type Error = nojson::JsonParseError;
+
+    fn try_from(value: nojson::RawJsonValue<'text, 'raw>) -> Result<Self, Self::Error> {
+        if value.kind() != nojson::JsonValueKind::Object {
+            let entries = vec![Layout::try_from(value)?];
+            if let Some(name) = unknown_switch_target(&entries[0], &entries) {
+                return Err(value.invalid(format!("unknown layout name: {name:?}")));
+            }
+            return Ok(Self { entries });
+        }
+
+        let mut entries = Vec::new();
+        let mut layout_values = Vec::new();
+        for (name_value, layout_value) in value.to_object()? {
+            let mut layout = Layout::try_from(layout_value)?;
+            layout.name = name_value.to_unquoted_string_str()?.into_owned();
+            entries.push(layout);
+            layout_values.push(layout_value);
+        }
+        if entries.is_empty() {
+            return Err(value.invalid("at least one layout must be defined"));
+        }
+
+        for (layout, layout_value) in entries.iter().zip(layout_values) {
+            if let Some(name) = unknown_switch_target(layout, &entries) {
+                return Err(layout_value.invalid(format!("unknown layout name: {name:?}")));
+            }
+        }
+
+        Ok(Self { entries })
+    }
+}
+
+/// Returns the first layout name that a `SwitchLayout` key of `layout` refers to but `entries` lacks
+fn unknown_switch_target<'a>(layout: &'a Layout, entries: &[Layout]) -> Option<&'a str> {
+    layout
+        .keys
+        .iter()
+        .chain(&layout.tray)
+        .find_map(|key| match &key.code {
+            KeyCode::SwitchLayout(name) if !entries.iter().any(|l| l.name == *name) => {
+                Some(name.as_str())
+            }
+            _ => None,
+        })
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Layout {
+    pub name: String,
     pub keys: Vec<Key>,
     pub preview: Option<Preview>,
+    pub chords: Option<Chords>,
+    pub border_style: Option<BorderStyle>,
+    pub label_overflow: Option<LabelOverflow>,
+    pub copy_mode_color: Option<tuinix::TerminalColor>,
+
+    /// Draw a single border between flush keys instead of two adjacent ones
+    pub shared_borders: bool,
+
+    /// Number of cells around each key that still count as a click on it (e.g. for touch screens)
+    pub hit_padding: usize,
+
+    /// Keys shown over the keyboard by a `Tray` key, positioned relative to the tray's top left
+    pub tray: Vec<Key>,
 }
 
 impl Layout {
+    /// Returns a builder for making a layout in code
+    pub fn builder() -> LayoutBuilder {
+        LayoutBuilder::default()
+    }
+
     pub fn load_from_file<P: AsRef<Path>>(path: P) -> orfail::Result<Self> {
         crate::jsonc::load_file(path).or_fail()
     }
+
+    /// Appends the keys of `other`, which must neither overlap the keys of this layout
+    /// nor reuse their ids
+    ///
+    /// Directives that this layout does not set (e.g. `preview`) are taken from `other`.
+    pub fn merge(&mut self, other: Layout) -> orfail::Result<()> {
+        for key in &other.keys {
+            if let Some(existing) = self
+                .keys
+                .iter()
+                .find(|k| regions_overlap(k.region, key.region))
+            {
+                let position = key.region.position;
+                return Err(orfail::Failure::new(format!(
+                    "key {:?} at row {}, column {} overlaps key {:?}",
+                    key.code.to_string(),
+                    position.row,
+                    position.col,
+                    existing.code.to_string()
+                )));
+            }
+            if let Some(id) = &key.id
+                && self.find_by_id(id).is_some()
+            {
+                return Err(orfail::Failure::new(format!("duplicate key id {id:?}")));
+            }
+        }
+
+        self.keys.extend(other.keys);
+        self.preview = self.preview.take().or(other.preview);
+        self.chords = self.chords.take().or(other.chords);
+        self.border_style = self.border_style.or(other.border_style);
+        self.label_overflow = self.label_overflow.or(other.label_overflow);
+        self.copy_mode_color = self.copy_mode_color.or(other.copy_mode_color);
+        self.shared_borders |= other.shared_borders;
+        self.hit_padding = self.hit_padding.max(other.hit_padding);
+        if self.tray.is_empty() {
+            self.tray = other.tray;
+        }
+        update_neighbors(&mut self.keys);
+        Ok(())
+    }
+
+    /// Returns the row just below the lowest key or preview
+    fn bottom(&self) -> usize {
+        self.keys
+            .iter()
+            .map(|k| k.region)
+            .chain(self.preview.iter().map(|p| p.region))
+            .map(|r| r.position.row + r.size.rows)
+            .max()
+            .unwrap_or_default()
+    }
+
+    fn move_down(&mut self, rows: usize) {
+        let regions = self
+            .keys
+            .iter_mut()
+            .map(|k| &mut k.region)
+            .chain(self.preview.iter_mut().map(|p| &mut p.region));
+        for region in regions {
+            region.position.row += rows;
+        }
+    }
+
+    /// Returns the key with the given `"id"` member
+    pub fn find_by_id(&self, id: &str) -> Option<&Key> {
+        self.keys.iter().find(|k| k.id.as_deref() == Some(id))
+    }
+
+    /// Flips the layout horizontally (e.g. for right-to-left use)
+    pub fn mirror(&mut self) {
+        let regions = self
+            .keys
+            .iter_mut()
+            .map(|k| &mut k.region)
+            .chain(self.preview.iter_mut().map(|p| &mut p.region))
+            .collect::<Vec<_>>();
+        let width = regions
+            .iter()
+            .map(|r| r.position.col + r.size.cols)
+            .max()
+            .unwrap_or_default();
+        for region in regions {
+            region.position.col = width - (region.position.col + region.size.cols);
+        }
+        update_neighbors(&mut self.keys);
+    }
+
+    /// Swaps the rows and columns of the key grid (e.g. for fitting a wide layout on a tall screen)
+    ///
+    /// Only the regions are transposed, so labels are still drawn upright.
+    pub fn transpose(&mut self) {
+        let regions = self
+            .keys
+            .iter_mut()
+            .map(|k| &mut k.region)
+            .chain(self.preview.iter_mut().map(|p| &mut p.region));
+        for region in regions {
+            let tuinix::TerminalRegion { position, size } = *region;
+            *region = tuinix::TerminalRegion {
+                position: tuinix::TerminalPosition::row_col(position.col, position.row),
+                size: tuinix::TerminalSize::rows_cols(size.cols, size.rows),
+            };
+        }
+        update_neighbors(&mut self.keys);
+    }
+
+    /// Renders the keys in their initial state as plain text (e.g. for sharing a layout)
+    pub fn to_text(&self, options: &RenderOptions) -> orfail::Result<String> {
+        let regions = self
+            .keys
+            .iter()
+            .map(|k| k.region)
+            .chain(self.preview.iter().map(|p| p.region));
+        let (rows, cols) = regions.fold((0, 0), |(rows, cols), r| {
+            (
+                rows.max(r.position.row + r.size.rows),
+                cols.max(r.position.col + r.size.cols),
+            )
+        });
+
+        let mut grid = vec![vec![' '; cols]; rows];
+        let mut put = |position: tuinix::TerminalPosition, lines: Vec<String>| {
+            for (row, line) in grid[position.row..].iter_mut().zip(lines) {
+                for (cell, c) in row[position.col..].iter_mut().zip(line.chars()) {
+                    *cell = c;
+                }
+            }
+        };
+        for key in &self.keys {
+            key.check_size().or_fail()?;
+            let lines = KeyState::new(key.clone()).to_text_lines(false, options);
+            put(key.region.position, lines);
+        }
+        if let Some(preview) = &self.preview {
+            let padding = " ".repeat(preview.region.size.cols.saturating_sub(3));
+            put(preview.region.position, vec![format!("> {padding}<")]);
+        }
+
+        let mut text = String::new();
+        for row in grid {
+            let line = row.into_iter().collect::<String>();
+            writeln!(text, "{}", line.trim_end()).or_fail()?;
+        }
+        Ok(text)
+    }
+}
+
+impl Layout {
+    /// Returns the layout bundled with tuke (`layouts/default.jsonc`)
+    ///
+    /// The bundled layouts are checked by the tests, so this fails only for a broken build.
+    pub fn embedded() -> orfail::Result<Self> {
+        // The embedded layout is parsed only once and cloned afterwards
+        static EMBEDDED: std::sync::OnceLock<Result<Layout, String>> = std::sync::OnceLock::new();
+        EMBEDDED
+            .get_or_init(|| {
+                crate::jsonc::load_str("default.jsonc", include_str!("../layouts/default.jsonc"))
+                    .map_err(|e| format!("the bundled default layout is broken:\n{e}"))
+            })
+            .clone()
+            .map_err(orfail::Failure::new)
+    }
+}
+
+/// Builder of a [`Layout`] in code (e.g. for generating a numpad) instead of a layout file
+///
+/// As in layout files, each key is placed right after the previous one and has the minimum size
+/// unless changed with [`LayoutBuilder::at()`] or [`LayoutBuilder::size()`],
+/// which apply to the key added last.
+#[derive(Debug, Default)]
+pub struct LayoutBuilder {
+    name: Option<String>,
+    keys: Vec<Key>,
+
+    /// First misuse of the builder, reported by [`LayoutBuilder::build()`]
+    error: Option<String>,
 }
 
-impl Default for Layout {
-    fn default() -> Self {
-        crate::jsonc::load_str("default.json", include_str!("../layouts/default.jsonc"))
-            .expect("bug")
+impl LayoutBuilder {
+    /// Sets the name of the layout (defaults to [`DEFAULT_LAYOUT_NAME`])
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_owned());
+        self
+    }
+
+    /// Adds a key to the right of the previous one
+    pub fn key(mut self, code: KeyCode) -> Self {
+        let position = self
+            .keys
+            .last()
+            .map_or(tuinix::TerminalPosition::ZERO, |k| {
+                let mut position = k.region.top_right();
+                position.col += 1;
+                position
+            });
+        let region = tuinix::TerminalRegion {
+            position,
+            size: MIN_KEY_SIZE,
+        };
+        self.keys.push(Key::new(code, region));
+        self
+    }
+
+    /// Sets the width and height of the last added key
+    pub fn size(self, width: usize, height: usize) -> Self {
+        self.update_last_key("size", |key| {
+            key.region.size = tuinix::TerminalSize::rows_cols(height, width);
+        })
+    }
+
+    /// Moves the last added key to `row` and `col`
+    ///
+    /// Keys added afterwards continue to the right of it.
+    pub fn at(self, row: usize, col: usize) -> Self {
+        self.update_last_key("at", |key| {
+            key.region.position = tuinix::TerminalPosition::row_col(row, col);
+        })
+    }
+
+    fn update_last_key(mut self, method: &str, f: impl FnOnce(&mut Key)) -> Self {
+        if let Some(key) = self.keys.last_mut() {
+            f(key);
+        } else {
+            self.error
+                .get_or_insert_with(|| format!("{method}() was called before any key()"));
+        }
+        self
+    }
+
+    /// Checks the key sizes and overlaps and returns the layout
+    pub fn build(self) -> orfail::Result<Layout> {
+        if let Some(error) = self.error {
+            return Err(orfail::Failure::new(error));
+        }
+        for (i, key) in self.keys.iter().enumerate() {
+            key.check_size().or_fail()?;
+            let position = key.region.position;
+            if let Some(existing) = self.keys[..i]
+                .iter()
+                .find(|k| regions_overlap(k.region, key.region))
+            {
+                return Err(orfail::Failure::new(format!(
+                    "key {:?} at row {}, column {} overlaps key {:?}",
+                    key.code.to_string(),
+                    position.row,
+                    position.col,
+                    existing.code.to_string()
+                )));
+            }
+        }
+
+        let mut keys = self.keys;
+        update_neighbors(&mut keys);
+        Ok(Layout {
+            name: self.name.unwrap_or_else(|| DEFAULT_LAYOUT_NAME.to_owned()),
+            keys,
+            preview: None,
+            chords: None,
+            border_style: None,
+            label_overflow: None,
+            copy_mode_color: None,
+            shared_borders: false,
+            hit_padding: 0,
+            tray: Vec::new(),
+        })
     }
 }
 
@@ -28,19 +536,48 @@ impl<'text, 'raw> TryFrom<nojson::RawJsonValue<'text, 'raw>> for Layout {
     fn try_from(value: nojson::RawJsonValue<'text, 'raw>) -> Result<Self, Self::Error> {
         let mut keys = Vec::new();
         let mut preview = None;
+        let mut chords = None;
+        let mut border_style = None;
+        let mut label_overflow = None;
+        let mut copy_mode_color = None;
+        let mut shared_borders = false;
+        let mut hit_padding = 0;
+        let mut tray = Vec::new();
         let mut next_newline_rows = 1;
-        let mut default_size = tuinix::TerminalSize { rows: 3, cols: 3 };
+        let mut min_size = MIN_KEY_SIZE;
+        let mut default_size = MIN_KEY_SIZE;
+        let mut default_modifier_mode = ModifierMode::default();
         let mut position = tuinix::TerminalPosition::ZERO;
         let mut base_col = 0;
+
+        // Check the version before anything else so that newer directives are not misreported
+        for key_value in value.to_array()? {
+            if let Some(version_value) = key_value.to_member("version")?.get() {
+                let version: u64 = version_value.try_into()?;
+                if version == 0 {
+                    return Err(version_value.invalid("version must be a positive integer"));
+                }
+                if version > LAYOUT_FORMAT_VERSION {
+                    return Err(version_value.invalid(format!(
+                        "layout format version {version} is not supported by this tuke \
+                         (supported up to {LAYOUT_FORMAT_VERSION}); please upgrade tuke"
+                    )));
+                }
+            }
+        }
+
         for key_value in value.to_array()? {
+            if key_value.to_member("version")?.get().is_some() {
+                continue;
+            }
             if let Some(blank_count) = key_value.to_member("blank")?.get() {
                 let count: std::num::NonZeroUsize = blank_count.try_into()?;
                 position.col += count.get();
                 continue;
             }
-            if let Some(newline_count) = key_value.to_member("newline")?.get() {
-                let count: std::num::NonZeroUsize = newline_count.try_into()?;
-                position.col = base_col;
+            if let Some(newline_value) = key_value.to_member("newline")?.get() {
+                let (count, col) = parse_newline(newline_value)?;
+                position.col = col.unwrap_or(base_col);
                 position.row += next_newline_rows - 1 + count.get();
                 next_newline_rows = 1;
                 continue;
@@ -53,7 +590,84 @@ impl<'text, 'raw> TryFrom<nojson::RawJsonValue<'text, 'raw>> for Layout {
                 continue;
             }
             if let Some(default_size_value) = key_value.to_member("default_size")?.get() {
-                default_size = parse_size(default_size_value)?;
+                default_size = parse_size(default_size_value, min_size)?;
+                continue;
+            }
+            if let Some(mode_value) = key_value.to_member("default_modifier_mode")?.get() {
+                default_modifier_mode = mode_value.try_into()?;
+                continue;
+            }
+            if let Some(defaults_value) = key_value.to_member("defaults")?.get() {
+                // Shorthand for the `default_*` directives above, applying to the keys that follow
+                for (name_value, member_value) in defaults_value.to_object()? {
+                    match name_value.to_unquoted_string_str()?.as_ref() {
+                        "size" => default_size = parse_size(member_value, min_size)?,
+                        "modifier_mode" => default_modifier_mode = member_value.try_into()?,
+                        name => {
+                            return Err(name_value.invalid(format!(
+                                "unknown member in defaults: {name:?} (expected \"size\" or \"modifier_mode\")"
+                            )));
+                        }
+                    }
+                }
+                continue;
+            }
+            if let Some(min_size_value) = key_value.to_member("min_key_size")?.get() {
+                min_size = parse_size(min_size_value, MIN_KEY_SIZE)?;
+
+                // Keys without an explicit size must satisfy the new minimum too
+                default_size.rows = default_size.rows.max(min_size.rows);
+                default_size.cols = default_size.cols.max(min_size.cols);
+                continue;
+            }
+            if let Some(border_style_value) = key_value.to_member("border_style")?.get() {
+                border_style = Some(border_style_value.try_into()?);
+                continue;
+            }
+            if let Some(label_overflow_value) = key_value.to_member("label_overflow")?.get() {
+                label_overflow = Some(label_overflow_value.try_into()?);
+                continue;
+            }
+            if let Some(color_value) = key_value.to_member("copy_mode_color")?.get() {
+                copy_mode_color = Some(parse_color(color_value)?);
+                continue;
+            }
+            if let Some(shared_borders_value) = key_value.to_member("shared_borders")?.get() {
+                shared_borders = shared_borders_value.try_into()?;
+                continue;
+            }
+            if let Some(hit_padding_value) = key_value.to_member("hit_padding")?.get() {
+                hit_padding = hit_padding_value.try_into()?;
+                if hit_padding > MAX_HIT_PADDING {
+                    return Err(hit_padding_value
+                        .invalid(format!("hit_padding must be at most {MAX_HIT_PADDING}")));
+                }
+                continue;
+            }
+            if let Some(tray_value) = key_value.to_member("tray")?.get() {
+                // Laid out like a layout of its own, starting from the top left of the tray
+                let tray_layout = Layout::try_from(tray_value)?;
+                if tray_layout.keys.is_empty() {
+                    return Err(tray_value.invalid("tray must have at least one key"));
+                }
+                if !tray_layout.tray.is_empty()
+                    || tray_layout.keys.iter().any(|k| k.code == KeyCode::Tray)
+                {
+                    return Err(tray_value.invalid("trays cannot be nested"));
+                }
+                // The tray closes after one click, which would leave such keys stuck
+                if tray_layout
+                    .keys
+                    .iter()
+                    .any(|k| k.code.is_modifier() || matches!(k.code, KeyCode::Dead(_)))
+                {
+                    return Err(tray_value.invalid("tray keys cannot be modifiers or dead keys"));
+                }
+                tray = tray_layout.keys;
+                continue;
+            }
+            if let Some(chords_value) = key_value.to_member("chords")?.get() {
+                chords = Some(Chords::try_from(chords_value)?);
                 continue;
             }
             if let Some(preview_value) = key_value.to_member("preview")?.get() {
@@ -68,7 +682,21 @@ impl<'text, 'raw> TryFrom<nojson::RawJsonValue<'text, 'raw>> for Layout {
                 continue;
             }
 
-            let key = Key::parse(key_value, position, default_size)?;
+            let key = Key::parse(
+                key_value,
+                position,
+                default_size,
+                min_size,
+                default_modifier_mode,
+            )?;
+            if let Some(id) = &key.id
+                && keys.iter().any(|k: &Key| k.id.as_ref() == Some(id))
+            {
+                return Err(key_value
+                    .to_member("id")?
+                    .required()?
+                    .invalid(format!("duplicate key id {id:?}")));
+            }
 
             position = key.region.top_right();
             position.col += 1;
@@ -76,11 +704,173 @@ impl<'text, 'raw> TryFrom<nojson::RawJsonValue<'text, 'raw>> for Layout {
 
             keys.push(key);
         }
-        Ok(Self { keys, preview })
+        update_neighbors(&mut keys);
+        Ok(Self {
+            name: DEFAULT_LAYOUT_NAME.to_owned(),
+            keys,
+            preview,
+            chords,
+            border_style,
+            label_overflow,
+            copy_mode_color,
+            shared_borders,
+            hit_padding,
+            tray,
+        })
+    }
+}
+
+/// Largest column accepted by `{"newline": {"col": C}}`
+const MAX_NEWLINE_COLUMN: usize = 1024;
+
+/// Parses `{"newline": N}` or `{"newline": {"count": N, "col": C}}`
+///
+/// Returns the number of rows to advance and the column to start the next row at, if given.
+fn parse_newline(
+    value: nojson::RawJsonValue<'_, '_>,
+) -> Result<(std::num::NonZeroUsize, Option<usize>), nojson::JsonParseError> {
+    if value.kind() != nojson::JsonValueKind::Object {
+        return Ok((value.try_into()?, None));
+    }
+
+    let count = value
+        .to_member("count")?
+        .map(std::num::NonZeroUsize::try_from)?
+        .unwrap_or(std::num::NonZeroUsize::MIN);
+    let col = value.to_member("col")?.map(|col_value| {
+        let col: usize = col_value.try_into()?;
+        if col > MAX_NEWLINE_COLUMN {
+            return Err(col_value.invalid(format!("col must be at most {MAX_NEWLINE_COLUMN}")));
+        }
+        Ok(col)
+    })?;
+    Ok((count, col))
+}
+
+/// Upper limit of the `hit_padding` directive, beyond which clicks would land far from any key
+const MAX_HIT_PADDING: usize = 8;
+
+/// Returns the index of the region that a click at `position` activates
+///
+/// Clicks within `padding` cells of a region still hit it. Where the padded areas of several
+/// regions contain the click, the nearest region wins, so the gap between neighbors is split.
+/// Between equally near regions (e.g. overlapping keys), the last one wins, as it is drawn on top.
+pub fn hit_test(
+    regions: impl IntoIterator<Item = tuinix::TerminalRegion>,
+    position: tuinix::TerminalPosition,
+    padding: usize,
+) -> Option<usize> {
+    let distance = |start: usize, len: usize, p: usize| {
+        if p < start {
+            start - p
+        } else {
+            p.saturating_sub(start + len - 1)
+        }
+    };
+    regions
+        .into_iter()
+        .enumerate()
+        .filter(|(_, region)| !region.is_empty())
+        .filter_map(|(i, region)| {
+            let dy = distance(region.position.row, region.size.rows, position.row);
+            let dx = distance(region.position.col, region.size.cols, position.col);
+            let rank = (dx.max(dy), dx + dy, std::cmp::Reverse(i));
+            (dx.max(dy) <= padding).then_some((rank, i))
+        })
+        .min_by_key(|&(rank, _)| rank)
+        .map(|(_, i)| i)
+}
+
+/// Returns whether `a` and `b` share at least one cell
+pub(crate) fn regions_overlap(a: tuinix::TerminalRegion, b: tuinix::TerminalRegion) -> bool {
+    a.position.row < b.position.row + b.size.rows
+        && b.position.row < a.position.row + a.size.rows
+        && a.position.col < b.position.col + b.size.cols
+        && b.position.col < a.position.col + a.size.cols
+}
+
+/// Records which sides of each key are flush against a key of the same height or width
+fn update_neighbors(keys: &mut [Key]) {
+    let regions = keys.iter().map(|k| k.region).collect::<Vec<_>>();
+    let is_flush_right = |a: &tuinix::TerminalRegion, b: &tuinix::TerminalRegion| {
+        a.position.row == b.position.row
+            && a.size.rows == b.size.rows
+            && a.position.col + a.size.cols == b.position.col
+    };
+    let is_flush_below = |a: &tuinix::TerminalRegion, b: &tuinix::TerminalRegion| {
+        a.position.col == b.position.col
+            && a.size.cols == b.size.cols
+            && a.position.row + a.size.rows == b.position.row
+    };
+    for key in keys {
+        let r = &key.region;
+        key.neighbors = Neighbors {
+            left: regions.iter().any(|o| is_flush_right(o, r)),
+            top: regions.iter().any(|o| is_flush_below(o, r)),
+            right: regions.iter().any(|o| is_flush_right(r, o)),
+            bottom: regions.iter().any(|o| is_flush_below(r, o)),
+        };
+    }
+}
+
+/// Combinations of two keys that produce another key when clicked in quick succession
+///
+/// Chords are only enabled when a layout has a `"chords"` directive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chords {
+    pub timeout: std::time::Duration,
+    pub entries: Vec<Chord>,
+}
+
+impl Chords {
+    pub fn find(&self, first: &KeyCode, second: &KeyCode) -> Option<&KeyCode> {
+        self.entries
+            .iter()
+            .find(|c| {
+                (c.keys[0] == *first && c.keys[1] == *second)
+                    || (c.keys[0] == *second && c.keys[1] == *first)
+            })
+            .map(|c| &c.output)
+    }
+
+    pub fn contains(&self, code: &KeyCode) -> bool {
+        self.entries.iter().any(|c| c.keys.contains(code))
+    }
+}
+
+impl<'text, 'raw> TryFrom<nojson::RawJsonValue<'text, 'raw>> for Chords {
+    type Error = nojson::JsonParseError;
+
+    fn try_from(value: nojson::RawJsonValue<'text, 'raw>) -> Result<Self, Self::Error> {
+        let timeout = value
+            .to_member("timeout_ms")?
+            .map(crate::tap_hold::parse_millis)?
+            .unwrap_or(std::time::Duration::from_millis(150));
+
+        let mut entries = Vec::new();
+        for entry_value in value.to_member("entries")?.required()?.to_array()? {
+            let keys_value = entry_value.to_member("keys")?.required()?;
+            let keys: Vec<KeyCode> = keys_value.try_into()?;
+            let keys: [KeyCode; 2] = keys
+                .try_into()
+                .map_err(|_| keys_value.invalid("a chord must consist of exactly two keys"))?;
+            if keys.iter().any(|k| !k.is_sendable()) {
+                return Err(keys_value.invalid("modifier keys cannot be part of a chord"));
+            }
+            let output = entry_value.to_member("output")?.required()?.try_into()?;
+            entries.push(Chord { keys, output });
+        }
+        Ok(Self { timeout, entries })
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chord {
+    pub keys: [KeyCode; 2],
+    pub output: KeyCode,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 struct SentKey {
     code: KeyCode,
     ctrl: bool,
@@ -93,7 +883,7 @@ impl SentKey {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Preview {
     pub region: tuinix::TerminalRegion,
     history: Vec<SentKey>,
@@ -156,22 +946,113 @@ impl Preview {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Key {
+    /// Name that identifies the key within its layout, if given
+    pub id: Option<String>,
+
     pub code: KeyCode,
     pub shift_code: KeyCode,
     pub region: tuinix::TerminalRegion,
+    pub target: Option<PaneTarget>,
+    pub mnemonic: Option<char>,
+    pub modifier_mode: ModifierMode,
+
+    /// Text drawn instead of the key code (e.g. `"␣"` for a space bar)
+    pub label: Option<String>,
+
+    /// Short description drawn below the label on keys that are tall enough (e.g. `↵`)
+    pub hint: Option<String>,
+
+    /// tmux format whose value is periodically shown as the label (e.g. `"#{window_name}"`)
+    pub label_format: Option<String>,
+
+    /// Number of times the key is sent per press (e.g. `5` for a "Down×5" key)
+    pub repeat_count: std::num::NonZeroUsize,
+
+    /// Whether holding the key repeats it or sends `shift_code` (see [`crate::tap_hold`])
+    pub tap_hold: Option<TapHoldConfig>,
+
+    /// Condition under which the key is shown; otherwise it is neither drawn nor clickable
+    pub when: Option<KeyCondition>,
+
+    /// Key sent some time after a special key's action to undo it (e.g. `q` to leave copy mode)
+    pub revert: Option<Revert>,
+
+    /// Sides of the key that touch another key (see [`Layout::shared_borders`])
+    pub neighbors: Neighbors,
+}
+
+/// Which sides of a key are flush against another key
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Neighbors {
+    pub left: bool,
+    pub top: bool,
+    pub right: bool,
+    pub bottom: bool,
 }
 
 impl Key {
+    /// Returns a key that sends `code`, with no optional members set
+    pub(crate) fn new(code: KeyCode, region: tuinix::TerminalRegion) -> Self {
+        Self {
+            id: None,
+            shift_code: code.default_shift_code(),
+            code,
+            region,
+            target: None,
+            mnemonic: None,
+            modifier_mode: ModifierMode::default(),
+            label: None,
+            hint: None,
+            label_format: None,
+            repeat_count: std::num::NonZeroUsize::MIN,
+            tap_hold: None,
+            when: None,
+            revert: None,
+            neighbors: Neighbors::default(),
+        }
+    }
+
+    /// Fails if the key is smaller than [`MIN_KEY_SIZE`], which leaves no room for its borders and label
+    ///
+    /// Layout files cannot make such keys, but layouts built or edited in code can.
+    pub fn check_size(&self) -> orfail::Result<()> {
+        let tuinix::TerminalRegion { position, size } = self.region;
+        if size.cols < MIN_KEY_SIZE.cols || size.rows < MIN_KEY_SIZE.rows {
+            return Err(orfail::Failure::new(format!(
+                "key {:?} at row {}, column {} is smaller than {}x{}",
+                self.code.to_string(),
+                position.row,
+                position.col,
+                MIN_KEY_SIZE.cols,
+                MIN_KEY_SIZE.rows
+            )));
+        }
+        Ok(())
+    }
+
     fn parse(
         value: nojson::RawJsonValue<'_, '_>,
         position: tuinix::TerminalPosition,
         default_size: tuinix::TerminalSize,
+        min_size: tuinix::TerminalSize,
+        default_modifier_mode: ModifierMode,
     ) -> Result<Self, nojson::JsonParseError> {
-        let code: KeyCode = value.to_member("key")?.required()?.try_into()?;
+        let code = KeyCode::parse_key_member(value)?;
+
+        let id = value.to_member("id")?.map(|v| {
+            let id = v.to_unquoted_string_str()?;
+            if id.is_empty() {
+                return Err(v.invalid("id must not be empty"));
+            }
+            Ok(id.into_owned())
+        })?;
 
         let shift_code = if let Some(shift) = value.to_member("shift")?.get() {
+            if code.is_special() || code.is_modifier() {
+                return Err(shift.invalid("shift can only be set on keys that send a key"));
+            }
             shift.try_into()?
         } else {
             code.default_shift_code()
@@ -179,57 +1060,542 @@ impl Key {
 
         let size = value
             .to_member("size")?
-            .map(parse_size)?
+            .map(|v| parse_size(v, min_size))?
             .unwrap_or(default_size);
 
         let region = tuinix::TerminalRegion { position, size };
 
-        Ok(Self {
-            code,
-            shift_code,
-            region,
-        })
-    }
-}
+        let target = value.to_member("target")?.map(PaneTarget::try_from)?;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum KeyCode {
-    Char(char),
-    Shift,
-    Ctrl,
-    Alt,
-    Up,
-    Down,
-    Left,
-    Right,
-    Enter,
-    Backspace,
-    Delete,
+        let mnemonic = value.to_member("mnemonic")?.map(|v| {
+            let s = v.to_unquoted_string_str()?;
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                // Plain `q` always quits, even with `--no-mouse`
+                (Some('q'), None) => Err(v.invalid("mnemonic \"q\" is reserved for quitting")),
+                (Some(c), None) if c.is_ascii_graphic() => Ok(c),
+                _ => Err(v.invalid("mnemonic must be a single printable ASCII character")),
+            }
+        })?;
+
+        let modifier_mode = match value.to_member("modifier_mode")?.get() {
+            Some(_) if !code.is_modifier() => {
+                return Err(value.invalid("modifier_mode can only be set on modifier keys"));
+            }
+            Some(mode_value) => mode_value.try_into()?,
+            None => default_modifier_mode,
+        };
+
+        let label = value
+            .to_member("label")?
+            .map(|v| v.to_unquoted_string_str().map(|s| s.into_owned()))?;
+
+        let hint = value
+            .to_member("hint")?
+            .map(|v| v.to_unquoted_string_str().map(|s| s.into_owned()))?;
+
+        let label_format = value.to_member("label_format")?.map(|v| {
+            let format = v.to_unquoted_string_str()?;
+            if format.is_empty() {
+                return Err(v.invalid("label_format must not be empty"));
+            }
+            Ok(format.into_owned())
+        })?;
+
+        let repeat_count = match value.to_member("repeat_count")?.get() {
+            Some(_) if code.is_special() || code.is_modifier() => {
+                return Err(value.invalid("repeat_count can only be set on keys that send a key"));
+            }
+            Some(count_value) => {
+                let count: usize = count_value.try_into()?;
+                std::num::NonZeroUsize::new(count)
+                    .ok_or_else(|| count_value.invalid("repeat_count must be at least 1"))?
+            }
+            None => std::num::NonZeroUsize::MIN,
+        };
+
+        let tap_hold = match value.to_member("tap_hold")?.get() {
+            Some(_) if code.is_special() || code.is_modifier() => {
+                return Err(value.invalid("tap_hold can only be set on keys that send a key"));
+            }
+            Some(tap_hold_value) => Some(tap_hold_value.try_into()?),
+            None => None,
+        };
+
+        let when = value.to_member("when")?.map(KeyCondition::try_from)?;
+
+        let revert = match value.to_member("revert")?.get() {
+            Some(_) if !code.is_special() => {
+                return Err(value.invalid("revert can only be set on special keys"));
+            }
+            Some(revert_value) => Some(revert_value.try_into()?),
+            None => None,
+        };
+
+        Ok(Self {
+            id,
+            code,
+            shift_code,
+            region,
+            target,
+            mnemonic,
+            modifier_mode,
+            label,
+            hint,
+            label_format,
+            repeat_count,
+            tap_hold,
+            when,
+            revert,
+            neighbors: Neighbors::default(),
+        })
+    }
+
+    /// Returns `true` if the key has no `"when"` condition or the condition holds in `context`
+    pub fn is_enabled(&self, context: &KeyContext) -> bool {
+        self.when.is_none_or(|condition| condition.holds(context))
+    }
+
+    /// Returns the `send-keys` options that make tmux send the key `repeat_count` times
+    pub fn repeat_args(&self) -> Vec<String> {
+        match self.repeat_count.get() {
+            1 => Vec::new(),
+            n => vec!["-N".to_owned(), n.to_string()],
+        }
+    }
+}
+
+/// `"revert"` member of a momentary special key
+///
+/// The key performs its action, stays pressed, and sends `code` to its target after `after`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Revert {
+    pub code: KeyCode,
+    pub after: std::time::Duration,
+}
+
+impl<'text, 'raw> TryFrom<nojson::RawJsonValue<'text, 'raw>> for Revert {
+    type Error = nojson::JsonParseError;
+
+    fn try_from(value: nojson::RawJsonValue<'text, 'raw>) -> Result<Self, Self::Error> {
+        let code = KeyCode::parse_key_member(value)?;
+        if code.is_special() || code.is_modifier() {
+            return Err(value.invalid("revert key must be a key that is sent to the pane"));
+        }
+        let after = value
+            .to_member("after_ms")?
+            .map(crate::tap_hold::parse_millis)?
+            .unwrap_or(DEFAULT_REVERT_AFTER);
+        Ok(Self { code, after })
+    }
+}
+
+/// State of the app that the `"when"` conditions of keys are evaluated against
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct KeyContext {
+    /// Whether the target pane is in copy mode (or another mode)
+    pub copy_mode: bool,
+
+    /// Whether a selection has been started in copy mode
+    pub has_selection: bool,
+
+    /// Index of the target pane
+    pub pane_index: usize,
+}
+
+/// Condition of a key's `"when"` member
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyCondition {
+    /// `"copy_mode"`: the target pane is in copy mode
+    CopyMode,
+
+    /// `"has_selection"`: text is being selected in copy mode
+    HasSelection,
+
+    /// `"pane:N"`: the target pane is pane N
+    Pane(usize),
+}
+
+impl KeyCondition {
+    pub fn holds(self, context: &KeyContext) -> bool {
+        match self {
+            Self::CopyMode => context.copy_mode,
+            Self::HasSelection => context.has_selection,
+            Self::Pane(index) => context.pane_index == index,
+        }
+    }
+}
+
+impl<'text, 'raw> TryFrom<nojson::RawJsonValue<'text, 'raw>> for KeyCondition {
+    type Error = nojson::JsonParseError;
+
+    fn try_from(value: nojson::RawJsonValue<'text, 'raw>) -> Result<Self, Self::Error> {
+        let s = value.to_unquoted_string_str()?;
+        match s.as_ref() {
+            "copy_mode" => Ok(Self::CopyMode),
+            "has_selection" => Ok(Self::HasSelection),
+            _ => {
+                // Only plain digits, so that e.g. "pane:+1" is not taken as pane 1
+                if let Some(index) = s.strip_prefix("pane:")
+                    && !index.is_empty()
+                    && index.bytes().all(|b| b.is_ascii_digit())
+                {
+                    let index = index
+                        .parse()
+                        .map_err(|_| value.invalid("pane index is too large"))?;
+                    Ok(Self::Pane(index))
+                } else {
+                    Err(value.invalid(
+                        "condition must be \"copy_mode\", \"has_selection\" or \"pane:N\"",
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// How an on-screen modifier key applies to other keys
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ModifierMode {
+    /// Clicking the modifier latches it for the next key (or locks it when clicked twice)
+    #[default]
+    Latch,
+
+    /// The modifier only applies while its button is held down,
+    /// i.e. when dragging from the modifier and releasing over another key
+    Momentary,
+}
+
+impl<'text, 'raw> TryFrom<nojson::RawJsonValue<'text, 'raw>> for ModifierMode {
+    type Error = nojson::JsonParseError;
+
+    fn try_from(value: nojson::RawJsonValue<'text, 'raw>) -> Result<Self, Self::Error> {
+        match value.to_unquoted_string_str()?.as_ref() {
+            "latch" => Ok(Self::Latch),
+            "momentary" => Ok(Self::Momentary),
+            _ => Err(value.invalid("modifier mode must be \"latch\" or \"momentary\"")),
+        }
+    }
+}
+
+/// Pane to which keys are sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaneTarget {
+    /// Pane index within the current window (e.g. `2`)
+    Index(usize),
+
+    /// Unique pane ID (e.g. `"%5"`)
+    Id(usize),
+}
+
+impl std::fmt::Display for PaneTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Index(i) => write!(f, ":.{i}"),
+            Self::Id(id) => write!(f, "%{id}"),
+        }
+    }
+}
+
+impl<'text, 'raw> TryFrom<nojson::RawJsonValue<'text, 'raw>> for PaneTarget {
+    type Error = nojson::JsonParseError;
+
+    fn try_from(value: nojson::RawJsonValue<'text, 'raw>) -> Result<Self, Self::Error> {
+        if value.kind() == nojson::JsonValueKind::Integer {
+            return value.try_into().map(Self::Index);
+        }
+
+        let s = value.to_unquoted_string_str()?;
+        if let Some(id) = s.strip_prefix('%')
+            && !id.is_empty()
+            && id.bytes().all(|b| b.is_ascii_digit())
+        {
+            let id = id
+                .parse()
+                .map_err(|_| value.invalid("pane ID is too large"))?;
+            Ok(Self::Id(id))
+        } else {
+            Err(value
+                .invalid("pane target must be a pane index (e.g. 2) or a pane ID (e.g. \"%5\")"))
+        }
+    }
+}
+
+/// Key to send or action to perform
+///
+/// In layout files, keys are written in tmux notation (e.g. `"Enter"`, `"BSpace"`).
+/// Key names are case-insensitive (e.g. `"enter"` and `"ENTER"`), while single characters are not
+/// (`"A"` and `"a"` differ). The following aliases are also accepted:
+///
+/// - `"Return"` for `"Enter"`
+/// - `"Esc"` for `"Escape"`
+/// - `"Del"` for `"Delete"`
+/// - `"BackSpace"` for `"BSpace"`
+/// - `"Space"` for `" "`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum KeyCode {
+    Char(char),
+    Shift,
+    Ctrl,
+    Alt,
+    Up,
+    Down,
+    Left,
+    Right,
+    Enter,
+    Escape,
+
+    /// Deletes the character before the cursor (`BSpace` in tmux, which sends `^?`)
+    Backspace,
+
+    /// Deletes the character under the cursor (forward delete, `Delete` in tmux)
+    Delete,
     Tab,
     BackTab,
+    SwitchLayout(String),
+    SetBuffer {
+        text: String,
+    },
+    Paste,
+
+    /// Cycles through modifier combinations to apply to the next key
+    ModifierCycle,
+
+    /// Types text that is evaluated when the key is pressed
+    Dynamic(DynamicKind),
+
+    /// Applies an accent (e.g. `´`) to the next character key
+    Dead(char),
+
+    /// Shows the recent paste buffers as temporary buttons
+    PasteHistory,
+
+    /// Pastes a specific paste buffer (only used by the paste history panel)
+    PasteBuffer {
+        name: String,
+        sample: String,
+    },
+
+    /// Replaces the layouts with those of a file (only used by the layout picker)
+    LoadLayoutFile(PathBuf),
+
+    /// Types the contents of a file, read when the key is pressed (e.g. a template)
+    Snippet {
+        path: PathBuf,
+    },
+
+    /// Runs the command bound to `key` in the tmux key table `table` (e.g. `c` in `prefix`)
+    ///
+    /// Unless the key has a label, it is labeled with the note of the binding.
+    Binding {
+        table: String,
+        key: String,
+    },
+
+    /// Saves the contents of the target pane to a file
+    ///
+    /// `start` and `end` are passed to `capture-pane -S`/`-E` (line numbers or `-`).
+    CapturePane {
+        path: PathBuf,
+        start: Option<String>,
+        end: Option<String>,
+    },
+    NextWindow,
+    PrevWindow,
+
+    /// Toggles sending keys to every other pane in the window instead of the target pane
+    ///
+    /// Text typed by `Snippet` and `Dynamic` keys is broadcast too, but scrolling is not.
+    Broadcast,
+
+    /// Starts entering a number (e.g. a count prefix) with the digit keys
+    NumberEntry,
+
+    /// Shows or hides the keys of the layout's `"tray"` over the keyboard
+    Tray,
+
+    /// Toggles queueing keys instead of sending them right away
+    QueueMode,
+
+    /// Sends the queued keys
+    FlushQueue,
+
+    /// Removes the last queued key
+    Unqueue,
+
+    /// Shows the pane numbers, after which the next digit key selects that pane
+    DisplayPanes,
+
+    /// Cycles the pane border status lines (`pane-border-status`) through off, top and bottom
+    TogglePaneBorders,
+
+    /// Scrolls the target pane up by half a page, entering copy mode if needed
+    ScrollUp,
+
+    /// Scrolls the target pane down by half a page while in copy mode
+    ScrollDown,
+
+    /// Repaints the whole keyboard (e.g. after output from another process garbled it)
+    Redraw,
 }
 
 impl KeyCode {
-    pub fn is_modifier(self) -> bool {
+    /// Parses the `"key"` member of a key object, including the extra members of parameterized keys
+    fn parse_key_member(
+        value: nojson::RawJsonValue<'_, '_>,
+    ) -> Result<Self, nojson::JsonParseError> {
+        let Some(code_value) = value.to_member("key")?.get() else {
+            // Typos in directive names also end up here, so mention both possibilities
+            return Err(value.invalid(
+                "layout element must be a key (with a \"key\" member) or a known directive \
+                 (e.g. \"newline\" or \"blank\")",
+            ));
+        };
+        match code_value.to_unquoted_string_str()?.as_ref() {
+            "SwitchLayout" => {
+                let name = value.to_member("layout")?.required()?;
+                Ok(Self::SwitchLayout(
+                    name.to_unquoted_string_str()?.into_owned(),
+                ))
+            }
+            "Dynamic" => DynamicKind::parse(value).map(Self::Dynamic),
+            "Dead" => {
+                let accent_value = value.to_member("accent")?.required()?;
+                let name = accent_value.to_unquoted_string_str()?;
+                let Some(&(_, accent)) = crate::compose::ACCENTS.iter().find(|(n, _)| *n == name)
+                else {
+                    let names = crate::compose::ACCENTS
+                        .iter()
+                        .map(|(n, _)| format!("{n:?}"))
+                        .collect::<Vec<_>>();
+                    return Err(
+                        accent_value.invalid(format!("accent must be one of {}", names.join(", ")))
+                    );
+                };
+                Ok(Self::Dead(accent))
+            }
+            "SetBuffer" => {
+                let text_value = value.to_member("text")?.required()?;
+                let text = text_value.to_unquoted_string_str()?.into_owned();
+                if text.is_empty() {
+                    return Err(text_value.invalid("text must not be empty"));
+                }
+                Ok(Self::SetBuffer { text })
+            }
+            "Snippet" => Ok(Self::Snippet {
+                path: parse_path_member(value)?,
+            }),
+            "Binding" => {
+                let key_value = value.to_member("binding")?.required()?;
+                let key = key_value.to_unquoted_string_str()?.into_owned();
+                if key.is_empty() {
+                    return Err(key_value.invalid("binding must not be empty"));
+                }
+                let table = value
+                    .to_member("table")?
+                    .map(|v| v.to_unquoted_string_str().map(|s| s.into_owned()))?
+                    .unwrap_or_else(|| DEFAULT_BINDING_TABLE.to_owned());
+                Ok(Self::Binding { table, key })
+            }
+            "CapturePane" => Ok(Self::CapturePane {
+                path: parse_path_member(value)?,
+                start: value.to_member("start")?.map(parse_capture_line)?,
+                end: value.to_member("end")?.map(parse_capture_line)?,
+            }),
+            _ => code_value.try_into(),
+        }
+    }
+
+    pub fn is_modifier(&self) -> bool {
         matches!(self, Self::Shift | Self::Ctrl | Self::Alt)
     }
 
-    pub fn is_modifiable(self) -> bool {
+    /// Returns `true` if this key runs a tmux command other than `send-keys` or changes the app state
+    pub fn is_special(&self) -> bool {
+        matches!(
+            self,
+            Self::SwitchLayout(_)
+                | Self::SetBuffer { .. }
+                | Self::Paste
+                | Self::ModifierCycle
+                | Self::Dynamic(_)
+                | Self::Dead(_)
+                | Self::PasteHistory
+                | Self::PasteBuffer { .. }
+                | Self::LoadLayoutFile(_)
+                | Self::Snippet { .. }
+                | Self::CapturePane { .. }
+                | Self::Binding { .. }
+                | Self::NextWindow
+                | Self::PrevWindow
+                | Self::Broadcast
+                | Self::NumberEntry
+                | Self::Tray
+                | Self::QueueMode
+                | Self::FlushQueue
+                | Self::Unqueue
+                | Self::DisplayPanes
+                | Self::TogglePaneBorders
+                | Self::ScrollUp
+                | Self::ScrollDown
+                | Self::Redraw
+        )
+    }
+
+    pub fn is_window_navigation(&self) -> bool {
+        matches!(self, Self::NextWindow | Self::PrevWindow)
+    }
+
+    /// Returns the symbol drawn instead of the name when [`RenderOptions::glyph_labels`] is set
+    pub fn glyph(&self) -> Option<char> {
+        let glyph = match self {
+            Self::Enter => '⏎',
+            Self::Backspace => '⌫',
+            Self::Delete => '⌦',
+            Self::Tab => '⇥',
+            Self::BackTab => '⇤',
+            Self::Escape => '⎋',
+            Self::Up => '↑',
+            Self::Down => '↓',
+            Self::Left => '←',
+            Self::Right => '→',
+            _ => return None,
+        };
+        Some(glyph)
+    }
+
+    /// Returns `true` if this key is sent to tmux via `send-keys`
+    pub fn is_sendable(&self) -> bool {
+        !(self.is_modifier() || self.is_special())
+    }
+
+    pub fn is_modifiable(&self) -> bool {
         matches!(
             self,
             Self::Char(_) | Self::Up | Self::Down | Self::Left | Self::Right
         )
     }
 
-    pub fn is_char(self) -> bool {
+    pub fn is_char(&self) -> bool {
         matches!(self, Self::Char(_))
     }
 
-    pub fn default_shift_code(self) -> Self {
+    /// Returns `Delete` for `Backspace` and vice versa, or the key itself for other keys
+    ///
+    /// This is for applications that treat the two keys the other way around.
+    pub fn swap_delete(&self) -> Self {
+        match self {
+            Self::Backspace => Self::Delete,
+            Self::Delete => Self::Backspace,
+            other => other.clone(),
+        }
+    }
+
+    pub fn default_shift_code(&self) -> Self {
         match self {
             Self::Char(c) => Self::Char(c.to_ascii_uppercase()),
             Self::Tab => Self::BackTab,
-            other => other,
+            other => other.clone(),
         }
     }
 }
@@ -247,10 +1613,98 @@ impl std::fmt::Display for KeyCode {
             Self::Left => write!(f, "Left"),
             Self::Right => write!(f, "Right"),
             Self::Enter => write!(f, "Enter"),
+            Self::Escape => write!(f, "Escape"),
             Self::Backspace => write!(f, "BSpace"),
             Self::Delete => write!(f, "Delete"),
             Self::Tab => write!(f, "Tab"),
             Self::BackTab => write!(f, "BTab"),
+            // Special - not sent to tmux
+            Self::SwitchLayout(name) => write!(f, "{name}"),
+            Self::SetBuffer { text } => write!(f, "{text}"),
+            Self::Paste => write!(f, "Paste"),
+            Self::ModifierCycle => write!(f, "ModifierCycle"),
+            Self::Dynamic(kind) => write!(f, "{kind}"),
+            Self::Dead(accent) => write!(f, "{accent}"),
+            Self::PasteHistory => write!(f, "PasteHistory"),
+            Self::PasteBuffer { sample, .. } => write!(f, "{sample}"),
+            Self::LoadLayoutFile(path) | Self::Snippet { path } => {
+                let name = path.file_stem().unwrap_or(path.as_os_str());
+                write!(f, "{}", name.to_string_lossy())
+            }
+            Self::CapturePane { .. } => write!(f, "CapturePane"),
+            Self::Binding { key, .. } => write!(f, "{key}"),
+            Self::NextWindow => write!(f, "NextWindow"),
+            Self::PrevWindow => write!(f, "PrevWindow"),
+            Self::Broadcast => write!(f, "Broadcast"),
+            Self::NumberEntry => write!(f, "NumberEntry"),
+            Self::Tray => write!(f, "Tray"),
+            Self::QueueMode => write!(f, "QueueMode"),
+            Self::FlushQueue => write!(f, "FlushQueue"),
+            Self::Unqueue => write!(f, "Unqueue"),
+            Self::DisplayPanes => write!(f, "DisplayPanes"),
+            Self::TogglePaneBorders => write!(f, "TogglePaneBorders"),
+            Self::ScrollUp => write!(f, "ScrollUp"),
+            Self::ScrollDown => write!(f, "ScrollDown"),
+            Self::Redraw => write!(f, "Redraw"),
+        }
+    }
+}
+
+/// Names accepted by [`KeyCode::from_token()`], which are also matched case-insensitively
+const KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("S-", KeyCode::Shift),
+    ("C-", KeyCode::Ctrl),
+    ("M-", KeyCode::Alt),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Enter", KeyCode::Enter),
+    ("Return", KeyCode::Enter),
+    ("Escape", KeyCode::Escape),
+    ("Esc", KeyCode::Escape),
+    ("BSpace", KeyCode::Backspace),
+    ("BackSpace", KeyCode::Backspace),
+    ("Delete", KeyCode::Delete),
+    ("Del", KeyCode::Delete),
+    ("Tab", KeyCode::Tab),
+    ("Space", KeyCode::Char(' ')),
+    ("BTab", KeyCode::BackTab),
+    ("Paste", KeyCode::Paste),
+    ("PasteHistory", KeyCode::PasteHistory),
+    ("ModifierCycle", KeyCode::ModifierCycle),
+    ("NextWindow", KeyCode::NextWindow),
+    ("PrevWindow", KeyCode::PrevWindow),
+    ("Broadcast", KeyCode::Broadcast),
+    ("NumberEntry", KeyCode::NumberEntry),
+    ("Tray", KeyCode::Tray),
+    ("QueueMode", KeyCode::QueueMode),
+    ("FlushQueue", KeyCode::FlushQueue),
+    ("Unqueue", KeyCode::Unqueue),
+    ("DisplayPanes", KeyCode::DisplayPanes),
+    ("TogglePaneBorders", KeyCode::TogglePaneBorders),
+    ("ScrollUp", KeyCode::ScrollUp),
+    ("ScrollDown", KeyCode::ScrollDown),
+    ("Redraw", KeyCode::Redraw),
+];
+
+impl KeyCode {
+    /// Parses a key code written as a single token (e.g. `"Enter"` or `"a"`)
+    ///
+    /// Parameterized keys such as `SwitchLayout` cannot be written this way.
+    pub fn from_token(s: &str) -> Option<Self> {
+        // All names are longer than a character, so single characters keep their case
+        if let Some((_, code)) = KEY_NAMES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+        {
+            return Some(code.clone());
+        }
+        let c = s.chars().next()?;
+        if s.len() == 1 && matches!(c, 'a'..='z' | '0'..='9' | '!'..='~' | ' ') {
+            Some(Self::Char(c))
+        } else {
+            None
         }
     }
 }
@@ -259,46 +1713,139 @@ impl<'text, 'raw> TryFrom<nojson::RawJsonValue<'text, 'raw>> for KeyCode {
     type Error = nojson::JsonParseError;
 
     fn try_from(value: nojson::RawJsonValue<'text, 'raw>) -> Result<Self, Self::Error> {
-        match value.to_unquoted_string_str()?.as_ref() {
-            "S-" => Ok(Self::Shift),
-            "C-" => Ok(Self::Ctrl),
-            "M-" => Ok(Self::Alt),
-            "Up" => Ok(Self::Up),
-            "Down" => Ok(Self::Down),
-            "Left" => Ok(Self::Left),
-            "Right" => Ok(Self::Right),
-            "Enter" => Ok(Self::Enter),
-            "BSpace" => Ok(Self::Backspace),
-            "Delete" => Ok(Self::Delete),
-            "Tab" => Ok(Self::Tab),
-            "BTab" => Ok(Self::BackTab),
-            s => {
-                if let Some(c) = s.chars().next()
-                    && s.len() == 1
-                    && matches!(c, 'a'..='z' | '0'..='9' | '!'..='~' | ' ')
-                {
-                    Ok(Self::Char(c))
-                } else {
-                    Err(value.invalid("unknown key code"))
+        let s = value.to_unquoted_string_str()?;
+        if let Some(hex) = s.strip_prefix("U+") {
+            // Only hex digits, as from_str_radix() would also take a sign
+            return (!hex.is_empty()
+                && hex.len() <= 6
+                && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+            .then(|| u32::from_str_radix(hex, 16).ok())
+            .flatten()
+            .and_then(char::from_u32)
+            .filter(|c| !c.is_control())
+            .map(Self::Char)
+            .ok_or_else(|| {
+                value.invalid("code point must be a printable character (e.g. \"U+00E9\")")
+            });
+        }
+        if let Some(code) = Self::from_token(&s) {
+            return Ok(code);
+        }
+
+        // Characters beyond ASCII (e.g. "é" or "\u00e9") are typed as they are
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if !c.is_control() => Ok(Self::Char(c)),
+            _ => Err(value.invalid("unknown key code")),
+        }
+    }
+}
+
+/// Source of the text typed by a `Dynamic` key
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DynamicKind {
+    /// Current local time formatted with `strftime(3)` specifiers (e.g. `"%Y-%m-%d"`)
+    DateTime { format: String },
+
+    /// Current working directory of the target pane
+    PanePath,
+
+    /// Name of the user running tmux
+    User,
+}
+
+impl DynamicKind {
+    const STRFTIME_SPECIFIERS: &str = "aAbBcCdDeFgGhHIjklmMnprRsStTuUVwWxXyYzZ%+";
+
+    fn parse(value: nojson::RawJsonValue<'_, '_>) -> Result<Self, nojson::JsonParseError> {
+        let kind_value = value.to_member("kind")?.required()?;
+        match kind_value.to_unquoted_string_str()?.as_ref() {
+            "datetime" => {
+                let format_value = value.to_member("format")?.required()?;
+                let format = format_value.to_unquoted_string_str()?.into_owned();
+                let mut chars = format.chars();
+                while let Some(c) = chars.next() {
+                    if c == '%'
+                        && !chars
+                            .next()
+                            .is_some_and(|c| Self::STRFTIME_SPECIFIERS.contains(c))
+                    {
+                        return Err(format_value.invalid("unknown conversion specifier in format"));
+                    }
                 }
+                Ok(Self::DateTime { format })
             }
+            "pane_path" => Ok(Self::PanePath),
+            "user" => Ok(Self::User),
+            _ => Err(kind_value.invalid("kind must be \"datetime\", \"pane_path\" or \"user\"")),
+        }
+    }
+
+    /// Returns the tmux format (for `display-message -p`) that evaluates to the text
+    pub fn tmux_format(&self) -> String {
+        match self {
+            // tmux expands strftime specifiers itself, but '#' starts a tmux format
+            Self::DateTime { format } => format.replace('#', "##"),
+            Self::PanePath => "#{pane_current_path}".to_owned(),
+            Self::User => "#{client_user}".to_owned(),
         }
     }
 }
 
+impl std::fmt::Display for DynamicKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DateTime { format } => write!(f, "{format}"),
+            Self::PanePath => write!(f, "PanePath"),
+            Self::User => write!(f, "User"),
+        }
+    }
+}
+
+/// Parses the required `"path"` member of a key
+fn parse_path_member(
+    value: nojson::RawJsonValue<'_, '_>,
+) -> Result<PathBuf, nojson::JsonParseError> {
+    let path_value = value.to_member("path")?.required()?;
+    let path = path_value.to_unquoted_string_str()?;
+    if path.is_empty() {
+        return Err(path_value.invalid("path must not be empty"));
+    }
+    Ok(PathBuf::from(path.as_ref()))
+}
+
+/// Parses a `capture-pane` line number (negative for the history) or `"-"` for the very start or end
+fn parse_capture_line(
+    value: nojson::RawJsonValue<'_, '_>,
+) -> Result<String, nojson::JsonParseError> {
+    if value.kind() == nojson::JsonValueKind::String {
+        return match value.to_unquoted_string_str()?.as_ref() {
+            "-" => Ok("-".to_owned()),
+            _ => Err(value.invalid("line must be an integer or \"-\"")),
+        };
+    }
+    let line: i64 = value.try_into()?;
+    Ok(line.to_string())
+}
+
 fn parse_size(
     value: nojson::RawJsonValue<'_, '_>,
+    min_size: tuinix::TerminalSize,
 ) -> Result<tuinix::TerminalSize, nojson::JsonParseError> {
+    if value.to_member("width")?.get().is_none() || value.to_member("height")?.get().is_none() {
+        return Err(value.invalid("size must specify both \"width\" and \"height\""));
+    }
+
     let width_value = value.to_member("width")?.required()?;
     let width = width_value.try_into()?;
-    if width < 3 {
-        return Err(width_value.invalid("width must be at least 3"));
+    if width < min_size.cols {
+        return Err(width_value.invalid(format!("width must be at least {}", min_size.cols)));
     }
 
     let height_value = value.to_member("height")?.required()?;
     let height = height_value.try_into()?;
-    if height < 3 {
-        return Err(height_value.invalid("height must be at least 3"));
+    if height < min_size.rows {
+        return Err(height_value.invalid(format!("height must be at least {}", min_size.rows)));
     }
 
     Ok(tuinix::TerminalSize {
@@ -307,85 +1854,1550 @@ fn parse_size(
     })
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum KeyPressState {
-    Neutral,
-    Activated,
-    OneshotActivated,
-    Pressed,
-}
+/// Parses a color name (e.g. `"yellow"`) or a `"#rrggbb"` hex color
+fn parse_color(
+    value: nojson::RawJsonValue<'_, '_>,
+) -> Result<tuinix::TerminalColor, nojson::JsonParseError> {
+    use tuinix::TerminalColor;
 
-#[derive(Debug, Clone)]
-pub struct KeyState {
-    pub key: Key,
-    pub press: KeyPressState,
+    let s = value.to_unquoted_string_str()?;
+    let color = match s.as_ref() {
+        "black" => TerminalColor::BLACK,
+        "red" => TerminalColor::RED,
+        "green" => TerminalColor::GREEN,
+        "yellow" => TerminalColor::YELLOW,
+        "blue" => TerminalColor::BLUE,
+        "magenta" => TerminalColor::MAGENTA,
+        "cyan" => TerminalColor::CYAN,
+        "white" => TerminalColor::WHITE,
+        s => {
+            let rgb = s
+                .strip_prefix('#')
+                .filter(|hex| hex.len() == 6)
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| value.invalid("color must be a color name or \"#rrggbb\""))?;
+            TerminalColor::new((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+        }
+    };
+    Ok(color)
 }
 
-impl KeyState {
-    pub fn new(key: Key) -> Self {
-        Self {
-            key,
-            press: KeyPressState::Neutral,
-        }
-    }
+/// Rendering settings shared by all keys
+#[derive(Debug, Default, Clone)]
+pub struct RenderOptions {
+    pub border_style: BorderStyle,
+    pub label_overflow: LabelOverflow,
 
-    pub fn to_frame(&self, shift: bool) -> orfail::Result<tuinix::TerminalFrame> {
-        let mut frame: tuinix::TerminalFrame = tuinix::TerminalFrame::new(self.key.region.size);
+    /// Foreground color applied to every key (e.g. while the target pane is in copy mode)
+    pub tint: Option<tuinix::TerminalColor>,
 
-        let width = self.key.region.size.cols;
-        let height = self.key.region.size.rows;
+    /// Omit the borders between flush keys so that the grid looks like a single table
+    pub shared_borders: bool,
 
-        let style = tuinix::TerminalStyle::new();
-        let style = match self.press {
-            KeyPressState::Neutral => style,
-            KeyPressState::Pressed => style.bold(),
-            KeyPressState::Activated => style.italic().reverse(),
-            KeyPressState::OneshotActivated => style.italic(),
-        };
-        let reset_style = tuinix::TerminalStyle::RESET;
+    /// Draw keys that have a glyph (see [`KeyCode::glyph()`]) with it instead of their name
+    pub glyph_labels: bool,
+
+    /// Mark keys that have a `target` with their pane in the top border (e.g. `→2`)
+    pub target_markers: bool,
+}
+
+/// Which part of a label to cut off when it does not fit within its key
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LabelOverflow {
+    /// Keep the beginning of the label (e.g. `Cod…`)
+    #[default]
+    End,
+
+    /// Keep both ends of the label (e.g. `Co…e`)
+    Middle,
+
+    /// Keep the end of the label (e.g. `…ode`)
+    Start,
+}
 
-        // Top border
-        write!(frame, "{}", style).or_fail()?;
-        write!(frame, "┌").or_fail()?;
-        for _ in 1..width - 1 {
-            write!(frame, "─").or_fail()?;
+impl LabelOverflow {
+    /// Shortens `label` to at most `width` characters, marking the cut with `ellipsis`
+    fn truncate(self, label: &str, width: usize, ellipsis: char) -> String {
+        let len = label.chars().count();
+        if len <= width {
+            return label.to_owned();
+        }
+        if width == 0 {
+            return String::new();
         }
-        writeln!(frame, "┐").or_fail()?;
 
-        // Middle rows with left/right borders
-        for row in 1..height - 1 {
-            write!(frame, "│").or_fail()?;
-            if row == (height - 1) / 2 {
-                let label = if shift {
-                    self.key.shift_code.to_string()
-                } else {
-                    self.key.code.to_string()
-                };
-                let padding_left = (width - 2 - label.len()) / 2;
-                let padding_right = width - 2 - padding_left - label.len();
-                write!(
-                    frame,
-                    "{:padding_left$}{label}{:padding_right$}",
-                    "",
-                    "",
-                    padding_left = padding_left,
-                    padding_right = padding_right,
-                )
-                .or_fail()?;
-            } else {
-                write!(frame, "{:width$}", "", width = width - 2).or_fail()?;
+        let keep = width - 1;
+        let chars = label.chars();
+        match self {
+            Self::End => chars.take(keep).chain([ellipsis]).collect(),
+            Self::Middle => {
+                let head = keep.div_ceil(2);
+                let tail = keep - head;
+                chars
+                    .clone()
+                    .take(head)
+                    .chain([ellipsis])
+                    .chain(chars.skip(len - tail))
+                    .collect()
             }
-            writeln!(frame, "│").or_fail()?;
+            Self::Start => [ellipsis]
+                .into_iter()
+                .chain(chars.skip(len - keep))
+                .collect(),
         }
+    }
+}
 
-        // Bottom border
-        write!(frame, "└").or_fail()?;
-        for _ in 1..width - 1 {
-            write!(frame, "─").or_fail()?;
-        }
-        writeln!(frame, "┘").or_fail()?;
-        write!(frame, "{}", reset_style).or_fail()?;
+impl<'text, 'raw> TryFrom<nojson::RawJsonValue<'text, 'raw>> for LabelOverflow {
+    type Error = nojson::JsonParseError;
 
+    fn try_from(value: nojson::RawJsonValue<'text, 'raw>) -> Result<Self, Self::Error> {
+        match value.to_unquoted_string_str()?.as_ref() {
+            "end" => Ok(Self::End),
+            "middle" => Ok(Self::Middle),
+            "start" => Ok(Self::Start),
+            _ => Err(value.invalid("label overflow must be \"end\", \"middle\" or \"start\"")),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BorderStyle {
+    #[default]
+    Unicode,
+
+    /// Plain ASCII borders for terminals that cannot display box-drawing characters
+    Ascii,
+}
+
+impl BorderStyle {
+    /// Picks [`BorderStyle::Ascii`] if the locale environment variables indicate a non-UTF-8 locale
+    pub fn detect() -> Self {
+        let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+            .into_iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|value| !value.is_empty());
+        Self::from_locale(locale.as_deref())
+    }
+
+    /// Without any locale set, most terminals still handle UTF-8, so only an explicit locale opts out
+    fn from_locale(locale: Option<&str>) -> Self {
+        let Some(locale) = locale else {
+            return Self::Unicode;
+        };
+        let locale = locale.to_ascii_lowercase();
+        if locale.contains("utf-8") || locale.contains("utf8") {
+            Self::Unicode
+        } else {
+            Self::Ascii
+        }
+    }
+
+    fn glyphs(self) -> BorderGlyphs {
+        match self {
+            Self::Unicode => BorderGlyphs {
+                top_left: '┌',
+                top_right: '┐',
+                bottom_left: '└',
+                bottom_right: '┘',
+                horizontal: '─',
+                vertical: '│',
+                tee_down: '┬',
+                tee_up: '┴',
+                tee_right: '├',
+                tee_left: '┤',
+                cross: '┼',
+                ellipsis: '…',
+                oneshot: '¹',
+                target: '→',
+            },
+            Self::Ascii => BorderGlyphs {
+                top_left: '+',
+                top_right: '+',
+                bottom_left: '+',
+                bottom_right: '+',
+                horizontal: '-',
+                vertical: '|',
+                tee_down: '+',
+                tee_up: '+',
+                tee_right: '+',
+                tee_left: '+',
+                cross: '+',
+                ellipsis: '~',
+                oneshot: '1',
+                target: '>',
+            },
+        }
+    }
+}
+
+impl<'text, 'raw> TryFrom<nojson::RawJsonValue<'text, 'raw>> for BorderStyle {
+    type Error = nojson::JsonParseError;
+
+    fn try_from(value: nojson::RawJsonValue<'text, 'raw>) -> Result<Self, Self::Error> {
+        match value.to_unquoted_string_str()?.as_ref() {
+            "unicode" => Ok(Self::Unicode),
+            "ascii" => Ok(Self::Ascii),
+            _ => Err(value.invalid("border style must be \"unicode\" or \"ascii\"")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BorderGlyphs {
+    top_left: char,
+    top_right: char,
+    bottom_left: char,
+    bottom_right: char,
+    horizontal: char,
+    vertical: char,
+    tee_down: char,
+    tee_up: char,
+    tee_right: char,
+    tee_left: char,
+    cross: char,
+    ellipsis: char,
+
+    /// Drawn at the top right of one-shot modifiers, which clear after the next key
+    oneshot: char,
+
+    /// Put before the pane of keys with a `target`, when [`RenderOptions::target_markers`] is set
+    target: char,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyPressState {
+    Neutral,
+    Activated,
+    OneshotActivated,
+    Pressed,
+}
+
+/// Character drawn in a terminal cell, with its style
+pub type StyledCell = (char, tuinix::TerminalStyle);
+
+#[derive(Debug, Clone)]
+pub struct KeyState {
+    pub key: Key,
+    pub press: KeyPressState,
+
+    /// Current frame of the press animation, if running
+    pub animation_frame: Option<usize>,
+
+    /// Label shown instead of the key code (e.g. for keys whose label reflects the app state)
+    pub label: Option<String>,
+}
+
+impl KeyState {
+    pub fn new(key: Key) -> Self {
+        Self {
+            key,
+            press: KeyPressState::Neutral,
+            animation_frame: None,
+            label: None,
+        }
+    }
+
+    pub fn to_frame(
+        &self,
+        shift: bool,
+        focused: bool,
+        options: &RenderOptions,
+    ) -> orfail::Result<tuinix::TerminalFrame> {
+        self.key.check_size().or_fail()?;
+        let mut frame: tuinix::TerminalFrame = tuinix::TerminalFrame::new(self.key.region.size);
+        for row in self.to_cells(shift, focused, options) {
+            let mut current_style = None;
+            for (c, style) in row {
+                if current_style != Some(style) {
+                    write!(frame, "{style}").or_fail()?;
+                    current_style = Some(style);
+                }
+                write!(frame, "{c}").or_fail()?;
+            }
+            writeln!(frame).or_fail()?;
+        }
+        write!(frame, "{}", tuinix::TerminalStyle::RESET).or_fail()?;
         Ok(frame)
     }
+
+    /// Returns the key drawn as rows of styled cells (e.g. for compositing into another UI)
+    pub fn to_cells(
+        &self,
+        shift: bool,
+        focused: bool,
+        options: &RenderOptions,
+    ) -> Vec<Vec<StyledCell>> {
+        let style = tuinix::TerminalStyle::new();
+        let style = match self.press {
+            KeyPressState::Neutral => style,
+            KeyPressState::Pressed => style.bold(),
+            KeyPressState::Activated => style.italic().reverse(),
+            KeyPressState::OneshotActivated => style.italic(),
+        };
+        let style = if self.key.code.is_window_navigation() {
+            style.fg_color(tuinix::TerminalColor::CYAN)
+        } else {
+            style
+        };
+        let style = match options.tint {
+            Some(color) => style.fg_color(color),
+            None => style,
+        };
+        let style = if focused {
+            style.bold().underline()
+        } else {
+            style
+        };
+        let style = match self.animation_frame {
+            Some(0) => style.reverse(),
+            Some(_) => style.dim(),
+            None => style,
+        };
+        self.box_cells(shift, style, options)
+    }
+
+    /// Returns the key drawn as plain text lines, without any styling
+    pub fn to_text_lines(&self, shift: bool, options: &RenderOptions) -> Vec<String> {
+        self.box_cells(shift, tuinix::TerminalStyle::new(), options)
+            .into_iter()
+            .map(|row| row.into_iter().map(|(c, _)| c).collect())
+            .collect()
+    }
+
+    /// Lays out the borders and the label in `style`
+    ///
+    /// Keys smaller than [`MIN_KEY_SIZE`] do not panic, but their cells may not fit their region.
+    fn box_cells(
+        &self,
+        shift: bool,
+        style: tuinix::TerminalStyle,
+        options: &RenderOptions,
+    ) -> Vec<Vec<StyledCell>> {
+        let width = self.key.region.size.cols;
+        let height = self.key.region.size.rows;
+        let border = options.border_style.glyphs();
+
+        // With shared borders, the right and bottom edges are drawn by the neighboring keys
+        let neighbors = if options.shared_borders {
+            self.key.neighbors
+        } else {
+            Neighbors::default()
+        };
+        let inner_width = width.saturating_sub(if neighbors.right { 1 } else { 2 });
+        let inner_end = if neighbors.bottom {
+            height
+        } else {
+            height.saturating_sub(1)
+        };
+        let top_left = match (neighbors.left, neighbors.top) {
+            (true, true) => border.cross,
+            (true, false) => border.tee_down,
+            (false, true) => border.tee_right,
+            (false, false) => border.top_left,
+        };
+        let top_right = if neighbors.top {
+            border.tee_left
+        } else {
+            border.top_right
+        };
+        let bottom_left = if neighbors.left {
+            border.tee_up
+        } else {
+            border.bottom_left
+        };
+
+        let row = |left: char, inner: Vec<StyledCell>, right: char| {
+            let mut cells = vec![(left, style)];
+            cells.extend(inner);
+            if !neighbors.right {
+                cells.push((right, style));
+            }
+            cells
+        };
+        let centered = |text: &str, text_style: &dyn Fn(usize) -> tuinix::TerminalStyle| {
+            let text = options
+                .label_overflow
+                .truncate(text, inner_width, border.ellipsis);
+            let padding_left = (inner_width - text.chars().count()) / 2;
+            let mut cells = vec![(' ', style); padding_left];
+            cells.extend(text.chars().enumerate().map(|(i, c)| (c, text_style(i))));
+            cells.resize(inner_width, (' ', style));
+            cells
+        };
+
+        let mut rows = Vec::with_capacity(height);
+        let mut top = vec![(border.horizontal, style); inner_width];
+        if self.press == KeyPressState::OneshotActivated
+            && let Some(last) = top.last_mut()
+        {
+            *last = (border.oneshot, style);
+        }
+        if options.target_markers
+            && let Some(target) = self.key.target
+        {
+            let marker = match target {
+                PaneTarget::Index(i) => format!("{}{i}", border.target),
+                PaneTarget::Id(id) => format!("{}%{id}", border.target),
+            };
+
+            // Keep a border cell on each side, and the last one free for the one-shot marker
+            let len = marker.chars().count();
+            if len + 2 <= inner_width {
+                let start = inner_width - 1 - len;
+                for (cell, c) in top[start..].iter_mut().zip(marker.chars()) {
+                    *cell = (c, style.dim());
+                }
+            }
+        }
+        rows.push(row(top_left, top, top_right));
+        for i in 1..inner_end {
+            let inner = if i == height.saturating_sub(1) / 2 {
+                let label = if let Some(label) = self.label.as_ref().or(self.key.label.as_ref()) {
+                    label.clone()
+                } else {
+                    let code = if shift {
+                        &self.key.shift_code
+                    } else {
+                        &self.key.code
+                    };
+                    let name = match code.glyph() {
+                        Some(glyph) if options.glyph_labels => glyph.to_string(),
+                        _ => code.to_string(),
+                    };
+                    match self.key.repeat_count.get() {
+                        1 => name,
+                        n => format!("{name}×{n}"),
+                    }
+                };
+
+                // Underline the mnemonic character within the label
+                let truncated =
+                    options
+                        .label_overflow
+                        .truncate(&label, inner_width, border.ellipsis);
+                let mnemonic = self
+                    .key
+                    .mnemonic
+                    .and_then(|m| truncated.chars().position(|c| c == m));
+                centered(&label, &|i| {
+                    if Some(i) == mnemonic {
+                        style.underline()
+                    } else {
+                        style
+                    }
+                })
+            } else if i == inner_end - 1
+                && let Some(hint) = &self.key.hint
+            {
+                // Keys with a spare row below the label show the hint on the last inner row
+                centered(hint, &|_| style.dim())
+            } else {
+                vec![(' ', style); inner_width]
+            };
+            rows.push(row(border.vertical, inner, border.vertical));
+        }
+        if !neighbors.bottom {
+            rows.push(row(
+                bottom_left,
+                vec![(border.horizontal, style); inner_width],
+                border.bottom_right,
+            ));
+        }
+        rows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn default_layout_is_cached() -> orfail::Result<()> {
+        let layout = Layout::embedded()?;
+        assert!(!layout.keys.is_empty());
+        assert_eq!(layout, Layout::embedded()?);
+        Ok(())
+    }
+
+    #[test]
+    fn bundled_layouts_parse() -> orfail::Result<()> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("layouts");
+        let mut count = 0;
+        for entry in std::fs::read_dir(&dir).or_fail()? {
+            let path = entry.or_fail()?.path();
+            if path.extension().is_some_and(|e| e == "jsonc") {
+                Layouts::load_from_file(&path).or_fail()?;
+                count += 1;
+            }
+        }
+        assert!(count >= 2, "no bundled layouts in {}", dir.display());
+        Ok(())
+    }
+
+    #[test]
+    fn newline_with_column_staggers_rows() -> orfail::Result<()> {
+        let layout: Layout = crate::jsonc::load_str(
+            "stagger.jsonc",
+            r#"[
+              {"key": "q"}, {"key": "w"},
+              {"newline": {"col": 1}},
+              {"key": "a"}, {"key": "s"},
+              {"newline": 1},
+              {"key": "z"}
+            ]"#,
+        )
+        .or_fail()?;
+        let positions = layout
+            .keys
+            .iter()
+            .map(|k| (k.region.position.row, k.region.position.col))
+            .collect::<Vec<_>>();
+        assert_eq!(positions, [(0, 0), (0, 3), (3, 1), (3, 4), (6, 0)]);
+
+        let error =
+            crate::jsonc::load_str::<Layout>("big.jsonc", r#"[{"newline": {"col": 5000}}]"#)
+                .expect_err("too large column");
+        assert!(error.to_string().contains("col must be at most"));
+        Ok(())
+    }
+
+    #[test]
+    fn shared_borders_2x2_grid() -> orfail::Result<()> {
+        let layout: Layout = crate::jsonc::load_str(
+            "grid.jsonc",
+            r#"[
+              {"shared_borders": true},
+              {"default_size": {"width": 5, "height": 3}},
+              {"key": "a"}, {"key": "b"}, {"newline": 1},
+              {"key": "c"}, {"key": "d"}
+            ]"#,
+        )
+        .or_fail()?;
+        let options = RenderOptions {
+            shared_borders: layout.shared_borders,
+            ..RenderOptions::default()
+        };
+        assert_eq!(
+            layout.to_text(&options).or_fail()?,
+            concat!(
+                "┌────┬───┐\n",
+                "│ a  │ b │\n",
+                "│    │   │\n",
+                "├────┼───┤\n",
+                "│ c  │ d │\n",
+                "└────┴───┘\n",
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn ascii_borders() -> orfail::Result<()> {
+        let layout: Layout = crate::jsonc::load_str(
+            "ascii.jsonc",
+            r#"[
+              {"default_size": {"width": 5, "height": 3}},
+              {"key": "a"}, {"key": "b"}
+            ]"#,
+        )
+        .or_fail()?;
+        let options = RenderOptions {
+            border_style: BorderStyle::Ascii,
+            ..RenderOptions::default()
+        };
+        assert_eq!(
+            layout.to_text(&options).or_fail()?,
+            concat!("+---++---+\n", "| a || b |\n", "+---++---+\n",)
+        );
+
+        let options = RenderOptions {
+            shared_borders: true,
+            ..options
+        };
+        assert_eq!(
+            layout.to_text(&options).or_fail()?,
+            concat!("+----+---+\n", "| a  | b |\n", "+----+---+\n",)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn border_style_follows_locale() {
+        assert_eq!(BorderStyle::from_locale(None), BorderStyle::Unicode);
+        assert_eq!(
+            BorderStyle::from_locale(Some("en_US.UTF-8")),
+            BorderStyle::Unicode
+        );
+        assert_eq!(
+            BorderStyle::from_locale(Some("ja_JP.utf8")),
+            BorderStyle::Unicode
+        );
+        assert_eq!(BorderStyle::from_locale(Some("C")), BorderStyle::Ascii);
+        assert_eq!(
+            BorderStyle::from_locale(Some("en_US.ISO-8859-1")),
+            BorderStyle::Ascii
+        );
+    }
+
+    #[test]
+    fn quit_mnemonic_is_reserved() {
+        let error = crate::jsonc::load_str::<Layout>(
+            "mnemonic.jsonc",
+            r#"[{"key": "a", "mnemonic": "q"}]"#,
+        )
+        .expect_err("reserved");
+        assert!(error.to_string().contains("reserved for quitting"));
+    }
+
+    #[test]
+    fn cells_style_mnemonic_and_hint() -> orfail::Result<()> {
+        let layout: Layout = crate::jsonc::load_str(
+            "cells.jsonc",
+            r#"[{"key": "x", "mnemonic": "x", "hint": "h", "size": {"width": 3, "height": 4}}]"#,
+        )
+        .or_fail()?;
+        let mut key = KeyState::new(layout.keys[0].clone());
+        key.press = KeyPressState::Pressed;
+        let cells = key.to_cells(false, false, &RenderOptions::default());
+
+        let style = tuinix::TerminalStyle::new().bold();
+        assert_eq!(cells.len(), 4);
+        assert_eq!(cells[0], [('┌', style), ('─', style), ('┐', style)]);
+        assert_eq!(cells[1][1], ('x', style.underline()));
+        assert_eq!(cells[2][1], ('h', style.dim()));
+        assert_eq!(cells[3][0], ('└', style));
+        Ok(())
+    }
+
+    #[test]
+    fn tiny_keys_do_not_panic() -> orfail::Result<()> {
+        let mut options = RenderOptions {
+            target_markers: true,
+            ..RenderOptions::default()
+        };
+        for shared_borders in [false, true] {
+            options.shared_borders = shared_borders;
+            for neighbors in 0..16 {
+                for rows in 0..6 {
+                    for cols in 0..6 {
+                        let mut key = Key::new(
+                            KeyCode::Char('a'),
+                            tuinix::TerminalRegion {
+                                position: tuinix::TerminalPosition::default(),
+                                size: tuinix::TerminalSize::rows_cols(rows, cols),
+                            },
+                        );
+                        key.label = Some("Label".to_owned());
+                        key.hint = Some("hint".to_owned());
+                        key.target = Some(PaneTarget::Index(1));
+                        key.neighbors = Neighbors {
+                            left: neighbors & 1 != 0,
+                            top: neighbors & 2 != 0,
+                            right: neighbors & 4 != 0,
+                            bottom: neighbors & 8 != 0,
+                        };
+                        let mut state = KeyState::new(key);
+                        state.press = KeyPressState::OneshotActivated;
+
+                        let _ = state.to_text_lines(false, &options);
+                        let _ = state.to_cells(true, true, &options);
+                        let frame = state.to_frame(false, false, &options);
+                        assert_eq!(frame.is_ok(), rows >= 3 && cols >= 3, "{rows}x{cols}");
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn switch_layout_names_are_checked() -> orfail::Result<()> {
+        let single = r#"[{"key": "SwitchLayout", "layout": "numbers"}]"#;
+        let error = crate::jsonc::load_str::<Layouts>("single.jsonc", single).expect_err("unknown");
+        assert!(
+            error
+                .to_string()
+                .contains("unknown layout name: \"numbers\"")
+        );
+
+        let named = r#"{"letters": [{"key": "SwitchLayout", "layout": "numbers"}],
+                        "numbers": [{"key": "SwitchLayout", "layout": "letters"}]}"#;
+        crate::jsonc::load_str::<Layouts>("named.jsonc", named).or_fail()?;
+
+        // Merged fragments are checked as a whole
+        let dir = TempDir::new("switch")?;
+        let first = dir.join("first.jsonc");
+        let second = dir.join("second.jsonc");
+        std::fs::write(&first, r#"[{"key": "a"}]"#).or_fail()?;
+        std::fs::write(&second, single).or_fail()?;
+        let result = Layouts::load_from_files(&[&first, &second]);
+        let error = result.expect_err("unknown");
+        assert!(
+            error.message.contains("unknown layout name"),
+            "{}",
+            error.message
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn tray_directive() -> orfail::Result<()> {
+        let layout: Layout = crate::jsonc::load_str(
+            "tray.jsonc",
+            r#"[{"key": "Tray"}, {"key": "a"},
+                {"tray": [{"key": "Paste"}, {"newline": 1}, {"key": "DisplayPanes"}]}]"#,
+        )
+        .or_fail()?;
+        assert_eq!(layout.keys.len(), 2);
+        let regions = layout
+            .tray
+            .iter()
+            .map(|k| (k.code.clone(), k.region.position));
+        assert_eq!(
+            regions.collect::<Vec<_>>(),
+            [
+                (KeyCode::Paste, tuinix::TerminalPosition::row_col(0, 0)),
+                (
+                    KeyCode::DisplayPanes,
+                    tuinix::TerminalPosition::row_col(3, 0)
+                )
+            ]
+        );
+
+        for text in [
+            r#"[{"tray": []}]"#,
+            r#"[{"tray": [{"key": "Tray"}]}]"#,
+            r#"[{"tray": [{"key": "a"}, {"tray": [{"key": "b"}]}]}]"#,
+            r#"[{"tray": [{"key": "C-"}]}]"#,
+            r#"[{"tray": [{"key": "Dead", "accent": "acute"}]}]"#,
+        ] {
+            assert!(crate::jsonc::load_str::<Layout>("tray.jsonc", text).is_err());
+        }
+
+        let text = r#"[{"key": "Tray"}, {"tray": [{"key": "SwitchLayout", "layout": "numbers"}]}]"#;
+        assert!(crate::jsonc::load_str::<Layouts>("tray.jsonc", text).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn code_points() -> orfail::Result<()> {
+        let layout: Layout = crate::jsonc::load_str(
+            "code_points.jsonc",
+            r#"[{"key": "U+00E9"}, {"key": "\u00e9"}, {"key": "U+41"},
+                {"key": "a", "shift": "U+00C9"}]"#,
+        )
+        .or_fail()?;
+        assert_eq!(layout.keys[0].code, KeyCode::Char('é'));
+        assert_eq!(layout.keys[1].code, KeyCode::Char('é'));
+        assert_eq!(layout.keys[2].code, KeyCode::Char('A'));
+        assert_eq!(layout.keys[3].shift_code, KeyCode::Char('É'));
+
+        for code in [
+            "U+",
+            "u+00e9",
+            "U+D800",
+            "U+110000",
+            "U+0000041",
+            "U+-41",
+            "U+0007",
+            "U+xyz",
+        ] {
+            let text = format!(r#"[{{"key": "{code}"}}]"#);
+            assert!(
+                crate::jsonc::load_str::<Layout>("code_points.jsonc", &text).is_err(),
+                "{code}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn wide_space_bar_centers_its_label() -> orfail::Result<()> {
+        let layout: Layout = crate::jsonc::load_str(
+            "space.jsonc",
+            r#"[{"key": "Space", "label": "␣", "size": {"width": 15, "height": 3}},
+                {"key": " ", "size": {"width": 7, "height": 3}}]"#,
+        )
+        .or_fail()?;
+        assert_eq!(layout.keys[0].code, KeyCode::Char(' '));
+        assert_eq!(layout.keys[1].code, KeyCode::Char(' '));
+
+        let lines =
+            |key: &Key| KeyState::new(key.clone()).to_text_lines(false, &RenderOptions::default());
+        assert_eq!(
+            lines(&layout.keys[0]),
+            ["┌─────────────┐", "│      ␣      │", "└─────────────┘"]
+        );
+
+        // Without a label, the box is filled with blanks up to the right border
+        assert_eq!(lines(&layout.keys[1]), ["┌─────┐", "│     │", "└─────┘"]);
+        Ok(())
+    }
+
+    #[test]
+    fn key_ids_are_unique() -> orfail::Result<()> {
+        let layout: Layout = crate::jsonc::load_str(
+            "ids.jsonc",
+            r#"[{"key": "a", "id": "left"}, {"key": "b"}, {"key": "c", "id": "right"}]"#,
+        )
+        .or_fail()?;
+        assert_eq!(
+            layout.find_by_id("right").map(|k| &k.code),
+            Some(&KeyCode::Char('c'))
+        );
+        assert_eq!(layout.find_by_id("b"), None);
+        assert_eq!(layout.keys[1].id, None);
+
+        let error = crate::jsonc::load_str::<Layout>(
+            "ids.jsonc",
+            "[{\"key\": \"a\", \"id\": \"x\"},\n {\"key\": \"b\", \"id\": \"x\"}]",
+        )
+        .expect_err("duplicate id");
+        let message = error.to_string();
+        assert!(message.contains("duplicate key id \"x\""), "{message}");
+        assert!(message.contains("ids.jsonc:2:"), "{message}");
+
+        let error = crate::jsonc::load_str::<Layout>("ids.jsonc", r#"[{"key": "a", "id": ""}]"#)
+            .expect_err("empty id");
+        assert!(error.to_string().contains("id must not be empty"));
+        Ok(())
+    }
+
+    #[test]
+    fn repeat_count_sends_the_key_n_times() -> orfail::Result<()> {
+        let layout: Layout = crate::jsonc::load_str(
+            "repeat.jsonc",
+            r#"[{"key": "Down", "repeat_count": 5, "size": {"width": 10, "height": 3}},
+                {"key": "Up"}]"#,
+        )
+        .or_fail()?;
+        assert_eq!(layout.keys[0].repeat_args(), ["-N", "5"]);
+        assert!(layout.keys[1].repeat_args().is_empty());
+
+        let lines =
+            KeyState::new(layout.keys[0].clone()).to_text_lines(false, &RenderOptions::default());
+        assert_eq!(lines[1], "│ Down×5 │");
+
+        for (text, reason) in [
+            (
+                r#"[{"key": "a", "repeat_count": 0}]"#,
+                "repeat_count must be at least 1",
+            ),
+            (
+                r#"[{"key": "C-", "repeat_count": 2}]"#,
+                "repeat_count can only be set",
+            ),
+        ] {
+            let error = crate::jsonc::load_str::<Layout>("repeat.jsonc", text).expect_err(text);
+            assert!(error.to_string().contains(reason), "{error}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn merge_fragments() -> orfail::Result<()> {
+        let load =
+            |text: &str| crate::jsonc::load_str::<LayoutFragment>("fragment.jsonc", text).or_fail();
+        let numbers = load(r#"[{"key": "1"}, {"key": "2"}]"#)?;
+        let letters = load(r#"[{"key": "q", "id": "q"}]"#)?;
+        let side = load(r#"[{"base_position": {"row": 0, "column": 7}}, {"key": "x"}]"#)?;
+        assert!(!letters.positioned);
+        assert!(side.positioned);
+
+        let mut merged = numbers.layout;
+        let mut layout = letters.layout;
+        layout.move_down(merged.bottom());
+        merged.merge(layout.clone()).or_fail()?;
+        merged.merge(side.layout).or_fail()?;
+        let positions = merged
+            .keys
+            .iter()
+            .map(|k| (k.region.position.row, k.region.position.col))
+            .collect::<Vec<_>>();
+        assert_eq!(positions, [(0, 0), (0, 3), (3, 0), (0, 7)]);
+
+        // The same fragment again overlaps (and reuses the id)
+        let error = merged.merge(layout).expect_err("overlap");
+        assert_eq!(
+            error.message,
+            "key \"q\" at row 3, column 0 overlaps key \"q\""
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn hit_padding_extends_clickable_area() -> orfail::Result<()> {
+        let layout: Layout = crate::jsonc::load_str(
+            "padding.jsonc",
+            r#"[{"hit_padding": 2}, {"key": "a"}, {"blank": 4}, {"key": "b"}]"#,
+        )
+        .or_fail()?;
+        assert_eq!(layout.hit_padding, 2);
+        let regions = || layout.keys.iter().map(|k| k.region);
+        let at = |row, col| tuinix::TerminalPosition::row_col(row, col);
+
+        // Keys are at columns 0..3 and 7..10, leaving a four-column gap
+        assert_eq!(hit_test(regions(), at(1, 1), 0), Some(0));
+        assert_eq!(hit_test(regions(), at(1, 3), 0), None);
+
+        // Just outside the visual box
+        assert_eq!(hit_test(regions(), at(1, 3), 2), Some(0));
+        assert_eq!(hit_test(regions(), at(3, 1), 2), Some(0));
+        assert_eq!(hit_test(regions(), at(4, 8), 2), Some(1));
+        assert_eq!(hit_test(regions(), at(5, 8), 2), None);
+
+        // The gap is split between the neighbors
+        assert_eq!(hit_test(regions(), at(1, 4), 2), Some(0));
+        assert_eq!(hit_test(regions(), at(1, 5), 2), Some(1));
+        assert_eq!(hit_test(regions(), at(1, 6), 2), Some(1));
+
+        // Empty regions are never hit
+        let empty = tuinix::TerminalRegion::default();
+        assert_eq!(hit_test([empty], at(0, 0), 2), None);
+        Ok(())
+    }
+
+    #[test]
+    fn last_overlapping_key_is_hit() -> orfail::Result<()> {
+        let layout: Layout = crate::jsonc::load_str(
+            "overlap.jsonc",
+            r#"[{"key": "a"}, {"key": "b"}, {"base_position": {"row": 1, "column": 2}}, {"key": "c"}]"#,
+        )
+        .or_fail()?;
+        let regions = || layout.keys.iter().map(|k| k.region);
+        let at = |row, col| tuinix::TerminalPosition::row_col(row, col);
+
+        // c is drawn over the bottom right corner of a and the bottom left corner of b
+        assert_eq!(hit_test(regions(), at(0, 2), 0), Some(0));
+        assert_eq!(hit_test(regions(), at(1, 2), 0), Some(2));
+        assert_eq!(hit_test(regions(), at(2, 3), 0), Some(2));
+        assert_eq!(hit_test(regions(), at(1, 5), 0), Some(1));
+
+        // Also when the click is equally near several keys
+        assert_eq!(hit_test(regions(), at(3, 1), 1), Some(2));
+        let text = layout.to_text(&RenderOptions::default())?;
+        assert_eq!(text.lines().nth(1), Some("│a┌─┐│"));
+        Ok(())
+    }
+
+    #[test]
+    fn glyph_labels() -> orfail::Result<()> {
+        let glyphs = [
+            ("Enter", '⏎'),
+            ("BSpace", '⌫'),
+            ("Delete", '⌦'),
+            ("Tab", '⇥'),
+            ("BTab", '⇤'),
+            ("Escape", '⎋'),
+            ("Up", '↑'),
+            ("Down", '↓'),
+            ("Left", '←'),
+            ("Right", '→'),
+        ];
+        for (token, glyph) in glyphs {
+            let code = KeyCode::from_token(token).or_fail()?;
+            assert_eq!(code.glyph(), Some(glyph), "{token}");
+            // The glyph only changes the label, not the key sent to tmux
+            assert_eq!(code.to_string(), token);
+        }
+        assert_eq!(KeyCode::Char('a').glyph(), None);
+
+        let layout: Layout = crate::jsonc::load_str(
+            "glyphs.jsonc",
+            r#"[{"key": "Enter", "size": {"width": 7, "height": 3}}]"#,
+        )
+        .or_fail()?;
+        let key = KeyState::new(layout.keys[0].clone());
+        let options = RenderOptions {
+            glyph_labels: true,
+            ..RenderOptions::default()
+        };
+        assert_eq!(key.to_text_lines(false, &options)[1], "│  ⏎  │");
+        assert_eq!(
+            key.to_text_lines(false, &RenderOptions::default())[1],
+            "│Enter│"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn target_markers() -> orfail::Result<()> {
+        let layout: Layout = crate::jsonc::load_str(
+            "targets.jsonc",
+            r#"[{"key": "a", "target": 2, "size": {"width": 6, "height": 3}},
+                {"key": "b", "target": "%15", "size": {"width": 6, "height": 3}},
+                {"key": "c", "size": {"width": 6, "height": 3}}]"#,
+        )
+        .or_fail()?;
+        let options = RenderOptions {
+            target_markers: true,
+            ..RenderOptions::default()
+        };
+        let top = |i: usize, options: &RenderOptions| {
+            KeyState::new(layout.keys[i].clone()).to_text_lines(false, options)[0].clone()
+        };
+        assert_eq!(top(0, &options), "┌─→2─┐");
+        assert_eq!(top(0, &RenderOptions::default()), "┌────┐");
+        assert_eq!(top(2, &options), "┌────┐");
+
+        // Markers that do not fit are left out
+        assert_eq!(top(1, &options), "┌────┐");
+        Ok(())
+    }
+
+    #[test]
+    fn key_names_are_case_insensitive() -> orfail::Result<()> {
+        for (name, _) in KEY_NAMES {
+            let code = KeyCode::from_token(name).or_fail_with(|()| format!("{name:?}"))?;
+            assert_eq!(
+                KeyCode::from_token(&name.to_lowercase()),
+                Some(code.clone())
+            );
+            assert_eq!(KeyCode::from_token(&name.to_uppercase()), Some(code));
+        }
+        assert_eq!(KeyCode::from_token("enter"), Some(KeyCode::Enter));
+        assert_eq!(KeyCode::from_token("ENTER"), Some(KeyCode::Enter));
+        assert_eq!(KeyCode::from_token("bspace"), Some(KeyCode::Backspace));
+        assert_eq!(KeyCode::from_token("eNtEr"), Some(KeyCode::Enter));
+
+        // Characters keep their case
+        assert_eq!(KeyCode::from_token("a"), Some(KeyCode::Char('a')));
+        assert_eq!(KeyCode::from_token("A"), Some(KeyCode::Char('A')));
+        assert_eq!(KeyCode::from_token("Entr"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn mirror_flips_columns() -> orfail::Result<()> {
+        let mut layout: Layout = crate::jsonc::load_str(
+            "mirror.jsonc",
+            r#"[{"key": "a", "size": {"width": 5, "height": 3}},
+                {"key": "b", "size": {"width": 7, "height": 3}},
+                {"newline": 1}, {"key": "c", "size": {"width": 4, "height": 3}}]"#,
+        )
+        .or_fail()?;
+        layout.mirror();
+        let cols = layout
+            .keys
+            .iter()
+            .map(|k| k.region.position.col)
+            .collect::<Vec<_>>();
+        assert_eq!(cols, [7, 0, 8]);
+        assert!(layout.keys[1].neighbors.right);
+        assert!(!layout.keys[0].neighbors.right);
+        Ok(())
+    }
+
+    #[test]
+    fn label_overflow_strategies() {
+        let truncate = |overflow: LabelOverflow, width| overflow.truncate("Escape", width, '…');
+        assert_eq!(truncate(LabelOverflow::End, 4), "Esc…");
+        assert_eq!(truncate(LabelOverflow::Middle, 4), "Es…e");
+        assert_eq!(truncate(LabelOverflow::Start, 4), "…ape");
+
+        // Labels that fit are kept, and nothing fits in zero width
+        for overflow in [
+            LabelOverflow::End,
+            LabelOverflow::Middle,
+            LabelOverflow::Start,
+        ] {
+            assert_eq!(truncate(overflow, 6), "Escape");
+            assert_eq!(truncate(overflow, 1), "…");
+            assert_eq!(truncate(overflow, 0), "");
+        }
+        assert_eq!(LabelOverflow::Middle.truncate("Escape", 5, '~'), "Es~pe");
+    }
+
+    #[test]
+    fn min_key_size() -> orfail::Result<()> {
+        let load = |text: &str| crate::jsonc::load_str::<Layout>("min.jsonc", text);
+
+        let error =
+            load(r#"[{"key": "a", "size": {"width": 2, "height": 3}}]"#).expect_err("too narrow");
+        assert!(error.to_string().contains("width must be at least 3"));
+        let error =
+            load(r#"[{"key": "a", "size": {"width": 3, "height": 2}}]"#).expect_err("too low");
+        assert!(error.to_string().contains("height must be at least 3"));
+        let layout = load(r#"[{"key": "a", "size": {"width": 3, "height": 3}}]"#).or_fail()?;
+        assert_eq!(layout.keys[0].region.size, MIN_KEY_SIZE);
+
+        // A raised minimum applies to the following keys and to their default size
+        let raised = r#"[{"min_key_size": {"width": 5, "height": 4}}, {"key": "a"},
+                         {"key": "b", "size": {"width": 5, "height": 4}}]"#;
+        let layout = load(raised).or_fail()?;
+        assert_eq!(
+            layout.keys[0].region.size,
+            tuinix::TerminalSize::rows_cols(4, 5)
+        );
+        let error = load(
+            r#"[{"min_key_size": {"width": 5, "height": 4}},
+                {"key": "a", "size": {"width": 4, "height": 4}}]"#,
+        )
+        .expect_err("below the raised minimum");
+        assert!(error.to_string().contains("width must be at least 5"));
+
+        // The minimum cannot be lowered
+        assert!(load(r#"[{"min_key_size": {"width": 2, "height": 3}}]"#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn missing_members_are_explained() -> orfail::Result<()> {
+        let load = |text: &str| crate::jsonc::load_str::<Layout>("missing.jsonc", text);
+
+        // Keys without a size use the default one
+        let layout = load(r#"[{"key": "a"}]"#).or_fail()?;
+        assert_eq!(layout.keys[0].region.size, MIN_KEY_SIZE);
+
+        for size in [r#"{"width": 5}"#, r#"{"height": 5}"#, "{}"] {
+            let text = format!(r#"[{{"key": "a", "size": {size}}}]"#);
+            let error = load(&text).expect_err("incomplete size");
+            assert!(
+                error
+                    .to_string()
+                    .contains(r#"size must specify both "width" and "height""#),
+                "{size}"
+            );
+        }
+
+        let error = load(r#"[{"newlin": 1}]"#).expect_err("misspelled directive");
+        assert!(error.to_string().contains("layout element must be a key"));
+        Ok(())
+    }
+
+    #[test]
+    fn key_name_aliases() -> orfail::Result<()> {
+        for (alias, name) in [
+            ("Return", "Enter"),
+            ("Esc", "Escape"),
+            ("BackSpace", "BSpace"),
+            ("Del", "Delete"),
+        ] {
+            let code = KeyCode::from_token(alias).or_fail()?;
+            assert_eq!(code, KeyCode::from_token(name).or_fail()?, "{alias}");
+
+            // Sent to tmux under the canonical name
+            assert_eq!(code.to_string(), name);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn chords_directive() -> orfail::Result<()> {
+        let text = r#"[{"key": "j"}, {"key": "k"},
+                       {"chords": {"timeout_ms": 80,
+                                   "entries": [{"keys": ["j", "k"], "output": "Escape"}]}}]"#;
+        let layout: Layout = crate::jsonc::load_str("chords.jsonc", text).or_fail()?;
+        let chords = layout.chords.or_fail()?;
+        assert_eq!(chords.timeout, std::time::Duration::from_millis(80));
+        let (j, k) = (KeyCode::Char('j'), KeyCode::Char('k'));
+        assert_eq!(chords.find(&k, &j), Some(&KeyCode::Escape));
+
+        for chords in [
+            r#"{"timeout_ms": 0, "entries": []}"#,
+            r#"{"timeout_ms": 0.5, "entries": []}"#,
+            r#"{"entries": [{"keys": ["j"], "output": "Escape"}]}"#,
+            r#"{"entries": [{"keys": ["C-", "k"], "output": "Escape"}]}"#,
+        ] {
+            let text = format!(r#"[{{"chords": {chords}}}]"#);
+            assert!(
+                crate::jsonc::load_str::<Layout>("chords.jsonc", &text).is_err(),
+                "{chords}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn transpose_swaps_rows_and_columns() -> orfail::Result<()> {
+        let mut layout: Layout = crate::jsonc::load_str(
+            "wide.jsonc",
+            r#"[{"default_size": {"width": 7, "height": 3}}, {"key": "a"}, {"key": "b"}, {"newline": 1}, {"key": "c"}]"#,
+        )
+        .or_fail()?;
+        layout.transpose();
+        let region = |row, col, rows, cols| tuinix::TerminalRegion {
+            position: tuinix::TerminalPosition::row_col(row, col),
+            size: tuinix::TerminalSize::rows_cols(rows, cols),
+        };
+        assert_eq!(layout.keys[0].region, region(0, 0, 7, 3));
+        assert_eq!(layout.keys[1].region, region(7, 0, 7, 3));
+        assert_eq!(layout.keys[2].region, region(0, 3, 7, 3));
+
+        // Clicks map to the transposed regions
+        let regions = || layout.keys.iter().map(|k| k.region);
+        let at = |row, col| tuinix::TerminalPosition::row_col(row, col);
+        assert_eq!(hit_test(regions(), at(8, 1), 0), Some(1));
+        assert_eq!(hit_test(regions(), at(1, 4), 0), Some(2));
+        assert_eq!(hit_test(regions(), at(1, 8), 0), None);
+
+        // The neighbors follow the new arrangement
+        assert!(layout.keys[0].neighbors.bottom);
+        assert!(layout.keys[0].neighbors.right);
+        assert!(!layout.keys[1].neighbors.right);
+        Ok(())
+    }
+
+    #[test]
+    fn key_conditions() -> orfail::Result<()> {
+        let layout: Layout = crate::jsonc::load_str(
+            "when.jsonc",
+            r#"[{"key": "q", "when": "copy_mode"}, {"key": "y", "when": "has_selection"},
+                {"key": "b", "when": "pane:2"}, {"key": "a"}, {"key": "c", "when": "pane:0"}]"#,
+        )
+        .or_fail()?;
+        let enabled = |context: KeyContext| {
+            layout
+                .keys
+                .iter()
+                .map(|k| k.is_enabled(&context))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            enabled(KeyContext::default()),
+            [false, false, false, true, true]
+        );
+        let copy_mode = KeyContext {
+            copy_mode: true,
+            ..KeyContext::default()
+        };
+        assert_eq!(enabled(copy_mode), [true, false, false, true, true]);
+        let selecting = KeyContext {
+            has_selection: true,
+            ..copy_mode
+        };
+        assert_eq!(enabled(selecting), [true, true, false, true, true]);
+        let pane_2 = KeyContext {
+            pane_index: 2,
+            ..KeyContext::default()
+        };
+        assert_eq!(enabled(pane_2), [false, false, true, true, false]);
+
+        for when in [
+            r#""normal""#,
+            r#""pane:""#,
+            r#""pane:x""#,
+            r#""pane:-1""#,
+            r#""pane:+1""#,
+            r#""pane:é""#,
+            r#""pane:99999999999999999999""#,
+            "1",
+        ] {
+            let text = format!(r#"[{{"key": "a", "when": {when}}}]"#);
+            assert!(crate::jsonc::load_str::<Layout>("when.jsonc", &text).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn defaults_block_applies_to_following_keys() -> orfail::Result<()> {
+        let layout: Layout = crate::jsonc::load_str(
+            "defaults.jsonc",
+            r#"[{"defaults": {"size": {"width": 5, "height": 4}, "modifier_mode": "momentary"}},
+                {"key": "a"}, {"key": "b", "size": {"width": 9, "height": 3}}, {"key": "C-"}]"#,
+        )
+        .or_fail()?;
+        assert_eq!(
+            layout.keys[0].region.size,
+            tuinix::TerminalSize::rows_cols(4, 5)
+        );
+        assert_eq!(
+            layout.keys[1].region.size,
+            tuinix::TerminalSize::rows_cols(3, 9)
+        );
+        assert_eq!(
+            layout.keys[2].region.size,
+            tuinix::TerminalSize::rows_cols(4, 5)
+        );
+        assert_eq!(layout.keys[2].modifier_mode, ModifierMode::Momentary);
+
+        for defaults in [
+            r#"{"size": {"width": 5}}"#,
+            r#"{"size": {"width": 1, "height": 1}}"#,
+            r#"{"modifier_mode": "sticky"}"#,
+            r#"{"fg": "red"}"#,
+            r#"[]"#,
+        ] {
+            let text = format!(r#"[{{"defaults": {defaults}}}, {{"key": "a"}}]"#);
+            assert!(
+                crate::jsonc::load_str::<Layout>("defaults.jsonc", &text).is_err(),
+                "{defaults}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn oneshot_modifiers_are_marked() -> orfail::Result<()> {
+        let mut key = KeyState::new(Layout::embedded()?.keys[0].clone());
+        key.key.code = KeyCode::Shift;
+        let options = RenderOptions {
+            border_style: BorderStyle::Unicode,
+            label_overflow: LabelOverflow::default(),
+            tint: None,
+            shared_borders: false,
+            glyph_labels: false,
+            target_markers: false,
+        };
+        let top_right = |key: &KeyState| {
+            let cells = key.to_cells(false, false, &options);
+            let top = &cells[0];
+            top[top.len() - 2].0
+        };
+        assert_eq!(top_right(&key), '─');
+        key.press = KeyPressState::OneshotActivated;
+        assert_eq!(top_right(&key), '¹');
+        key.press = KeyPressState::Activated;
+        assert_eq!(top_right(&key), '─');
+        Ok(())
+    }
+
+    #[test]
+    fn backspace_and_delete_are_distinct() {
+        let backspace = KeyCode::from_token("BSpace").expect("BSpace");
+        let delete = KeyCode::from_token("Delete").expect("Delete");
+        assert_eq!(backspace.to_string(), "BSpace");
+        assert_eq!(delete.to_string(), "Delete");
+        assert_eq!(backspace.default_shift_code(), backspace);
+        assert_eq!(delete.default_shift_code(), delete);
+        assert_eq!(backspace.swap_delete(), delete);
+        assert_eq!(delete.swap_delete(), backspace);
+        assert_eq!(KeyCode::Tab.swap_delete(), KeyCode::Tab);
+    }
+
+    #[test]
+    fn picker_lists_layout_files() -> orfail::Result<()> {
+        let dir = TempDir::new("picker")?;
+        for name in ["vim.jsonc", "emacs.jsonc", "notes.txt"] {
+            std::fs::write(dir.join(name), "[]").or_fail()?;
+        }
+        let paths = Layouts::find_files(&dir)?;
+        assert_eq!(paths, [dir.join("emacs.jsonc"), dir.join("vim.jsonc")]);
+        assert!(Layouts::find_files(dir.join("missing"))?.is_empty());
+
+        let picker = Layouts::picker(&paths);
+        let layout = picker.get(PICKER_LAYOUT_NAME).or_fail()?;
+        assert_eq!(layout.keys.len(), 2);
+        assert_eq!(layout.keys[0].code.to_string(), "emacs");
+        assert_eq!(
+            layout.keys[1].region.position,
+            tuinix::TerminalPosition::row(3)
+        );
+        assert_eq!(layout.keys[1].region.size.cols, PICKER_MIN_WIDTH);
+        Ok(())
+    }
+
+    #[test]
+    fn revert_members() -> orfail::Result<()> {
+        let layouts: Layouts = crate::jsonc::load_str(
+            "revert.jsonc",
+            r#"[{"key": "ScrollUp", "revert": {"key": "q"}},
+                {"key": "ScrollDown", "revert": {"key": "Escape", "after_ms": 200}}]"#,
+        )
+        .or_fail()?;
+        let keys = &layouts.get(DEFAULT_LAYOUT_NAME).or_fail()?.keys;
+        let revert = |i: usize| keys[i].revert.clone();
+        assert_eq!(
+            revert(0),
+            Some(Revert {
+                code: KeyCode::Char('q'),
+                after: DEFAULT_REVERT_AFTER
+            })
+        );
+        assert_eq!(
+            revert(1),
+            Some(Revert {
+                code: KeyCode::Escape,
+                after: std::time::Duration::from_millis(200)
+            })
+        );
+
+        for text in [
+            r#"[{"key": "a", "revert": {"key": "q"}}]"#,
+            r#"[{"key": "ScrollUp", "revert": {"key": "Redraw"}}]"#,
+            r#"[{"key": "ScrollUp", "revert": {"key": "q", "after_ms": 0}}]"#,
+        ] {
+            assert!(crate::jsonc::load_str::<Layouts>("revert.jsonc", text).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn shift_is_only_allowed_on_keys_that_send_a_key() -> orfail::Result<()> {
+        let layouts: Layouts = crate::jsonc::load_str(
+            "shift.jsonc",
+            r#"[{"key": "1", "shift": "!"}, {"key": "Enter", "shift": "Tab"}]"#,
+        )
+        .or_fail()?;
+        let keys = &layouts.get(DEFAULT_LAYOUT_NAME).or_fail()?.keys;
+        assert_eq!(keys[0].shift_code, KeyCode::Char('!'));
+        assert_eq!(keys[1].shift_code, KeyCode::Tab);
+
+        for text in [
+            r#"[{"key": "Paste", "shift": "p"}]"#,
+            r#"[{"key": "C-", "shift": "a"}]"#,
+        ] {
+            let e = crate::jsonc::load_str::<Layouts>("shift.jsonc", text)
+                .err()
+                .or_fail()?;
+            assert!(e.to_string().contains("shift can only be set"), "{e}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn snippet_keys() -> orfail::Result<()> {
+        let layouts: Layouts = crate::jsonc::load_str(
+            "snippet.jsonc",
+            r#"[{"key": "Snippet", "path": "snippets/main.rs"}]"#,
+        )
+        .or_fail()?;
+        let key = &layouts.get(DEFAULT_LAYOUT_NAME).or_fail()?.keys[0];
+        assert_eq!(
+            key.code,
+            KeyCode::Snippet {
+                path: PathBuf::from("snippets/main.rs")
+            }
+        );
+        assert_eq!(key.code.to_string(), "main");
+
+        for text in [
+            r#"[{"key": "Snippet"}]"#,
+            r#"[{"key": "Snippet", "path": ""}]"#,
+        ] {
+            assert!(crate::jsonc::load_str::<Layouts>("snippet.jsonc", text).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn label_format_member() -> orfail::Result<()> {
+        let layouts: Layouts = crate::jsonc::load_str(
+            "label_format.jsonc",
+            r##"[{"key": "NextWindow", "label_format": "#{window_name}"}, {"key": "a"}]"##,
+        )
+        .or_fail()?;
+        let keys = &layouts.get(DEFAULT_LAYOUT_NAME).or_fail()?.keys;
+        assert_eq!(keys[0].label_format.as_deref(), Some("#{window_name}"));
+        assert_eq!(keys[1].label_format, None);
+
+        let text = r#"[{"key": "a", "label_format": ""}]"#;
+        assert!(crate::jsonc::load_str::<Layouts>("label_format.jsonc", text).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn builder_matches_parsed_layout() -> orfail::Result<()> {
+        let built = Layout::builder()
+            .key(KeyCode::Char('7'))
+            .key(KeyCode::Char('8'))
+            .key(KeyCode::Char('9'))
+            .key(KeyCode::Char('4'))
+            .at(3, 0)
+            .key(KeyCode::Char('5'))
+            .key(KeyCode::Char('6'))
+            .key(KeyCode::Char('0'))
+            .size(6, 3)
+            .at(6, 0)
+            .key(KeyCode::Enter)
+            .build()?;
+        let parsed: Layout = crate::jsonc::load_str(
+            "numpad.jsonc",
+            r#"[{"key": "7"}, {"key": "8"}, {"key": "9"}, {"newline": 1},
+                {"key": "4"}, {"key": "5"}, {"key": "6"}, {"newline": 1},
+                {"key": "0", "size": {"width": 6, "height": 3}}, {"key": "Enter"}]"#,
+        )
+        .or_fail()?;
+        assert_eq!(built, parsed);
+        assert!(built.keys[0].neighbors.right);
+        Ok(())
+    }
+
+    #[test]
+    fn builder_rejects_invalid_keys() {
+        let small = Layout::builder().key(KeyCode::Char('a')).size(2, 3).build();
+        assert!(small.is_err_and(|e| e.message.contains("smaller than 3x3")));
+
+        let overlapping = Layout::builder()
+            .key(KeyCode::Char('a'))
+            .key(KeyCode::Char('b'))
+            .at(1, 1)
+            .build();
+        assert!(overlapping.is_err_and(|e| e.message.contains("overlaps key \"a\"")));
+
+        let no_key = Layout::builder().size(3, 3).build();
+        assert!(no_key.is_err());
+    }
+
+    #[test]
+    fn capture_pane_keys() -> orfail::Result<()> {
+        let layouts: Layouts = crate::jsonc::load_str(
+            "capture.jsonc",
+            r#"[{"key": "CapturePane", "path": "out.txt"},
+                {"key": "CapturePane", "path": "all.txt", "start": "-", "end": -1}]"#,
+        )
+        .or_fail()?;
+        let keys = &layouts.get(DEFAULT_LAYOUT_NAME).or_fail()?.keys;
+        assert_eq!(
+            keys[0].code,
+            KeyCode::CapturePane {
+                path: PathBuf::from("out.txt"),
+                start: None,
+                end: None
+            }
+        );
+        assert_eq!(
+            keys[1].code,
+            KeyCode::CapturePane {
+                path: PathBuf::from("all.txt"),
+                start: Some("-".to_owned()),
+                end: Some("-1".to_owned())
+            }
+        );
+
+        for text in [
+            r#"[{"key": "CapturePane"}]"#,
+            r#"[{"key": "CapturePane", "path": "out.txt", "start": "top"}]"#,
+            r#"[{"key": "CapturePane", "path": "out.txt", "end": 1.5}]"#,
+        ] {
+            assert!(crate::jsonc::load_str::<Layouts>("capture.jsonc", text).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn binding_keys() -> orfail::Result<()> {
+        let layouts: Layouts = crate::jsonc::load_str(
+            "binding.jsonc",
+            r#"[{"key": "Binding", "binding": "c"},
+                {"key": "Binding", "binding": "C-Up", "table": "root"}]"#,
+        )
+        .or_fail()?;
+        let keys = &layouts.get(DEFAULT_LAYOUT_NAME).or_fail()?.keys;
+        assert_eq!(
+            keys[0].code,
+            KeyCode::Binding {
+                table: "prefix".to_owned(),
+                key: "c".to_owned()
+            }
+        );
+        assert_eq!(
+            keys[1].code,
+            KeyCode::Binding {
+                table: "root".to_owned(),
+                key: "C-Up".to_owned()
+            }
+        );
+        assert_eq!(keys[1].code.to_string(), "C-Up");
+
+        for text in [
+            r#"[{"key": "Binding"}]"#,
+            r#"[{"key": "Binding", "binding": ""}]"#,
+        ] {
+            assert!(crate::jsonc::load_str::<Layouts>("binding.jsonc", text).is_err());
+        }
+        Ok(())
+    }
 }
@@ -1,4 +1,10 @@
 pub mod app;
+pub mod compose;
+pub mod frame_limiter;
 pub mod jsonc;
 pub mod layout;
+pub mod pane_map;
+pub mod tap_hold;
+#[cfg(test)]
+mod test_util;
 pub mod tmux_client;
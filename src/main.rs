@@ -1,7 +1,57 @@
 use std::path::PathBuf;
+use std::process::ExitCode;
 use std::time::Duration;
 
-fn main() -> noargs::Result<()> {
+/// Exit code for errors that occur while tuke is running
+const EXIT_FAILURE: u8 = 1;
+
+/// Exit code for invalid command-line arguments
+const EXIT_USAGE: u8 = 2;
+
+/// Exit code for layout files that cannot be read or parsed
+const EXIT_LAYOUT: u8 = 3;
+
+/// Exit code for when the tmux control mode client cannot be started or does not respond
+const EXIT_TMUX: u8 = 4;
+
+/// Exit code for when the terminal cannot be set up
+const EXIT_TERMINAL: u8 = 5;
+
+/// Fatal error with the exit code for its category
+struct Fatal {
+    exit_code: u8,
+    error: noargs::Error,
+}
+
+impl Fatal {
+    fn with_code<E: Into<noargs::Error>>(exit_code: u8) -> impl FnOnce(E) -> Self {
+        move |error| Self {
+            exit_code,
+            error: error.into(),
+        }
+    }
+}
+
+impl From<noargs::Error> for Fatal {
+    fn from(error: noargs::Error) -> Self {
+        Self {
+            exit_code: EXIT_USAGE,
+            error,
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(fatal) => {
+            eprintln!("Error: {:?}", fatal.error);
+            ExitCode::from(fatal.exit_code)
+        }
+    }
+}
+
+fn run() -> Result<(), Fatal> {
     let mut args = noargs::raw_args();
 
     args.metadata_mut().app_name = env!("CARGO_PKG_NAME");
@@ -13,14 +63,45 @@ fn main() -> noargs::Result<()> {
     }
     noargs::HELP_FLAG.take_help(&mut args);
 
-    let layout_file_path: Option<PathBuf> = noargs::opt("layout-file")
-        .short('l')
-        .ty("PATH")
-        .env("TUKE_LAYOUT_FILE")
-        .doc("Path of layout JSONC file")
+    let mut layout_file_paths: Vec<PathBuf> = Vec::new();
+    loop {
+        let mut spec = noargs::opt("layout-file")
+            .short('l')
+            .ty("PATH")
+            .doc("Path of layout JSONC file (repeat to merge layout fragments in order)");
+        if layout_file_paths.is_empty() {
+            // Only the first occurrence falls back to the environment variable
+            spec = spec.env("TUKE_LAYOUT_FILE");
+        }
+        let Some(path) = spec
+            .take(&mut args)
+            .present_and_then(|a| a.value().parse())?
+        else {
+            break;
+        };
+        layout_file_paths.push(path);
+    }
+    let layout_dir: Option<PathBuf> = noargs::opt("layout-dir")
+        .ty("DIR")
+        .env("TUKE_LAYOUT_DIR")
+        .doc("Directory of layout files to choose from at startup when no --layout-file is given [default: ~/.config/tuke/layouts]")
         .take(&mut args)
         .present_and_then(|a| a.value().parse())?;
-    let options = tuke::app::AppOptions {
+    let print_regions: Option<RegionsFormat> = noargs::opt("print-regions")
+        .ty("text|json")
+        .doc("Print each key's computed region without launching the TUI")
+        .take(&mut args)
+        .present_and_then(|a| a.value().parse())?;
+    let print_layout = noargs::flag("print-layout")
+        .doc("Print the keyboard as plain text without launching the TUI")
+        .take(&mut args)
+        .is_present();
+    let no_self_check = noargs::flag("no-self-check")
+        .env("TUKE_NO_SELF_CHECK")
+        .doc("Skip checking that tmux responds to commands on startup")
+        .take(&mut args)
+        .is_present();
+    let mut options = tuke::app::AppOptions {
         cursor_refresh_interval: noargs::opt("cursor-refresh-interval")
             .ty("SECONDS")
             .env("TUKE_CURSOR_REFRESH_INTERVAL")
@@ -33,6 +114,181 @@ fn main() -> noargs::Result<()> {
             .doc("Automatically resize the tmux pane to fit the keyboard layout")
             .take(&mut args)
             .is_present(),
+        tmux_path: noargs::opt("tmux-path")
+            .ty("PATH")
+            .env("TUKE_TMUX")
+            .doc("Path or name of the tmux executable")
+            .default("tmux")
+            .take(&mut args)
+            .then(|a| a.value().parse())?,
+        tmux_socket: tmux_socket(&mut args)?,
+        tmux_session: noargs::opt("tmux-session")
+            .short('t')
+            .ty("NAME")
+            .env("TUKE_TMUX_SESSION")
+            .doc("tmux session containing the panes to send keys to")
+            .default("0")
+            .take(&mut args)
+            .then(|a| a.value().parse())?,
+        tmux_client: noargs::opt("tmux-client")
+            .short('c')
+            .ty("NAME")
+            .env("TUKE_TMUX_CLIENT")
+            .doc("tmux client (as in `tmux list-clients`) whose session receives the keys and which shows the pane numbers; overrides --tmux-session")
+            .take(&mut args)
+            .present_and_then(|a| a.value().parse())?,
+        tmux_timeout: noargs::opt("tmux-timeout")
+            .ty("SECONDS")
+            .env("TUKE_TMUX_TIMEOUT")
+            .doc("Maximum time to wait for a response to a tmux command")
+            .default("3")
+            .take(&mut args)
+            .then(|a| a.value().parse().map(Duration::from_secs_f64))?,
+        min_frame_interval: noargs::opt("min-frame-interval")
+            .ty("SECONDS")
+            .env("TUKE_MIN_FRAME_INTERVAL")
+            .doc("Minimum interval between redraws (updates arriving faster are merged)")
+            .default("0.016")
+            .take(&mut args)
+            .then(|a| a.value().parse().map(Duration::from_secs_f64))?,
+        timer_slack: noargs::opt("timer-slack")
+            .ty("SECONDS")
+            .env("TUKE_TIMER_SLACK")
+            .doc("How late timers (e.g. animations and cursor refresh) may fire so that close ones share a wakeup, saving battery")
+            .default("0")
+            .take(&mut args)
+            .then(|a| a.value().parse().map(Duration::from_secs_f64))?,
+        pane_badge: noargs::opt("pane-badge")
+            .ty("CORNER")
+            .env("TUKE_PANE_BADGE")
+            .doc("Corner to show the target pane badge in (top-left, top-right, bottom-left, bottom-right or none)")
+            .default("top-right")
+            .take(&mut args)
+            .then(|a| match a.value() {
+                "none" => Ok(None),
+                s => s.parse().map(Some),
+            })?,
+        pane_map: noargs::opt("pane-map")
+            .ty("CORNER")
+            .env("TUKE_PANE_MAP")
+            .doc("Corner to show a clickable map of the window's panes in, for choosing the target pane (top-left, top-right, bottom-left or bottom-right)")
+            .take(&mut args)
+            .present_and_then(|a| a.value().parse())?,
+        status_overlap: noargs::opt("status-overlap")
+            .ty("warn|avoid")
+            .env("TUKE_STATUS_OVERLAP")
+            .doc("What to do when keys are drawn in the top or bottom row, which the status line and badges share (warn, or avoid by moving the keyboard and reserving rows when auto-resizing)")
+            .default("warn")
+            .take(&mut args)
+            .then(|a| a.value().parse())?,
+        empty_click_action: noargs::opt("empty-click")
+            .ty("ACTION")
+            .env("TUKE_EMPTY_CLICK")
+            .doc("Action when clicking outside of any key (none, clear-modifiers, quit or send:KEY)")
+            .default("none")
+            .take(&mut args)
+            .then(|a| a.value().parse())?,
+        oneshot_timeout: noargs::opt("oneshot-timeout")
+            .ty("SECONDS")
+            .env("TUKE_ONESHOT_TIMEOUT")
+            .doc("Clear a modifier clicked once (marked with ¹) if no key follows within SECONDS")
+            .take(&mut args)
+            .present_and_then(|a| a.value().parse().map(Duration::from_secs_f64))?,
+        confirm_modified: noargs::opt("confirm-modified")
+            .ty("SECONDS")
+            .env("TUKE_CONFIRM_MODIFIED")
+            .doc("Send keys with Ctrl or Alt applied only when clicked a second time within SECONDS")
+            .take(&mut args)
+            .present_and_then(|a| a.value().parse().map(Duration::from_secs_f64))?,
+        press_animation: noargs::flag("press-animation")
+            .env("TUKE_PRESS_ANIMATION")
+            .doc("Briefly animate keys when they are pressed")
+            .take(&mut args)
+            .is_present(),
+        ascii_border: noargs::flag("ascii")
+            .env("TUKE_ASCII")
+            .doc("Draw key borders with ASCII characters instead of box-drawing characters")
+            .take(&mut args)
+            .is_present(),
+        normalize_nfc: noargs::flag("normalize-nfc")
+            .env("TUKE_NORMALIZE_NFC")
+            .doc("Normalize text typed by snippet and dynamic keys to NFC (e.g. `e` + U+0301 to `é`)")
+            .take(&mut args)
+            .is_present(),
+        queue_keys: noargs::flag("queue")
+            .env("TUKE_QUEUE")
+            .doc("Start in queue mode, where clicked keys are only sent by a `FlushQueue` key")
+            .take(&mut args)
+            .is_present(),
+        drag_to_cancel: noargs::flag("drag-to-cancel")
+            .env("TUKE_DRAG_TO_CANCEL")
+            .doc("Do not send a key if the mouse button is released outside of it (as on touch screens)")
+            .take(&mut args)
+            .is_present(),
+        no_mouse: noargs::flag("no-mouse")
+            .env("TUKE_NO_MOUSE")
+            .doc("Leave mouse events to tmux (e.g. for text selection) and use keyboard activation only")
+            .take(&mut args)
+            .is_present(),
+        mirror: noargs::flag("mirror")
+            .env("TUKE_MIRROR")
+            .doc("Flip the keyboard layout horizontally")
+            .take(&mut args)
+            .is_present(),
+        orientation: noargs::opt("orientation")
+            .ty("landscape|portrait")
+            .env("TUKE_ORIENTATION")
+            .doc("Arrangement of the keys; portrait swaps rows and columns so that a wide keyboard fits a tall screen")
+            .default("landscape")
+            .take(&mut args)
+            .then(|a| a.value().parse())?,
+        swap_delete: noargs::flag("swap-delete")
+            .env("TUKE_SWAP_DELETE")
+            .doc("Send Delete for BSpace keys and BSpace for Delete keys, for applications or terminals that map them the other way around")
+            .take(&mut args)
+            .is_present(),
+        glyph_labels: noargs::flag("glyph-labels")
+            .env("TUKE_GLYPH_LABELS")
+            .doc("Draw keys such as Enter, Tab and the arrows as symbols (e.g. ⏎) instead of their names")
+            .take(&mut args)
+            .is_present(),
+        show_targets: noargs::flag("show-targets")
+            .env("TUKE_SHOW_TARGETS")
+            .doc("Mark keys that are sent to a pane of their own with that pane (e.g. →2) in their top border")
+            .take(&mut args)
+            .is_present(),
+        stats_path: noargs::opt("stats")
+            .ty("PATH")
+            .env("TUKE_STATS")
+            .doc("Write the number of presses of each key to PATH on exit (`-` for stdout)")
+            .take(&mut args)
+            .present_and_then(|a| a.value().parse())?,
+        debug_grid: noargs::flag("debug-grid")
+            .doc("Show layout row/column numbers around the keyboard (for writing layouts)")
+            .take(&mut args)
+            .is_present(),
+        allow_broadcast: noargs::flag("allow-broadcast")
+            .env("TUKE_ALLOW_BROADCAST")
+            .doc("Enable `Broadcast` keys, which send keys to every other pane of the window")
+            .take(&mut args)
+            .is_present(),
+        on_press_command: noargs::opt("on-press")
+            .ty("COMMAND")
+            .env("TUKE_ON_PRESS")
+            .doc("Shell command run in the background on each key press, with $TUKE_KEY set to the key (e.g. `aplay -q click.wav`)")
+            .take(&mut args)
+            .present_and_then(|a| a.value().parse())?,
+        echo_target: noargs::opt("echo")
+            .ty("status|pane:N")
+            .env("TUKE_ECHO")
+            .doc("Show each sent key with a timestamp in the status line or type it into pane N (e.g. for screencasts)")
+            .take(&mut args)
+            .present_and_then(|a| a.value().parse())?,
+        title_shows_last_key: noargs::flag("title-shows-last-key")
+            .env("TUKE_TITLE_SHOWS_LAST_KEY")
+            .doc("Set the terminal title to each sent key (e.g. for screencasts)")
+            .take(&mut args)
+            .is_present(),
     };
 
     if let Some(help) = args.finish()? {
@@ -40,11 +296,195 @@ fn main() -> noargs::Result<()> {
         return Ok(());
     }
 
-    let layout = layout_file_path
-        .map(|path| tuke::layout::Layout::load_from_file(path))
-        .transpose()?
-        .unwrap_or_default();
-    let app = tuke::app::App::new(layout, options)?;
-    app.run()?;
+    let layouts = tuke::layout::Layouts::load_from_files(&layout_file_paths)
+        .map_err(Fatal::with_code(EXIT_LAYOUT))?;
+    if let Some(format) = print_regions {
+        print_key_regions(&layouts, format);
+        return Ok(());
+    }
+    if print_layout {
+        print_layouts(&layouts, &options).map_err(Fatal::with_code(EXIT_FAILURE))?;
+        return Ok(());
+    }
+
+    // Without explicit layout files, offer the ones in the layout directory
+    let mut layouts = layouts;
+    if layout_file_paths.is_empty()
+        && let Some(dir) = layout_dir.or_else(default_layout_dir)
+    {
+        let paths =
+            tuke::layout::Layouts::find_files(&dir).map_err(Fatal::with_code(EXIT_LAYOUT))?;
+        match &paths[..] {
+            [] => {}
+            [path] => {
+                layouts = tuke::layout::Layouts::load_from_file(path)
+                    .map_err(Fatal::with_code(EXIT_LAYOUT))?;
+            }
+            _ => layouts = tuke::layout::Layouts::picker(&paths),
+        }
+    }
+    if let Some(client) = &options.tmux_client {
+        options.tmux_session = tuke::tmux_client::find_client_session(
+            &options.tmux_path,
+            &options.tmux_socket,
+            client,
+        )
+        .map_err(Fatal::with_code(EXIT_TMUX))?;
+    }
+
+    // Start tmux before taking over the terminal so that a missing tmux is reported plainly
+    let mut tmux_client = tuke::tmux_client::TmuxClient::new(
+        &options.tmux_path,
+        &options.tmux_socket,
+        &options.tmux_session,
+        options.tmux_timeout,
+    )
+    .map_err(Fatal::with_code(EXIT_TMUX))?;
+    if !no_self_check {
+        tmux_client
+            .self_check()
+            .map_err(Fatal::with_code(EXIT_TMUX))?;
+    }
+    let app = tuke::app::App::new(layouts, tmux_client, options)
+        .map_err(Fatal::with_code(EXIT_TERMINAL))?;
+    app.run().map_err(Fatal::with_code(EXIT_FAILURE))?;
     Ok(())
 }
+
+fn tmux_socket(args: &mut noargs::RawArgs) -> noargs::Result<tuke::tmux_client::TmuxSocket> {
+    let name: Option<String> = noargs::opt("tmux-socket-name")
+        .short('L')
+        .ty("NAME")
+        .env("TUKE_TMUX_SOCKET_NAME")
+        .doc("Socket name of the tmux server (same as `tmux -L`)")
+        .take(args)
+        .present_and_then(|a| a.value().parse())?;
+    let path: Option<PathBuf> = noargs::opt("tmux-socket-path")
+        .short('S')
+        .ty("PATH")
+        .env("TUKE_TMUX_SOCKET_PATH")
+        .doc("Socket path of the tmux server (same as `tmux -S`)")
+        .take(args)
+        .present_and_then(|a| a.value().parse())?;
+    match (name, path) {
+        (None, None) => Ok(tuke::tmux_client::TmuxSocket::Default),
+        (Some(name), None) => Ok(tuke::tmux_client::TmuxSocket::Name(name)),
+        (None, Some(path)) => Ok(tuke::tmux_client::TmuxSocket::Path(path)),
+        (Some(_), Some(_)) => Err(noargs::Error::other(
+            args,
+            "--tmux-socket-name and --tmux-socket-path cannot be used together",
+        )),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum RegionsFormat {
+    Text,
+    Json,
+}
+
+impl std::str::FromStr for RegionsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "unknown format: {s:?} (expected \"text\" or \"json\")"
+            )),
+        }
+    }
+}
+
+fn print_layouts(
+    layouts: &tuke::layout::Layouts,
+    app_options: &tuke::app::AppOptions,
+) -> orfail::Result<()> {
+    for (i, layout) in layouts.entries.iter().enumerate() {
+        let mut layout = layout.clone();
+        if app_options.mirror {
+            layout.mirror();
+        }
+        if app_options.orientation == tuke::app::Orientation::Portrait {
+            layout.transpose();
+        }
+        let border_style = if app_options.ascii_border {
+            tuke::layout::BorderStyle::Ascii
+        } else {
+            layout.border_style.unwrap_or_default()
+        };
+        let options = tuke::layout::RenderOptions {
+            border_style,
+            label_overflow: layout.label_overflow.unwrap_or_default(),
+            tint: None,
+            shared_borders: layout.shared_borders,
+            glyph_labels: app_options.glyph_labels,
+            target_markers: app_options.show_targets,
+        };
+        if layouts.entries.len() > 1 {
+            if i > 0 {
+                println!();
+            }
+            println!("[{}]", layout.name);
+        }
+        print!("{}", layout.to_text(&options)?);
+    }
+    Ok(())
+}
+
+fn print_key_regions(layouts: &tuke::layout::Layouts, format: RegionsFormat) {
+    let entries = layouts
+        .entries
+        .iter()
+        .flat_map(|l| l.keys.iter().map(move |k| (&l.name, k)));
+    match format {
+        RegionsFormat::Text => {
+            println!(
+                "{:<12} {:<12} {:>5} {:>6} {:>5} {:>6}",
+                "LAYOUT", "KEY", "ROW", "COLUMN", "WIDTH", "HEIGHT"
+            );
+            for (name, key) in entries {
+                let region = key.region;
+                println!(
+                    "{:<12} {:<12} {:>5} {:>6} {:>5} {:>6}",
+                    name,
+                    format!("{:?}", key.code.to_string()),
+                    region.position.row,
+                    region.position.col,
+                    region.size.cols,
+                    region.size.rows
+                );
+            }
+        }
+        RegionsFormat::Json => {
+            let json = nojson::json(|f| {
+                f.set_indent_size(2);
+                f.set_spacing(true);
+                f.array(|f| {
+                    for (name, key) in entries.clone() {
+                        f.element(nojson::object(|f| {
+                            f.member("layout", name)?;
+                            f.member("key", key.code.to_string())?;
+                            f.member("row", key.region.position.row)?;
+                            f.member("column", key.region.position.col)?;
+                            f.member("width", key.region.size.cols)?;
+                            f.member("height", key.region.size.rows)
+                        }))?;
+                    }
+                    Ok(())
+                })
+            });
+            println!("{json}");
+        }
+    }
+}
+
+/// Returns `$XDG_CONFIG_HOME/tuke/layouts`, falling back to `~/.config/tuke/layouts`
+fn default_layout_dir() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("tuke").join("layouts"))
+}
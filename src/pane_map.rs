@@ -0,0 +1,183 @@
+//! Miniature map of the panes in the current window, drawn in a corner for selecting the target pane
+use std::fmt::Write;
+
+use orfail::OrFail;
+
+/// Size of the map on screen
+pub const PANE_MAP_SIZE: tuinix::TerminalSize = tuinix::TerminalSize { rows: 6, cols: 20 };
+
+/// `list-panes` format that produces the lines parsed by [`PaneMap::parse()`]
+pub const PANE_MAP_FORMAT: &str = "#{window_width} #{window_height} #{pane_index} #{pane_id} #{pane_left} #{pane_top} #{pane_width} #{pane_height}";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaneMap {
+    window: tuinix::TerminalSize,
+    panes: Vec<PaneGeometry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PaneGeometry {
+    index: usize,
+    id: String,
+    region: tuinix::TerminalRegion,
+}
+
+impl PaneMap {
+    /// Parses the output of `list-panes -F` with [`PANE_MAP_FORMAT`]
+    pub fn parse(lines: &[String]) -> orfail::Result<Self> {
+        let mut window = tuinix::TerminalSize::default();
+        let mut panes = Vec::new();
+        for line in lines {
+            let fields = line.split(' ').collect::<Vec<_>>();
+            let [width, height, index, id, left, top, cols, rows] = fields[..] else {
+                return Err(orfail::Failure::new(format!(
+                    "unexpected list-panes output: {line:?}"
+                )));
+            };
+            let number = |s: &str| s.parse::<usize>().or_fail();
+            window = tuinix::TerminalSize::rows_cols(number(height)?, number(width)?);
+            panes.push(PaneGeometry {
+                index: number(index)?,
+                id: id.to_owned(),
+                region: tuinix::TerminalRegion {
+                    position: tuinix::TerminalPosition::row_col(number(top)?, number(left)?),
+                    size: tuinix::TerminalSize::rows_cols(number(rows)?, number(cols)?),
+                },
+            });
+        }
+        Ok(Self { window, panes })
+    }
+
+    /// Returns the index of the pane shown at `position` within the map
+    ///
+    /// Cells over the borders between panes belong to no pane.
+    pub fn pane_at(&self, position: tuinix::TerminalPosition) -> Option<usize> {
+        self.pane_at_cell(position).map(|p| p.index)
+    }
+
+    fn pane_at_cell(&self, position: tuinix::TerminalPosition) -> Option<&PaneGeometry> {
+        if !PANE_MAP_SIZE.contains(position) {
+            return None;
+        }
+
+        // Sample the window at the center of the cell
+        let scale =
+            |i: usize, map_len: usize, window_len: usize| (2 * i + 1) * window_len / (2 * map_len);
+        let window_position = tuinix::TerminalPosition::row_col(
+            scale(position.row, PANE_MAP_SIZE.rows, self.window.rows),
+            scale(position.col, PANE_MAP_SIZE.cols, self.window.cols),
+        );
+        self.panes
+            .iter()
+            .find(|p| p.region.contains(window_position))
+    }
+
+    /// Draws the map, highlighting the target pane and dimming the pane with `own_pane_id`
+    pub fn to_frame(
+        &self,
+        target_index: usize,
+        own_pane_id: Option<&str>,
+    ) -> orfail::Result<tuinix::TerminalFrame> {
+        // The number of each pane goes to the cell nearest to its center
+        let labels = self
+            .panes
+            .iter()
+            .filter_map(|pane| {
+                let center = (0..PANE_MAP_SIZE.rows)
+                    .flat_map(|row| {
+                        (0..PANE_MAP_SIZE.cols)
+                            .map(move |col| tuinix::TerminalPosition::row_col(row, col))
+                    })
+                    .filter(|&p| self.pane_at_cell(p).is_some_and(|q| q.index == pane.index))
+                    .min_by_key(|p| {
+                        let row = (2 * p.row + 1) * self.window.rows / PANE_MAP_SIZE.rows;
+                        let col = (2 * p.col + 1) * self.window.cols / PANE_MAP_SIZE.cols;
+                        let center_row = 2 * pane.region.position.row + pane.region.size.rows;
+                        let center_col = 2 * pane.region.position.col + pane.region.size.cols;
+                        row.abs_diff(center_row) + col.abs_diff(center_col)
+                    })?;
+                Some((center, pane.index))
+            })
+            .collect::<Vec<_>>();
+
+        let mut frame: tuinix::TerminalFrame = tuinix::TerminalFrame::new(PANE_MAP_SIZE);
+        for row in 0..PANE_MAP_SIZE.rows {
+            for col in 0..PANE_MAP_SIZE.cols {
+                let position = tuinix::TerminalPosition::row_col(row, col);
+                let pane = self.pane_at_cell(position);
+                let style = match pane {
+                    None => tuinix::TerminalStyle::new(),
+                    Some(p) if own_pane_id == Some(p.id.as_str()) => {
+                        tuinix::TerminalStyle::new().dim()
+                    }
+                    Some(p) if p.index == target_index => {
+                        tuinix::TerminalStyle::new().bold().reverse()
+                    }
+                    Some(_) => tuinix::TerminalStyle::new().reverse(),
+                };
+                let c = labels
+                    .iter()
+                    .find(|(p, _)| *p == position)
+                    .and_then(|(_, index)| char::from_digit((*index % 10) as u32, 10))
+                    .unwrap_or(
+                        if pane.is_some_and(|p| own_pane_id == Some(p.id.as_str())) {
+                            '░'
+                        } else {
+                            ' '
+                        },
+                    );
+                write!(frame, "{style}{c}").or_fail()?;
+            }
+            writeln!(frame, "{}", tuinix::TerminalStyle::RESET).or_fail()?;
+        }
+        Ok(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 100x30 window split into a left pane and two stacked right panes
+    fn three_panes() -> orfail::Result<PaneMap> {
+        let lines = [
+            "100 30 0 %0 0 0 49 30",
+            "100 30 1 %1 50 0 50 14",
+            "100 30 2 %2 50 15 50 15",
+        ]
+        .map(|s| s.to_owned());
+        PaneMap::parse(&lines)
+    }
+
+    #[test]
+    fn parse_list_panes_output() -> orfail::Result<()> {
+        let map = three_panes()?;
+        assert_eq!(map.window, tuinix::TerminalSize::rows_cols(30, 100));
+        assert_eq!(map.panes.len(), 3);
+        assert_eq!(map.panes[2].id, "%2");
+        assert!(PaneMap::parse(&["100 30 0".to_owned()]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn cells_map_to_panes() -> orfail::Result<()> {
+        let map = three_panes()?;
+        let at = |row, col| map.pane_at(tuinix::TerminalPosition::row_col(row, col));
+        assert_eq!(at(0, 0), Some(0));
+        assert_eq!(at(5, 9), Some(0));
+        assert_eq!(at(0, 10), Some(1));
+        assert_eq!(at(2, 19), Some(1));
+        assert_eq!(at(3, 19), Some(2));
+        assert_eq!(at(5, 10), Some(2));
+        assert_eq!(at(6, 0), None);
+        assert_eq!(at(0, 20), None);
+        Ok(())
+    }
+
+    #[test]
+    fn frame_has_the_map_size() -> orfail::Result<()> {
+        let frame = three_panes()?.to_frame(1, Some("%2"))?;
+        assert_eq!(frame.size(), PANE_MAP_SIZE);
+        Ok(())
+    }
+}
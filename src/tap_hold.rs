@@ -0,0 +1,210 @@
+//! Distinguishing taps from holds on keys with a `"tap_hold"` member
+//!
+//! A tap (released before the hold threshold) sends the key's `code`.
+//! Holding past the threshold either repeats `code` (if `repeat_ms` is given)
+//! or sends `shift_code` on release.
+use std::time::{Duration, Instant};
+
+/// Hold threshold used when `hold_ms` is omitted
+pub const DEFAULT_HOLD: Duration = Duration::from_millis(300);
+
+/// Per-key tap/hold settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TapHoldConfig {
+    /// Minimum press duration to be treated as a hold
+    pub hold: Duration,
+
+    /// Interval between repeated sends while held, or `None` to send `shift_code` on release
+    pub repeat: Option<Duration>,
+}
+
+impl<'text, 'raw> TryFrom<nojson::RawJsonValue<'text, 'raw>> for TapHoldConfig {
+    type Error = nojson::JsonParseError;
+
+    fn try_from(value: nojson::RawJsonValue<'text, 'raw>) -> Result<Self, Self::Error> {
+        let hold = value
+            .to_member("hold_ms")?
+            .map(parse_millis)?
+            .unwrap_or(DEFAULT_HOLD);
+        let repeat = value.to_member("repeat_ms")?.map(parse_millis)?;
+        Ok(Self { hold, repeat })
+    }
+}
+
+/// Parses a positive number of milliseconds (e.g. `"hold_ms": 300`)
+pub(crate) fn parse_millis(
+    value: nojson::RawJsonValue<'_, '_>,
+) -> Result<Duration, nojson::JsonParseError> {
+    let ms: std::num::NonZeroU64 = value.try_into()?;
+    Ok(Duration::from_millis(ms.get()))
+}
+
+/// What to send in response to a press, a timer expiry or a release
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TapHoldAction {
+    /// Send `code` once (released before the hold threshold)
+    Tap,
+
+    /// Send `code` again (still held)
+    Repeat,
+
+    /// Send `shift_code` (released after the hold threshold without repeating)
+    HoldRelease,
+}
+
+/// Timing state of a single press of a tap/hold key
+#[derive(Debug, Clone)]
+pub struct TapHold {
+    config: TapHoldConfig,
+    pressed_at: Instant,
+    next_repeat: Option<Instant>,
+    repeated: bool,
+}
+
+impl TapHold {
+    pub fn new(config: TapHoldConfig, now: Instant) -> Self {
+        Self {
+            config,
+            pressed_at: now,
+            next_repeat: config.repeat.map(|_| now + config.hold),
+            repeated: false,
+        }
+    }
+
+    /// Returns when [`TapHold::poll()`] should be called next, if ever
+    pub fn deadline(&self) -> Option<Instant> {
+        self.next_repeat
+    }
+
+    /// Returns [`TapHoldAction::Repeat`] if the key has been held long enough for the next send
+    pub fn poll(&mut self, now: Instant) -> Option<TapHoldAction> {
+        let (next, interval) = self.next_repeat.zip(self.config.repeat)?;
+        if now < next {
+            return None;
+        }
+
+        // Schedule from now rather than from the missed deadline so that a stalled
+        // event loop does not produce a burst of repeats
+        self.next_repeat = Some(now + interval);
+        self.repeated = true;
+        Some(TapHoldAction::Repeat)
+    }
+
+    /// Finishes the press and returns what remains to be sent
+    pub fn release(self, now: Instant) -> Option<TapHoldAction> {
+        if self.repeated {
+            None
+        } else if now.saturating_duration_since(self.pressed_at) < self.config.hold {
+            Some(TapHoldAction::Tap)
+        } else if self.config.repeat.is_some() {
+            // Held past the threshold, but released before the first repeat was polled
+            Some(TapHoldAction::Tap)
+        } else {
+            Some(TapHoldAction::HoldRelease)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOLD: Duration = Duration::from_millis(300);
+    const REPEAT: Duration = Duration::from_millis(50);
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    fn shift_on_hold() -> TapHoldConfig {
+        TapHoldConfig {
+            hold: HOLD,
+            repeat: None,
+        }
+    }
+
+    fn repeat_on_hold() -> TapHoldConfig {
+        TapHoldConfig {
+            hold: HOLD,
+            repeat: Some(REPEAT),
+        }
+    }
+
+    #[test]
+    fn quick_tap_sends_code() {
+        let t0 = Instant::now();
+        for config in [shift_on_hold(), repeat_on_hold()] {
+            let mut state = TapHold::new(config, t0);
+            assert_eq!(state.poll(t0 + ms(100)), None);
+            assert_eq!(state.release(t0 + ms(100)), Some(TapHoldAction::Tap));
+        }
+    }
+
+    #[test]
+    fn release_just_before_threshold_is_a_tap() {
+        let t0 = Instant::now();
+        let state = TapHold::new(shift_on_hold(), t0);
+        assert_eq!(state.release(t0 + HOLD - ms(1)), Some(TapHoldAction::Tap));
+    }
+
+    #[test]
+    fn long_hold_without_repeat_sends_shift_code_on_release() {
+        let t0 = Instant::now();
+        let mut state = TapHold::new(shift_on_hold(), t0);
+        assert_eq!(state.deadline(), None);
+        assert_eq!(state.poll(t0 + ms(1000)), None);
+        assert_eq!(state.release(t0 + HOLD), Some(TapHoldAction::HoldRelease));
+    }
+
+    #[test]
+    fn hold_with_repeat_sends_code_repeatedly() {
+        let t0 = Instant::now();
+        let mut state = TapHold::new(repeat_on_hold(), t0);
+        assert_eq!(state.deadline(), Some(t0 + HOLD));
+        assert_eq!(state.poll(t0 + HOLD - ms(1)), None);
+
+        assert_eq!(state.poll(t0 + HOLD), Some(TapHoldAction::Repeat));
+        assert_eq!(state.deadline(), Some(t0 + HOLD + REPEAT));
+        assert_eq!(state.poll(t0 + HOLD + ms(10)), None);
+        assert_eq!(state.poll(t0 + HOLD + REPEAT), Some(TapHoldAction::Repeat));
+        assert_eq!(
+            state.poll(t0 + HOLD + REPEAT * 2),
+            Some(TapHoldAction::Repeat)
+        );
+
+        // Everything has already been sent
+        assert_eq!(state.release(t0 + HOLD + REPEAT * 2 + ms(10)), None);
+    }
+
+    #[test]
+    fn late_poll_does_not_burst() {
+        let t0 = Instant::now();
+        let mut state = TapHold::new(repeat_on_hold(), t0);
+        let late = t0 + HOLD + REPEAT * 10;
+        assert_eq!(state.poll(late), Some(TapHoldAction::Repeat));
+        assert_eq!(state.poll(late), None);
+        assert_eq!(state.deadline(), Some(late + REPEAT));
+    }
+
+    #[test]
+    fn release_after_threshold_before_first_repeat_is_a_tap() {
+        let t0 = Instant::now();
+        let state = TapHold::new(repeat_on_hold(), t0);
+        assert_eq!(state.release(t0 + HOLD + ms(1)), Some(TapHoldAction::Tap));
+    }
+
+    #[test]
+    fn parse_config() {
+        let parse = |text: &str| {
+            crate::jsonc::load_str::<TapHoldConfig>("tap_hold.jsonc", text)
+                .map_err(|e| e.to_string())
+        };
+        assert_eq!(parse("{}"), Ok(shift_on_hold()));
+        assert_eq!(
+            parse(r#"{"hold_ms": 300, "repeat_ms": 50}"#),
+            Ok(repeat_on_hold())
+        );
+        assert!(parse(r#"{"hold_ms": 0}"#).is_err());
+        assert!(parse(r#"{"repeat_ms": 0}"#).is_err());
+    }
+}
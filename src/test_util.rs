@@ -0,0 +1,42 @@
+//! Helpers shared by the unit tests of several modules
+use std::path::{Path, PathBuf};
+
+use orfail::OrFail;
+
+/// Directory under the system temporary directory that is removed when dropped
+///
+/// The directory is removed even if the test fails partway through.
+#[derive(Debug)]
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates an empty `tuke-{name}-{pid}` directory
+    ///
+    /// `name` must be unique among the tests, as they run in parallel.
+    pub fn new(name: &str) -> orfail::Result<Self> {
+        let path = std::env::temp_dir().join(format!("tuke-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).or_fail()?;
+        Ok(Self(path))
+    }
+}
+
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -1,65 +1,581 @@
-use std::io::{BufRead, BufReader, Write};
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
 
 use orfail::OrFail;
 
+const RESPONSE_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
 /// Control mode client for tmux communication
 ///
 /// Doc: <https://github.com/tmux/tmux/wiki/Control-Mode>
 #[derive(Debug)]
 pub struct TmuxClient {
     stdin: std::process::ChildStdin,
-    reader: BufReader<std::process::ChildStdout>,
+    output: ControlOutput<std::process::ChildStdout>,
+    timeout: Duration,
+}
+
+/// Parser of the control mode client output, split into command responses and notifications
+#[derive(Debug)]
+struct ControlOutput<R> {
+    reader: R,
+    read_buf: Vec<u8>,
+    in_block: bool,
+    lines: VecDeque<String>,
+    notifications: Vec<Notification>,
+    stale_responses: usize,
+}
+
+/// Asynchronous notification sent by tmux outside of command responses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Notification {
+    /// The active pane of a window has changed
+    WindowPaneChanged { pane_id: String },
+
+    /// The layout of a window has changed (e.g. a pane was split or closed)
+    LayoutChange,
+
+    /// A pane entered or left a mode such as copy mode
+    PaneModeChanged,
+}
+
+impl Notification {
+    fn parse(line: &str) -> Option<Self> {
+        let mut tokens = line.split(' ');
+        match tokens.next()? {
+            "%window-pane-changed" => {
+                let _window_id = tokens.next()?;
+                let pane_id = tokens.next()?.to_owned();
+                Some(Self::WindowPaneChanged { pane_id })
+            }
+            "%layout-change" => Some(Self::LayoutChange),
+            "%pane-mode-changed" => Some(Self::PaneModeChanged),
+            _ => None,
+        }
+    }
+}
+
+/// Connection to tmux through which the app runs commands and receives notifications
+///
+/// This is implemented by [`TmuxClient`], and by test doubles that record the commands instead.
+pub trait TmuxBackend: std::fmt::Debug {
+    /// Runs a command and returns its output lines
+    fn query(&mut self, command: &str, args: &[&str]) -> orfail::Result<Vec<String>>;
+
+    fn send_command(&mut self, command: &str, args: &[&str]) -> orfail::Result<()> {
+        self.query(command, args).or_fail()?;
+        Ok(())
+    }
+
+    /// Returns the file descriptor to wait on for notifications
+    fn fd(&self) -> RawFd;
+
+    /// Returns `true` if notifications were received while waiting for command responses
+    fn has_pending_notifications(&self) -> bool;
+
+    /// Takes the pending notifications
+    fn take_notifications(&mut self) -> orfail::Result<Vec<Notification>>;
+}
+
+/// Socket of the tmux server to connect to
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum TmuxSocket {
+    /// The server of the current tmux environment (or the default one)
+    #[default]
+    Default,
+
+    /// Socket name in the tmux socket directory (`tmux -L NAME`)
+    Name(String),
+
+    /// Full socket path (`tmux -S PATH`)
+    Path(PathBuf),
+}
+
+impl TmuxSocket {
+    /// Returns a command that runs tmux against this socket
+    pub fn command(&self, tmux_path: &Path) -> Command {
+        let mut command = Command::new(tmux_path);
+        match self {
+            Self::Default => {}
+            Self::Name(name) => {
+                command.arg("-L").arg(name);
+            }
+            Self::Path(path) => {
+                command.arg("-S").arg(path);
+            }
+        }
+        command
+    }
 }
 
 impl TmuxClient {
-    pub fn new() -> orfail::Result<Self> {
-        // Start tmux in control mode (-C) attached to the default session
-        let mut child = Command::new("tmux")
-            .arg("-C")
+    pub fn new(
+        tmux_path: &Path,
+        socket: &TmuxSocket,
+        session: &str,
+        timeout: Duration,
+    ) -> orfail::Result<Self> {
+        // Check the session first, as the control mode client would just exit without a reason
+        let output = socket
+            .command(tmux_path)
+            .args(["has-session", "-t", session])
+            .stdin(Stdio::null())
+            .output()
+            .or_fail_with(|e| format!("failed to run tmux ({}): {e}", tmux_path.display()))?;
+        if !output.status.success() {
+            let reason = String::from_utf8_lossy(&output.stderr);
+            return Err(orfail::Failure::new(format!(
+                "cannot attach to tmux session {session:?}: {}",
+                reason.trim()
+            )));
+        }
+
+        // Start tmux in control mode (-C) attached to the session that contains the target panes
+        let mut child = socket
+            .command(tmux_path)
+            .args(attach_args(session))
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::null())
             .spawn()
-            .or_fail()?;
+            .or_fail_with(|e| format!("failed to run tmux ({}): {e}", tmux_path.display()))?;
 
         let stdin = child.stdin.take().or_fail()?;
         let stdout = child.stdout.take().or_fail()?;
-        let reader = BufReader::new(stdout);
 
-        Ok(Self { stdin, reader })
+        // The output is read without blocking so that the app can wait for it
+        // together with terminal events and a stalled tmux cannot block the app
+        tuinix::set_nonblocking(stdout.as_raw_fd()).or_fail()?;
+
+        Ok(Self {
+            stdin,
+            output: ControlOutput::new(stdout),
+            timeout,
+        })
+    }
+
+    /// Runs a harmless command to verify that tmux responds
+    ///
+    /// Returns the ID of the active pane on success.
+    pub fn self_check(&mut self) -> orfail::Result<String> {
+        let lines = self
+            .query("display-message", &["-p", "#{pane_id}"])
+            .map_err(|mut e| {
+                e.message = format!("can't reach tmux: {}", e.message);
+                e
+            })?;
+        let pane_id = lines.into_iter().next().unwrap_or_default();
+        if !pane_id.starts_with('%') {
+            return Err(orfail::Failure::new(format!(
+                "can't reach tmux: unexpected response to display-message: {pane_id:?}"
+            )));
+        }
+        Ok(pane_id)
+    }
+}
+
+impl<R: Read> ControlOutput<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            read_buf: Vec::new(),
+            in_block: false,
+            lines: VecDeque::new(),
+            notifications: Vec::new(),
+            stale_responses: 0,
+        }
+    }
+
+    /// Reads the response to the last command sent, skipping those of earlier timed out commands
+    fn read_response(&mut self, command: &str, timeout: Duration) -> orfail::Result<Vec<String>> {
+        // Read response until %end or %error marker
+        let deadline = Instant::now() + timeout;
+        let mut output = Vec::new();
+        let mut in_block = false;
+        let mut from_client = true;
+        loop {
+            let Some(response) = self.lines.pop_front() else {
+                self.read_available().or_fail()?;
+                if !self.lines.is_empty() {
+                    continue;
+                }
+                if Instant::now() >= deadline {
+                    // The late response to this command must be skipped by the next command
+                    self.stale_responses += 1;
+                    return Err(orfail::Failure::new(format!(
+                        "tmux command timed out: {command}"
+                    )));
+                }
+                std::thread::sleep(RESPONSE_POLL_INTERVAL);
+                continue;
+            };
+
+            // Control mode responses are wrapped in %begin/%end or %begin/%error
+            if response.starts_with("%begin") {
+                output.clear();
+                in_block = true;
+
+                // The last field is zero for blocks not caused by a command sent by this
+                // client (e.g. the startup command, which may be answered after the first query)
+                from_client = !response.ends_with(" 0");
+                continue;
+            }
+            let is_end = response.starts_with("%end");
+            let is_error = response.starts_with("%error");
+            if !from_client && (is_end || is_error) {
+                output.clear();
+                in_block = false;
+                continue;
+            }
+            if self.stale_responses > 0 && (is_end || is_error) {
+                self.stale_responses -= 1;
+                output.clear();
+                in_block = false;
+                continue;
+            }
+            if is_end {
+                return Ok(output);
+            }
+            if is_error {
+                return Err(orfail::Failure::new(format!(
+                    "tmux command failed: {}",
+                    output.join(" ")
+                )));
+            }
+            if in_block {
+                output.push(response);
+            }
+        }
+    }
+
+    /// Drains the control mode client output without blocking
+    ///
+    /// Notifications are queued separately from the lines of command responses.
+    fn read_available(&mut self) -> orfail::Result<()> {
+        let mut buf = [0; 4096];
+        while let Some(n) = tuinix::try_nonblocking(self.reader.read(&mut buf)).or_fail()? {
+            if n == 0 {
+                return Err(orfail::Failure::new("tmux control mode client exited"));
+            }
+            self.read_buf.extend_from_slice(&buf[..n]);
+        }
+
+        while let Some(i) = self.read_buf.iter().position(|&b| b == b'\n') {
+            // Attached clients also receive pane output, which may not be valid UTF-8
+            let line = String::from_utf8_lossy(&self.read_buf[..i]).into_owned();
+            self.read_buf.drain(..=i);
+
+            if line.starts_with("%begin") {
+                self.in_block = true;
+            } else if line.starts_with("%end") || line.starts_with("%error") {
+                self.in_block = false;
+            } else if !self.in_block && line.starts_with('%') {
+                self.notifications.extend(Notification::parse(&line));
+                continue;
+            }
+            self.lines.push_back(line);
+        }
+        Ok(())
     }
+}
 
-    pub fn send_command(&mut self, command: &str, args: &[&str]) -> orfail::Result<()> {
-        let mut cmd_string = format!("{}", command);
+impl TmuxBackend for TmuxClient {
+    fn query(&mut self, command: &str, args: &[&str]) -> orfail::Result<Vec<String>> {
+        let mut cmd_string = command.to_owned();
         for arg in args {
             cmd_string.push(' ');
-            if *arg == "'" {
-                cmd_string.push_str("\"'\"");
-            } else {
-                cmd_string.push('\'');
-                cmd_string.push_str(arg);
-                cmd_string.push('\'');
-            }
+            push_quoted_arg(&mut cmd_string, arg);
         }
 
         // Send command to control mode client
         writeln!(self.stdin, "{}", cmd_string).or_fail()?;
         self.stdin.flush().or_fail()?;
 
-        // Read response until %end or %error marker
-        let mut response = String::new();
-        loop {
-            response.clear();
-            self.reader.read_line(&mut response).or_fail()?;
+        self.output.read_response(command, self.timeout)
+    }
 
-            // Control mode responses are wrapped in %begin/%end or %begin/%error
-            if response.starts_with("%end") {
-                return Ok(());
+    /// Returns the file descriptor of the control mode client output
+    ///
+    /// This can be passed to [`tuinix::Terminal::poll_event()`] to wait for notifications.
+    fn fd(&self) -> RawFd {
+        self.output.reader.as_raw_fd()
+    }
+
+    fn has_pending_notifications(&self) -> bool {
+        !self.output.notifications.is_empty()
+    }
+
+    /// Reads the available output and takes the pending notifications
+    fn take_notifications(&mut self) -> orfail::Result<Vec<Notification>> {
+        self.output.read_available().or_fail()?;
+        Ok(std::mem::take(&mut self.output.notifications))
+    }
+}
+
+/// `list-clients` format that produces the lines parsed by [`client_session()`]
+const CLIENT_LIST_FORMAT: &str = "#{client_control_mode} #{client_name} #{session_name}";
+
+/// Returns the session that the tmux client named `client` (e.g. `/dev/pts/3`) is attached to
+///
+/// Keys are sent to the panes of that session, so that they reach what the client shows
+/// when several clients are attached to different sessions.
+pub fn find_client_session(
+    tmux_path: &Path,
+    socket: &TmuxSocket,
+    client: &str,
+) -> orfail::Result<String> {
+    let output = socket
+        .command(tmux_path)
+        .args(["list-clients", "-F", CLIENT_LIST_FORMAT])
+        .stdin(Stdio::null())
+        .output()
+        .or_fail_with(|e| format!("failed to run tmux ({}): {e}", tmux_path.display()))?;
+    if !output.status.success() {
+        let reason = String::from_utf8_lossy(&output.stderr);
+        return Err(orfail::Failure::new(format!(
+            "cannot list tmux clients: {}",
+            reason.trim()
+        )));
+    }
+    client_session(&String::from_utf8_lossy(&output.stdout), client)
+}
+
+/// Finds the session of `client` in `list-clients` output with [`CLIENT_LIST_FORMAT`]
+///
+/// Control mode clients (e.g. other tuke instances) are not candidates.
+fn client_session(list: &str, client: &str) -> orfail::Result<String> {
+    let clients = list
+        .lines()
+        .filter_map(|line| line.strip_prefix("0 ")?.split_once(' '))
+        .collect::<Vec<_>>();
+    if let Some((_, session)) = clients.iter().find(|(name, _)| *name == client) {
+        return Ok((*session).to_owned());
+    }
+    let names = clients
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(", ");
+    Err(orfail::Failure::new(format!(
+        "no tmux client named {client:?} (attached clients: {})",
+        if names.is_empty() { "none" } else { &names }
+    )))
+}
+
+/// Returns the command of a key binding from a `list-keys -T TABLE KEY` output line
+///
+/// For example, `bind-key -r -T prefix Up select-pane -U` yields `select-pane -U`.
+pub fn binding_command(line: &str) -> Option<&str> {
+    let (word, mut rest) = split_word(line)?;
+    if word != "bind-key" {
+        return None;
+    }
+    loop {
+        let (word, after) = split_word(rest)?;
+        match word {
+            "-r" | "-n" => rest = after,
+            "-T" => rest = split_word(after)?.1,
+            _ => {
+                // `word` is the key itself
+                let command = after.trim();
+                return (!command.is_empty()).then_some(command);
             }
-            if response.starts_with("%error") {
-                return Err(orfail::Failure::new("tmux command failed"));
+        }
+    }
+}
+
+/// Splits off the first whitespace-separated word of `s`
+fn split_word(s: &str) -> Option<(&str, &str)> {
+    let s = s.trim_start();
+    (!s.is_empty()).then(|| s.split_once(char::is_whitespace).unwrap_or((s, "")))
+}
+
+/// Returns the arguments that start a control mode client attached to `session`
+///
+/// Commands sent through the client without a session in their target (e.g. `:.1`)
+/// then refer to this session.
+fn attach_args(session: &str) -> Vec<&str> {
+    vec!["-C", "attach-session", "-t", session]
+}
+
+fn push_quoted_arg(buf: &mut String, arg: &str) {
+    if !arg.contains(['\'', '\n']) {
+        buf.push('\'');
+        buf.push_str(arg);
+        buf.push('\'');
+        return;
+    }
+
+    // Single quotes cannot contain escapes, so fall back to double quotes
+    buf.push('"');
+    for c in arg.chars() {
+        match c {
+            '\\' | '"' | '$' => {
+                buf.push('\\');
+                buf.push(c);
+            }
+            '\n' => buf.push_str("\\n"),
+            _ => buf.push(c),
+        }
+    }
+    buf.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Output that is never closed, like a stalled tmux
+    #[derive(Debug, Default)]
+    struct StalledOutput(VecDeque<u8>);
+
+    impl Read for StalledOutput {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(std::io::ErrorKind::WouldBlock.into());
             }
+            self.0.read(buf)
         }
     }
+
+    fn feed(output: &mut ControlOutput<StalledOutput>, text: &str) {
+        output.reader.0.extend(text.as_bytes());
+    }
+
+    #[test]
+    fn late_responses_are_skipped() -> orfail::Result<()> {
+        let timeout = Duration::from_millis(10);
+        let mut output = ControlOutput::new(StalledOutput::default());
+
+        // A response that never ends times out
+        feed(&mut output, "%begin 1 1 1\nfoo\n");
+        let e = output.read_response("foo", timeout).expect_err("no %end");
+        assert_eq!(e.message, "tmux command timed out: foo");
+        assert_eq!(output.stale_responses, 1);
+
+        // Its late end is skipped, as are blocks not caused by this client
+        feed(
+            &mut output,
+            "%end 1 1 1\n%begin 2 2 0\nstartup\n%end 2 2 0\n",
+        );
+        feed(&mut output, "%window-pane-changed @1 %2\n");
+        feed(&mut output, "%begin 3 3 1\nbar\n%end 3 3 1\n");
+        assert_eq!(output.read_response("bar", timeout)?, ["bar"]);
+        assert_eq!(output.stale_responses, 0);
+        assert_eq!(
+            output.notifications,
+            [Notification::WindowPaneChanged {
+                pane_id: "%2".to_owned()
+            }]
+        );
+
+        feed(&mut output, "%begin 4 4 1\nunknown command\n%error 4 4 1\n");
+        let e = output.read_response("baz", timeout).expect_err("%error");
+        assert_eq!(e.message, "tmux command failed: unknown command");
+        Ok(())
+    }
+
+    #[test]
+    fn args_are_quoted_for_the_command_parser() {
+        let quoted = |arg: &str| {
+            let mut buf = String::new();
+            push_quoted_arg(&mut buf, arg);
+            buf
+        };
+        assert_eq!(quoted("a b;c"), "'a b;c'");
+        assert_eq!(quoted(r#"say "$HOME\""#), r#"'say "$HOME\"'"#);
+
+        // Single quotes and newlines need double quotes, where escapes work
+        assert_eq!(quoted("it's"), r#""it's""#);
+        assert_eq!(quoted("a\nb"), r#""a\nb""#);
+        assert_eq!(quoted("'$x\\\"'"), r#""'\$x\\\"'""#);
+    }
+
+    #[test]
+    fn startup_response_is_skipped() -> orfail::Result<()> {
+        // The block of the command that started the client may arrive after the first query
+        let mut output = ControlOutput::new(StalledOutput::default());
+        feed(&mut output, "%begin 1 1 0\n%end 1 1 0\n");
+        feed(&mut output, "%begin 2 2 1\n%42\n%end 2 2 1\n");
+        assert_eq!(
+            output.read_response("display-message", Duration::from_millis(10))?,
+            ["%42"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn attach_args_target_the_session() {
+        assert_eq!(attach_args("0"), ["-C", "attach-session", "-t", "0"]);
+        assert_eq!(attach_args("work"), ["-C", "attach-session", "-t", "work"]);
+    }
+
+    #[test]
+    fn client_session_is_looked_up_by_name() -> orfail::Result<()> {
+        let list = "0 /dev/pts/1 work\n1 client-42 work\n0 /dev/pts/3 my notes\n";
+        assert_eq!(client_session(list, "/dev/pts/1")?, "work");
+        assert_eq!(client_session(list, "/dev/pts/3")?, "my notes");
+
+        let e = client_session(list, "/dev/pts/9").expect_err("unknown client");
+        assert_eq!(
+            e.message,
+            "no tmux client named \"/dev/pts/9\" (attached clients: /dev/pts/1, /dev/pts/3)"
+        );
+
+        // Control mode clients cannot be chosen
+        assert!(client_session(list, "client-42").is_err());
+        let e = client_session("", "/dev/pts/1").expect_err("no clients");
+        assert!(e.message.ends_with("(attached clients: none)"));
+        Ok(())
+    }
+
+    #[test]
+    fn socket_args_precede_the_command() {
+        let args = |socket: TmuxSocket| {
+            let mut command = socket.command(Path::new("tmux"));
+            command.args(attach_args("0"));
+            command
+                .get_args()
+                .map(|a| a.to_str().expect("bug").to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            args(TmuxSocket::Default),
+            ["-C", "attach-session", "-t", "0"]
+        );
+        assert_eq!(
+            args(TmuxSocket::Name("work".to_owned())),
+            ["-L", "work", "-C", "attach-session", "-t", "0"]
+        );
+        assert_eq!(
+            args(TmuxSocket::Path(PathBuf::from("/tmp/tmux.sock"))),
+            ["-S", "/tmp/tmux.sock", "-C", "attach-session", "-t", "0"]
+        );
+    }
+
+    #[test]
+    fn binding_commands_are_extracted() {
+        assert_eq!(
+            binding_command("bind-key -T prefix c new-window"),
+            Some("new-window")
+        );
+        assert_eq!(
+            binding_command("bind-key -r -T prefix Up select-pane -U"),
+            Some("select-pane -U")
+        );
+        assert_eq!(
+            binding_command(r#"bind-key -T prefix X display-message "a  b;c""#),
+            Some(r#"display-message "a  b;c""#)
+        );
+        assert_eq!(
+            binding_command(r#"bind-key -T prefix - split-window"#),
+            Some("split-window")
+        );
+        assert_eq!(binding_command("invalid key: zz"), None);
+        assert_eq!(binding_command("bind-key -T prefix c"), None);
+    }
 }